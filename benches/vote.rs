@@ -0,0 +1,101 @@
+#![feature(test)]
+
+extern crate test;
+
+use solana::bank::Bank;
+use solana::compute_leader_finality_service::{ComputeLeaderFinalityService, FinalityMode};
+use solana::create_vote_account::create_vote_account;
+use solana::mint::Mint;
+use solana_sdk::hash::hash;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, KeypairUtil};
+use solana_sdk::timing;
+use solana_sdk::transaction::Transaction;
+use solana_sdk::vote_program::{Vote, VoteProgram, MAX_VOTE_HISTORY};
+use solana_sdk::vote_transaction::VoteTransaction;
+use std::sync::Arc;
+use test::Bencher;
+
+const NUM_VOTE_ACCOUNTS: usize = 1000;
+
+// Validators lag the tip by up to this many ticks, as on a live cluster
+const MAX_VOTE_LAG: usize = 8;
+
+/// Builds a bank with NUM_VOTE_ACCOUNTS vote accounts, each with a full vote history
+fn setup_bank() -> Arc<Bank> {
+    let mint = Mint::new(1_000_000_000);
+    let bank = Bank::new(&mint);
+
+    let mut last_id = bank.last_id();
+    let mut ids = vec![];
+    for _ in 0..MAX_VOTE_HISTORY + MAX_VOTE_LAG {
+        last_id = hash(last_id.as_ref());
+        bank.register_tick(&last_id);
+        ids.push(last_id);
+    }
+
+    for i in 0..NUM_VOTE_ACCOUNTS {
+        let validator = Keypair::new();
+        bank.transfer(100 + i as u64, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+
+        // Vote for every tick of a window ending up to MAX_VOTE_LAG ticks behind the tip
+        let first_tick = i % MAX_VOTE_LAG;
+        for (tick_height, bank_hash) in ids
+            .iter()
+            .enumerate()
+            .skip(first_tick)
+            .take(MAX_VOTE_HISTORY)
+        {
+            let vote = Vote {
+                tick_height: tick_height as u64 + 2,
+                bank_hash: *bank_hash,
+            };
+            let tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+            bank.process_transaction(&tx).unwrap();
+        }
+    }
+
+    Arc::new(bank)
+}
+
+#[bench]
+fn bench_vote_deserialize(bencher: &mut Bencher) {
+    let bank = setup_bank();
+    let vote_accounts = bank.vote_accounts();
+    assert_eq!(vote_accounts.len(), NUM_VOTE_ACCOUNTS);
+    assert!(vote_accounts.iter().all(|(_, account)| {
+        VoteProgram::deserialize(&account.userdata)
+            .unwrap()
+            .votes
+            .len()
+            == MAX_VOTE_HISTORY
+    }));
+
+    bencher.iter(|| {
+        for (_, account) in &vote_accounts {
+            test::black_box(VoteProgram::deserialize(&account.userdata).unwrap());
+        }
+    })
+}
+
+#[bench]
+fn bench_vote_get_last_supermajority_timestamp(bencher: &mut Bencher) {
+    let bank = setup_bank();
+    let leader_id = Pubkey::default();
+    assert_eq!(bank.vote_accounts().len(), NUM_VOTE_ACCOUNTS);
+
+    bencher.iter(|| {
+        let outcome = ComputeLeaderFinalityService::get_last_supermajority_timestamp(
+            &bank,
+            &*bank,
+            leader_id,
+            false,
+            FinalityMode::Optimistic,
+            timing::timestamp(),
+            0,
+        );
+        assert!(outcome.confirmed);
+    })
+}
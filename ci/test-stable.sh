@@ -27,6 +27,13 @@ for program in programs/native/*; do
   )
 done
 
+echo --- programs/native/vote test without metrics
+(
+  set -x
+  cd programs/native/vote
+  cargo test --verbose --no-default-features -- --nocapture
+)
+
 # Run integration tests serially
 for test in tests/*.rs; do
   test=${test##*/} # basename x
@@ -2,16 +2,476 @@
 //! Receive and processes votes from validators
 
 use log::*;
-use solana_sdk::account::KeyedAccount;
+use solana_sdk::account::{Account, KeyedAccount};
+use solana_sdk::hash::Hash;
 use solana_sdk::native_program::ProgramError;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::recent_hashes::{self, RecentHashes};
+use solana_sdk::rent;
 use solana_sdk::solana_entrypoint;
+use solana_sdk::timing;
 use solana_sdk::vote_program::*;
-use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Vote program metrics, compiled out without the default `metrics` feature
+#[cfg(feature = "metrics")]
+mod metrics {
+    use lazy_static::lazy_static;
+    use std::env;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const DEFAULT_METRICS_SAMPLE_RATE: usize = 1;
+
+    // Number of accepted votes not yet reported in a `<prefix>vote-native` metrics point
+    static UNREPORTED_VOTES: AtomicUsize = AtomicUsize::new(0);
+
+    // Cached value of SOLANA_VOTE_METRICS_SAMPLE_RATE, 0 until first read
+    static METRICS_SAMPLE_RATE: AtomicUsize = AtomicUsize::new(0);
+
+    fn metrics_sample_rate() -> usize {
+        let mut sample_rate = METRICS_SAMPLE_RATE.load(Ordering::Relaxed);
+        if sample_rate == 0 {
+            sample_rate = env::var("SOLANA_VOTE_METRICS_SAMPLE_RATE")
+                .map(|x| x.parse().unwrap_or(DEFAULT_METRICS_SAMPLE_RATE))
+                .unwrap_or(DEFAULT_METRICS_SAMPLE_RATE);
+            if sample_rate == 0 {
+                sample_rate = DEFAULT_METRICS_SAMPLE_RATE;
+            }
+            METRICS_SAMPLE_RATE.store(sample_rate, Ordering::Relaxed);
+        }
+        sample_rate
+    }
+
+    lazy_static! {
+        // Cached value of SOLANA_VOTE_METRICS_PREFIX, read by the first `metrics_prefix` call
+        static ref METRICS_PREFIX: String =
+            env::var("SOLANA_VOTE_METRICS_PREFIX").unwrap_or_default();
+    }
+
+    /// Prefix prepended verbatim to every vote program measurement name, empty by default
+    fn metrics_prefix() -> &'static str {
+        &METRICS_PREFIX
+    }
+
+    fn votes_point(prefix: &str, votes: usize) -> solana_metrics::influxdb::Point {
+        solana_metrics::influxdb::Point::new(&format!("{}vote-native", prefix))
+            .add_field(
+                "count",
+                solana_metrics::influxdb::Value::Integer(votes as i64),
+            )
+            .to_owned()
+    }
+
+    /// Count one accepted vote. Once `sample_rate` votes have accumulated, returns the number
+    /// of votes to report and resets the count, so submitted points add up to the total vote
+    /// count.
+    fn sample_vote(unreported_votes: &AtomicUsize, sample_rate: usize) -> Option<usize> {
+        let votes = unreported_votes.fetch_add(1, Ordering::Relaxed) + 1;
+        if votes < sample_rate {
+            return None;
+        }
+        match unreported_votes.swap(0, Ordering::Relaxed) {
+            0 => None,
+            votes => Some(votes),
+        }
+    }
+
+    /// Counts an accepted vote, submitting the votes counted so far once every
+    /// SOLANA_VOTE_METRICS_SAMPLE_RATE votes
+    pub fn record_vote() {
+        if let Some(votes) = sample_vote(&UNREPORTED_VOTES, metrics_sample_rate()) {
+            solana_metrics::submit(votes_point(metrics_prefix(), votes));
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_sample_vote() {
+            let unreported_votes = AtomicUsize::new(0);
+            let reported: Vec<_> = (0..10)
+                .filter_map(|_| sample_vote(&unreported_votes, 4))
+                .collect();
+            assert_eq!(reported, vec![4, 4]);
+
+            // Nothing is lost, the remainder goes out with the next sample
+            let total = reported.iter().sum::<usize>() + unreported_votes.load(Ordering::Relaxed);
+            assert_eq!(total, 10);
+
+            let unreported_votes = AtomicUsize::new(0);
+            assert!((0..5).all(|_| sample_vote(&unreported_votes, 1) == Some(1)));
+        }
+
+        #[test]
+        fn test_votes_point_prefix() {
+            assert_eq!(votes_point("", 1).measurement, "vote-native");
+            assert_eq!(votes_point("tenant-", 1).measurement, "tenant-vote-native");
+        }
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod metrics {
+    pub fn record_vote() {}
+}
+
+// Invalid instruction userdata is logged at most once per this many milliseconds
+const INVALID_USERDATA_LOG_INTERVAL_MS: usize = 1000;
+
+// Only this many leading bytes of invalid instruction userdata are logged
+const MAX_LOGGED_USERDATA_LEN: usize = 32;
+
+// Timestamp of the last invalid userdata log, and the number of logs dropped since then
+static LAST_INVALID_USERDATA_LOG_MS: AtomicUsize = AtomicUsize::new(0);
+static SUPPRESSED_INVALID_USERDATA_LOGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Rate limits a log to once per `INVALID_USERDATA_LOG_INTERVAL_MS`. Returns the number of
+/// logs suppressed since the previous one if a log is due at `now`.
+fn rate_limit_log(
+    last_log_ms: &AtomicUsize,
+    suppressed: &AtomicUsize,
+    now: usize,
+) -> Option<usize> {
+    let last = last_log_ms.load(Ordering::Relaxed);
+    if now.saturating_sub(last) < INVALID_USERDATA_LOG_INTERVAL_MS
+        || last_log_ms.compare_and_swap(last, now, Ordering::Relaxed) != last
+    {
+        suppressed.fetch_add(1, Ordering::Relaxed);
+        return None;
+    }
+    Some(suppressed.swap(0, Ordering::Relaxed))
+}
+
+/// Returns `keyed_accounts[index]`, or InvalidArgument if the instruction didn't carry that
+/// many accounts
+fn account<'a, 'b>(
+    keyed_accounts: &'b [KeyedAccount<'a>],
+    index: usize,
+) -> Result<&'b KeyedAccount<'a>, ProgramError> {
+    keyed_accounts.get(index).ok_or_else(|| {
+        error!("account[{}] is missing", index);
+        ProgramError::InvalidArgument
+    })
+}
+
+/// Mutable version of `account`
+fn account_mut<'a, 'b>(
+    keyed_accounts: &'b mut [KeyedAccount<'a>],
+    index: usize,
+) -> Result<&'b mut KeyedAccount<'a>, ProgramError> {
+    keyed_accounts.get_mut(index).ok_or_else(|| {
+        error!("account[{}] is missing", index);
+        ProgramError::InvalidArgument
+    })
+}
+
+/// Deserializes the vote state of `account`, logging why if it fails
+fn deserialize_vote_state(account: &Account) -> Result<VoteProgram, ProgramError> {
+    VoteProgram::deserialize(&account.userdata).map_err(|err| {
+        error!("{}", err);
+        err.into()
+    })
+}
+
+/// Serialize `vote_state` into a copy of the account's userdata, and only replace the
+/// account's userdata once that succeeds so a failed write leaves the account intact
+fn store_vote_state(vote_state: &VoteProgram, account: &mut Account) -> Result<(), ProgramError> {
+    let mut userdata = account.userdata.clone();
+    vote_state.serialize(&mut userdata)?;
+    account.userdata = userdata;
+    Ok(())
+}
+
+fn register_account(
+    program_id: &Pubkey,
+    keyed_accounts: &mut [KeyedAccount],
+    activation_tick_height: Option<u64>,
+) -> Result<(), ProgramError> {
+    // account[0]'s key becomes the node_id, which the default pubkey can't stand for
+    let node_id = *account(keyed_accounts, 0)?.signer_key().unwrap();
+    if node_id == Pubkey::default() {
+        error!("account[0] has the default pubkey, which can't be a node_id");
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    // The activation vote is stored like any other, so it can't be for the genesis either
+    if activation_tick_height == Some(0) {
+        error!("activation vote for tick_height 0, the genesis");
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    if account(keyed_accounts, 1)?.account.owner != *program_id {
+        error!("account[1] is not assigned to the VOTE_PROGRAM");
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    // An account that isn't rent-exempt could be reaped later, losing its votes
+    let vote_account = &account(keyed_accounts, 1)?.account;
+    let minimum = rent::rent_exempt_minimum(vote_account.userdata.len());
+    if vote_account.tokens < minimum {
+        error!(
+            "account[1] holds {} tokens, below the rent-exempt minimum of {}",
+            vote_account.tokens, minimum
+        );
+        Err(ProgramError::NotRentExempt)?;
+    }
+
+    // Registering again would wipe the account's votes and credits, or hand it to another
+    // validator. Nothing has been written yet, so the account is left as it was.
+    if VoteProgram::deserialize(&vote_account.userdata).is_ok() {
+        error!("account[1] is already registered");
+        Err(ProgramError::AccountAlreadyInitialized)?;
+    }
+
+    // The program can't see how many "vote accounts" the validator already registered, so
+    // the bank caps those before executing the instruction. See github issue 1654.
+    let mut vote_state = VoteProgram::new(node_id);
+
+    if let Some(tick_height) = activation_tick_height {
+        vote_state.push_activation_vote(tick_height);
+    }
+
+    store_vote_state(&vote_state, &mut account_mut(keyed_accounts, 1)?.account)?;
+
+    Ok(())
+}
+
+fn new_votes(
+    program_id: &Pubkey,
+    keyed_accounts: &mut [KeyedAccount],
+    votes: Vec<Vote>,
+) -> Result<(), ProgramError> {
+    if account(keyed_accounts, 0)?.account.owner != *program_id {
+        error!("account[0] is not assigned to the VOTE_PROGRAM");
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    let vote_id = *account(keyed_accounts, 0)?.signer_key().unwrap();
+    let mut vote_state = deserialize_vote_state(&account(keyed_accounts, 0)?.account)?;
+    for vote in votes {
+        debug!("{:?} by {}", vote, vote_id);
+        // Tick 0 is the genesis, which there's nothing to vote on
+        if vote.tick_height == 0 {
+            error!("vote by {} for tick_height 0, the genesis", vote_id);
+            Err(ProgramError::InvalidArgument)?;
+        }
+        metrics::record_vote();
+
+        // TODO: Integrity checks
+        // a) Verify the vote's bank hash matches what is expected
+        // b) Verify vote is older than previous votes
+
+        // A real vote at the activation vote's tick_height takes its place
+        if vote_state.activation_tick_height == Some(vote.tick_height) {
+            vote_state.remove_activation_vote();
+        }
+
+        // Record evidence of a validator voting for two different states at the same height
+        if vote_state.check_conflict(&vote) {
+            warn!("conflicting vote {:?} by {}", vote, vote_id);
+        }
+
+        // Only keep around the most recent MAX_VOTE_HISTORY votes, rooting the oldest. Votes
+        // aren't required to be in order yet, so a trimmed vote may be older than the root,
+        // which must never move backwards.
+        if vote_state.votes.len() == MAX_VOTE_HISTORY {
+            let trimmed = vote_state.votes.pop_front().map(|vote| vote.tick_height);
+            if trimmed == vote_state.activation_tick_height {
+                vote_state.activation_tick_height = None;
+            }
+            vote_state.root_tick_height = std::cmp::max(vote_state.root_tick_height, trimmed);
+        }
+
+        vote_state.votes.push_back(vote);
+        vote_state.credits = vote_state.credits.saturating_add(1);
+    }
+    store_vote_state(&vote_state, &mut account_mut(keyed_accounts, 0)?.account)?;
+
+    Ok(())
+}
+
+/// Checks that `recent_hash` is held by the bank-maintained recent hashes account
+fn check_recent_hash(
+    recent_hashes_account: &KeyedAccount,
+    recent_hash: &Hash,
+) -> Result<(), ProgramError> {
+    if !recent_hashes::check_id(recent_hashes_account.unsigned_key())
+        || !recent_hashes::check_id(&recent_hashes_account.account.owner)
+    {
+        error!("account[1] is not the recent hashes account");
+        Err(ProgramError::InvalidArgument)?;
+    }
+    let recent_hashes = RecentHashes::deserialize(&recent_hashes_account.account.userdata)
+        .map_err(|err| {
+            error!("failed to deserialize recent hashes: {}", err);
+            ProgramError::InvalidUserdata
+        })?;
+    if !recent_hashes.contains(recent_hash) {
+        error!("vote references unknown hash {}", recent_hash);
+        Err(ProgramError::InvalidArgument)?;
+    }
+    Ok(())
+}
+
+/// Zeroes the userdata of the "vote account" in keyed_accounts[1], so it no longer holds a
+/// vote state, and moves its tokens to keyed_accounts[2]
+fn close_account(
+    program_id: &Pubkey,
+    keyed_accounts: &mut [KeyedAccount],
+) -> Result<(), ProgramError> {
+    if account(keyed_accounts, 1)?.account.owner != *program_id {
+        error!("account[1] is not assigned to the VOTE_PROGRAM");
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    let vote_state = deserialize_vote_state(&account(keyed_accounts, 1)?.account)?;
+    if account(keyed_accounts, 0)?.signer_key() != Some(&vote_state.node_id) {
+        error!("account[0] is not the validator registered to account[1]");
+        Err(ProgramError::InvalidArgument)?;
+    }
+    if !vote_state.delegates.is_empty() {
+        error!(
+            "account[1] still has {} stake accounts delegated to it",
+            vote_state.delegates.len()
+        );
+        Err(ProgramError::InvalidArgument)?;
+    }
+    if account(keyed_accounts, 2)?.unsigned_key() == account(keyed_accounts, 1)?.unsigned_key() {
+        error!("account[2] is the vote account being closed");
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    let vote_account = &mut account_mut(keyed_accounts, 1)?.account;
+    let tokens = vote_account.tokens;
+    vote_account.tokens = 0;
+    for byte in vote_account.userdata.iter_mut() {
+        *byte = 0;
+    }
+    account_mut(keyed_accounts, 2)?.account.tokens += tokens;
+
+    Ok(())
+}
+
+/// Flags the "vote account" in keyed_accounts[1] as slashed, given a vote it signed that
+/// conflicts with one in its vote history
+fn slash(
+    program_id: &Pubkey,
+    keyed_accounts: &mut [KeyedAccount],
+    signed_vote: &SignedVote,
+) -> Result<(), ProgramError> {
+    if account(keyed_accounts, 1)?.account.owner != *program_id {
+        error!("account[1] is not assigned to the VOTE_PROGRAM");
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    let vote_id = *account(keyed_accounts, 1)?.unsigned_key();
+    if !signed_vote.verify(&vote_id) {
+        error!("vote wasn't signed by {}", vote_id);
+        Err(ProgramError::InvalidArgument)?;
+    }
+    let mut vote_state = deserialize_vote_state(&account(keyed_accounts, 1)?.account)?;
+    if vote_state.conflicting_vote(&signed_vote.vote).is_none() {
+        error!(
+            "vote doesn't conflict with {}'s vote history at tick {}",
+            vote_id, signed_vote.vote.tick_height
+        );
+        Err(ProgramError::InvalidArgument)?;
+    }
+    if vote_state.slashed {
+        error!("{} was already slashed", vote_id);
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    vote_state.slashed = true;
+    store_vote_state(&vote_state, &mut account_mut(keyed_accounts, 1)?.account)?;
+    info!(
+        "slashed {} for conflicting votes at tick {}",
+        vote_id, signed_vote.vote.tick_height
+    );
+    Ok(())
+}
+
+/// Merges the "vote account" in keyed_accounts[1] into the one in keyed_accounts[2], then
+/// zeroes its userdata and moves its tokens to keyed_accounts[2]
+fn merge_accounts(
+    program_id: &Pubkey,
+    keyed_accounts: &mut [KeyedAccount],
+) -> Result<(), ProgramError> {
+    for index in 1..=2 {
+        if account(keyed_accounts, index)?.account.owner != *program_id {
+            error!("account[{}] is not assigned to the VOTE_PROGRAM", index);
+            Err(ProgramError::InvalidArgument)?;
+        }
+    }
+    if account(keyed_accounts, 1)?.signer_key().is_none() {
+        error!("account[1] is unsigned");
+        Err(ProgramError::InvalidArgument)?;
+    }
+    if account(keyed_accounts, 2)?.unsigned_key() == account(keyed_accounts, 1)?.unsigned_key() {
+        error!("account[2] is the vote account being merged");
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    let source = deserialize_vote_state(&account(keyed_accounts, 1)?.account)?;
+    let mut destination = deserialize_vote_state(&account(keyed_accounts, 2)?.account)?;
+    if account(keyed_accounts, 0)?.signer_key() != Some(&source.node_id) {
+        error!("account[0] is not the validator registered to account[1]");
+        Err(ProgramError::InvalidArgument)?;
+    }
+    if destination.node_id != source.node_id {
+        error!("account[1] and account[2] are registered to different validators");
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    destination.merge(source)?;
+    store_vote_state(&destination, &mut account_mut(keyed_accounts, 2)?.account)?;
+
+    let source_account = &mut account_mut(keyed_accounts, 1)?.account;
+    let tokens = source_account.tokens;
+    source_account.tokens = 0;
+    for byte in source_account.userdata.iter_mut() {
+        *byte = 0;
+    }
+    account_mut(keyed_accounts, 2)?.account.tokens += tokens;
+
+    Ok(())
+}
+
+fn get_state(program_id: &Pubkey, keyed_accounts: &mut [KeyedAccount]) -> Result<(), ProgramError> {
+    if account(keyed_accounts, 0)?.account.owner != *program_id {
+        error!("account[0] is not assigned to the VOTE_PROGRAM");
+        Err(ProgramError::InvalidArgument)?;
+    }
+    if account(keyed_accounts, 1)?.account.owner != *program_id {
+        error!("account[1] is not assigned to the VOTE_PROGRAM");
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    let vote_state = deserialize_vote_state(&account(keyed_accounts, 0)?.account)?;
+    let summary = VoteStateSummary {
+        node_id: vote_state.node_id,
+        last_vote_tick_height: vote_state.votes.back().map(|vote| vote.tick_height),
+        credits: vote_state.credits,
+    };
+    let summary = bincode::serialize(&summary).map_err(|_| ProgramError::GenericError)?;
+
+    let output = &mut account_mut(keyed_accounts, 1)?.account.userdata;
+    if output.len() < summary.len() {
+        Err(ProgramError::UserdataTooSmall)?;
+    }
+    output[..summary.len()].copy_from_slice(&summary);
+
+    Ok(())
+}
 
 solana_entrypoint!(entrypoint);
+/// The vote program may be loaded under an id other than `vote_program::id()`, e.g. by a
+/// local test cluster, so the accounts it operates on must be owned by `program_id`, the id
+/// it was invoked under. Such an id is only accepted if it owns one of those accounts.
 fn entrypoint(
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
     keyed_accounts: &mut [KeyedAccount],
     data: &[u8],
     _tick_height: u64,
@@ -21,61 +481,1249 @@ fn entrypoint(
     trace!("process_instruction: {:?}", data);
     trace!("keyed_accounts: {:?}", keyed_accounts);
 
-    // all vote instructions require that accounts_keys[0] be a signer
-    if keyed_accounts[0].signer_key().is_none() {
-        error!("account[0] is unsigned");
+    if !check_id(program_id)
+        && keyed_accounts
+            .iter()
+            .all(|keyed_account| keyed_account.account.owner != *program_id)
+    {
+        error!("program_id {} is not the VOTE_PROGRAM", program_id);
+        Err(ProgramError::InvalidArgument)?;
+    }
+
+    let instruction = bincode::deserialize(data);
+
+    // all vote instructions require that accounts_keys[0] be a signer, except for GetState
+    // which only reads accounts_keys[0] and writes to accounts_keys[1]
+    let signer_index = match instruction {
+        Ok(VoteInstruction::GetState) => 1,
+        _ => 0,
+    };
+    if account(keyed_accounts, signer_index)?
+        .signer_key()
+        .is_none()
+    {
+        error!("account[{}] is unsigned", signer_index);
         Err(ProgramError::InvalidArgument)?;
     }
 
-    match bincode::deserialize(data) {
-        Ok(VoteInstruction::RegisterAccount) => {
-            if !check_id(&keyed_accounts[1].account.owner) {
+    match instruction {
+        Ok(VoteInstruction::RegisterAccount) => register_account(program_id, keyed_accounts, None),
+        Ok(VoteInstruction::RegisterAccountWithActivation(tick_height)) => {
+            register_account(program_id, keyed_accounts, Some(tick_height))
+        }
+        Ok(VoteInstruction::NewVote(vote)) => {
+            // Only the vote account itself is expected, extra accounts point at a client bug
+            if keyed_accounts.len() != 1 {
+                error!(
+                    "NewVote expects exactly 1 account, got {}",
+                    keyed_accounts.len()
+                );
+                Err(ProgramError::InvalidArgument)?;
+            }
+            new_votes(program_id, keyed_accounts, vec![vote])
+        }
+        Ok(VoteInstruction::NewVoteWithRecentHash(vote, recent_hash)) => {
+            if keyed_accounts.len() != 2 {
+                error!(
+                    "NewVoteWithRecentHash expects exactly 2 accounts, got {}",
+                    keyed_accounts.len()
+                );
+                Err(ProgramError::InvalidArgument)?;
+            }
+            check_recent_hash(account(keyed_accounts, 1)?, &recent_hash)?;
+            new_votes(program_id, &mut keyed_accounts[..1], vec![vote])
+        }
+        Ok(VoteInstruction::NewVotes(votes)) => {
+            if keyed_accounts.len() != 1 {
+                error!(
+                    "NewVotes expects exactly 1 account, got {}",
+                    keyed_accounts.len()
+                );
+                Err(ProgramError::InvalidArgument)?;
+            }
+            if votes.len() > MAX_VOTES_PER_BATCH {
+                error!(
+                    "batch of {} votes exceeds the maximum of {}",
+                    votes.len(),
+                    MAX_VOTES_PER_BATCH
+                );
+                Err(ProgramError::InvalidArgument)?;
+            }
+            new_votes(program_id, keyed_accounts, votes)
+        }
+        Ok(VoteInstruction::SetInfo(info)) => {
+            if account(keyed_accounts, 1)?.account.owner != *program_id {
                 error!("account[1] is not assigned to the VOTE_PROGRAM");
                 Err(ProgramError::InvalidArgument)?;
             }
 
-            // TODO: a single validator could register multiple "vote accounts"
-            // which would clutter the "accounts" structure. See github issue 1654.
-            let vote_state = VoteProgram {
-                votes: VecDeque::new(),
-                node_id: *keyed_accounts[0].signer_key().unwrap(),
-            };
+            let mut vote_state = deserialize_vote_state(&account(keyed_accounts, 1)?.account)?;
+            if vote_state.node_id != *account(keyed_accounts, 0)?.signer_key().unwrap() {
+                error!("account[0] is not the validator registered to account[1]");
+                Err(ProgramError::InvalidArgument)?;
+            }
 
-            vote_state.serialize(&mut keyed_accounts[1].account.userdata)?;
+            vote_state.set_info(info)?;
+            store_vote_state(&vote_state, &mut account_mut(keyed_accounts, 1)?.account)?;
 
             Ok(())
         }
-        Ok(VoteInstruction::NewVote(vote)) => {
-            if !check_id(&keyed_accounts[0].account.owner) {
-                error!("account[0] is not assigned to the VOTE_PROGRAM");
+        Ok(VoteInstruction::SetCommission(commission)) => {
+            if account(keyed_accounts, 1)?.account.owner != *program_id {
+                error!("account[1] is not assigned to the VOTE_PROGRAM");
                 Err(ProgramError::InvalidArgument)?;
             }
-            debug!("{:?} by {}", vote, keyed_accounts[0].signer_key().unwrap());
-            solana_metrics::submit(
-                solana_metrics::influxdb::Point::new("vote-native")
-                    .add_field("count", solana_metrics::influxdb::Value::Integer(1))
-                    .to_owned(),
-            );
 
-            let mut vote_state = VoteProgram::deserialize(&keyed_accounts[0].account.userdata)?;
+            let mut vote_state = deserialize_vote_state(&account(keyed_accounts, 1)?.account)?;
+            if vote_state.node_id != *account(keyed_accounts, 0)?.signer_key().unwrap() {
+                error!("account[0] is not the validator registered to account[1]");
+                Err(ProgramError::InvalidArgument)?;
+            }
 
-            // TODO: Integrity checks
-            // a) Verify the vote's bank hash matches what is expected
-            // b) Verify vote is older than previous votes
+            vote_state.set_commission(commission)?;
+            store_vote_state(&vote_state, &mut account_mut(keyed_accounts, 1)?.account)?;
 
-            // Only keep around the most recent MAX_VOTE_HISTORY votes
-            if vote_state.votes.len() == MAX_VOTE_HISTORY {
-                vote_state.votes.pop_front();
+            Ok(())
+        }
+        Ok(VoteInstruction::DelegateStake) => {
+            if account(keyed_accounts, 1)?.account.owner != *program_id {
+                error!("account[1] is not assigned to the VOTE_PROGRAM");
+                Err(ProgramError::InvalidArgument)?;
+            }
+
+            let mut vote_state = deserialize_vote_state(&account(keyed_accounts, 1)?.account)?;
+            if account(keyed_accounts, 2)?.signer_key() != Some(&vote_state.node_id) {
+                error!("account[2] is not the validator registered to account[1]");
+                Err(ProgramError::InvalidArgument)?;
             }
 
-            vote_state.votes.push_back(vote);
-            vote_state.serialize(&mut keyed_accounts[0].account.userdata)?;
+            vote_state.add_delegate(*account(keyed_accounts, 0)?.signer_key().unwrap())?;
+            store_vote_state(&vote_state, &mut account_mut(keyed_accounts, 1)?.account)?;
 
             Ok(())
         }
+        Ok(VoteInstruction::UndelegateStake) => {
+            if account(keyed_accounts, 1)?.account.owner != *program_id {
+                error!("account[1] is not assigned to the VOTE_PROGRAM");
+                Err(ProgramError::InvalidArgument)?;
+            }
+
+            let mut vote_state = deserialize_vote_state(&account(keyed_accounts, 1)?.account)?;
+            let stake_id = *account(keyed_accounts, 0)?.signer_key().unwrap();
+            if vote_state.remove_delegate(&stake_id).is_err() {
+                error!("account[0] is not delegated to account[1]");
+                Err(ProgramError::InvalidArgument)?;
+            }
+            store_vote_state(&vote_state, &mut account_mut(keyed_accounts, 1)?.account)?;
+
+            Ok(())
+        }
+        Ok(VoteInstruction::GetState) => get_state(program_id, keyed_accounts),
+        Ok(VoteInstruction::CloseAccount) => close_account(program_id, keyed_accounts),
+        Ok(VoteInstruction::Merge) => merge_accounts(program_id, keyed_accounts),
+        Ok(VoteInstruction::Slash(signed_vote)) => slash(program_id, keyed_accounts, &signed_vote),
         Err(_) => {
-            info!("Invalid transaction instruction userdata: {:?}", data);
+            if let Some(suppressed) = rate_limit_log(
+                &LAST_INVALID_USERDATA_LOG_MS,
+                &SUPPRESSED_INVALID_USERDATA_LOGS,
+                timing::timestamp() as usize,
+            ) {
+                info!(
+                    "Invalid transaction instruction userdata ({} bytes): {:?}, {} more suppressed",
+                    data.len(),
+                    &data[..data.len().min(MAX_LOGGED_USERDATA_LEN)],
+                    suppressed
+                );
+            }
             Err(ProgramError::InvalidUserdata)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::signature::{Keypair, KeypairUtil};
+    use solana_sdk::transaction::Transaction;
+    use solana_sdk::vote_transaction::VoteTransaction;
+
+    fn create_vote_account() -> (Pubkey, Account) {
+        let vote_id = Keypair::new().pubkey();
+        let account = Account::new(1, get_max_size(), id());
+        (vote_id, account)
+    }
+
+    fn register(
+        node_id: &Pubkey,
+        vote_id: &Pubkey,
+        vote_account: &mut Account,
+    ) -> Result<(), ProgramError> {
+        let mut node_account = Account::default();
+        let mut keyed_accounts = [
+            KeyedAccount::new(node_id, true, &mut node_account),
+            KeyedAccount::new(vote_id, false, vote_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::RegisterAccount).unwrap();
+        entrypoint(&id(), &mut keyed_accounts, &data, 0)
+    }
+
+    fn vote(vote_id: &Pubkey, vote_account: &mut Account, vote: Vote) -> Result<(), ProgramError> {
+        let mut keyed_accounts = [KeyedAccount::new(vote_id, true, vote_account)];
+        let data = bincode::serialize(&VoteInstruction::NewVote(vote)).unwrap();
+        entrypoint(&id(), &mut keyed_accounts, &data, 0)
+    }
+
+    fn set_info(
+        node_id: &Pubkey,
+        vote_id: &Pubkey,
+        vote_account: &mut Account,
+        info: &[u8],
+    ) -> Result<(), ProgramError> {
+        let mut node_account = Account::default();
+        let mut keyed_accounts = [
+            KeyedAccount::new(node_id, true, &mut node_account),
+            KeyedAccount::new(vote_id, false, vote_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::SetInfo(info.to_vec())).unwrap();
+        entrypoint(&id(), &mut keyed_accounts, &data, 0)
+    }
+
+    fn delegate(
+        stake_id: &Pubkey,
+        vote_id: &Pubkey,
+        vote_account: &mut Account,
+        node_id: &Pubkey,
+        node_signed: bool,
+    ) -> Result<(), ProgramError> {
+        let mut stake_account = Account::new(10, 0, Pubkey::default());
+        let mut node_account = Account::default();
+        let mut keyed_accounts = [
+            KeyedAccount::new(stake_id, true, &mut stake_account),
+            KeyedAccount::new(vote_id, false, vote_account),
+            KeyedAccount::new(node_id, node_signed, &mut node_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::DelegateStake).unwrap();
+        entrypoint(&id(), &mut keyed_accounts, &data, 0)
+    }
+
+    fn undelegate(
+        stake_id: &Pubkey,
+        vote_id: &Pubkey,
+        vote_account: &mut Account,
+    ) -> Result<(), ProgramError> {
+        let mut stake_account = Account::new(10, 0, Pubkey::default());
+        let mut keyed_accounts = [
+            KeyedAccount::new(stake_id, true, &mut stake_account),
+            KeyedAccount::new(vote_id, false, vote_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::UndelegateStake).unwrap();
+        entrypoint(&id(), &mut keyed_accounts, &data, 0)
+    }
+
+    #[test]
+    fn test_missing_accounts() {
+        let (vote_id, mut vote_account) = create_vote_account();
+        let vote_tx = Transaction::vote_new(&Keypair::new(), Vote::default(), Hash::default(), 0);
+        let instructions = vec![
+            VoteInstruction::RegisterAccount,
+            VoteInstruction::NewVote(Vote::default()),
+            VoteInstruction::SetInfo(vec![]),
+            VoteInstruction::RegisterAccountWithActivation(1),
+            VoteInstruction::DelegateStake,
+            VoteInstruction::GetState,
+            VoteInstruction::SetCommission(0),
+            VoteInstruction::NewVoteWithRecentHash(Vote::default(), Hash::default()),
+            VoteInstruction::NewVotes(vec![]),
+            VoteInstruction::CloseAccount,
+            VoteInstruction::Merge,
+            VoteInstruction::UndelegateStake,
+            VoteInstruction::Slash(SignedVote::from_transaction(&vote_tx).unwrap()),
+        ];
+        for instruction in instructions {
+            let data = bincode::serialize(&instruction).unwrap();
+            assert_eq!(
+                entrypoint(&id(), &mut [], &data, 0),
+                Err(ProgramError::InvalidArgument)
+            );
+
+            // Every instruction but NewVote and NewVotes expects a second account
+            let expects_two_accounts = match instruction {
+                VoteInstruction::NewVote(_) | VoteInstruction::NewVotes(_) => false,
+                _ => true,
+            };
+            if expects_two_accounts {
+                let mut keyed_accounts = [KeyedAccount::new(&vote_id, true, &mut vote_account)];
+                assert_eq!(
+                    entrypoint(&id(), &mut keyed_accounts, &data, 0),
+                    Err(ProgramError::InvalidArgument)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_vote_with_extra_account() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        // A NewVote carrying a second account, as if it were a RegisterAccount
+        let mut node_account = Account::default();
+        let mut keyed_accounts = [
+            KeyedAccount::new(&vote_id, true, &mut vote_account),
+            KeyedAccount::new(&node_id, false, &mut node_account),
+        ];
+        let vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::default(),
+        };
+        let data = bincode::serialize(&VoteInstruction::NewVote(vote)).unwrap();
+        assert_eq!(
+            entrypoint(&id(), &mut keyed_accounts, &data, 0),
+            Err(ProgramError::InvalidArgument)
+        );
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert!(vote_state.votes.is_empty());
+    }
+
+    #[test]
+    fn test_vote_with_recent_hash() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        let recent_hash = Hash::new(&[1; 32]);
+        let mut recent_hashes = RecentHashes::default();
+        recent_hashes.push(recent_hash);
+        let mut recent_hashes_account = Account::new(1, 0, recent_hashes::id());
+        recent_hashes_account.userdata = recent_hashes.serialize();
+
+        let mut vote_with_recent_hash =
+            |recent_hashes_id: &Pubkey, recent_hashes_account: &mut Account, hash: Hash| {
+                let mut keyed_accounts = [
+                    KeyedAccount::new(&vote_id, true, &mut vote_account),
+                    KeyedAccount::new(recent_hashes_id, false, recent_hashes_account),
+                ];
+                let vote = Vote {
+                    tick_height: 1,
+                    bank_hash: Hash::default(),
+                };
+                let instruction = VoteInstruction::NewVoteWithRecentHash(vote, hash);
+                let data = bincode::serialize(&instruction).unwrap();
+                entrypoint(&id(), &mut keyed_accounts, &data, 0)
+            };
+
+        vote_with_recent_hash(
+            &recent_hashes::id(),
+            &mut recent_hashes_account,
+            recent_hash,
+        )
+        .unwrap();
+
+        // A stale or made up hash
+        assert_eq!(
+            vote_with_recent_hash(
+                &recent_hashes::id(),
+                &mut recent_hashes_account,
+                Hash::new(&[2; 32])
+            ),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        // An account posing as the recent hashes account
+        let mut forged_account = Account::new(1, 0, Keypair::new().pubkey());
+        forged_account.userdata = recent_hashes.serialize();
+        assert_eq!(
+            vote_with_recent_hash(&Keypair::new().pubkey(), &mut forged_account, recent_hash),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.votes.len(), 1);
+    }
+
+    #[test]
+    fn test_vote_batch() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        let mut vote_batch = |num_votes: usize| {
+            let votes = (1..=num_votes as u64)
+                .map(|tick_height| Vote {
+                    tick_height,
+                    bank_hash: Hash::default(),
+                })
+                .collect();
+            let mut keyed_accounts = [KeyedAccount::new(&vote_id, true, &mut vote_account)];
+            let data = bincode::serialize(&VoteInstruction::NewVotes(votes)).unwrap();
+            entrypoint(&id(), &mut keyed_accounts, &data, 0)
+        };
+
+        assert_eq!(
+            vote_batch(MAX_VOTES_PER_BATCH + 1),
+            Err(ProgramError::InvalidArgument)
+        );
+        vote_batch(MAX_VOTES_PER_BATCH).unwrap();
+
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.votes.len(), MAX_VOTES_PER_BATCH);
+        assert_eq!(vote_state.credits, MAX_VOTES_PER_BATCH as u64);
+    }
+
+    #[test]
+    fn test_vote_for_tick_zero() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        let zero_vote = Vote {
+            tick_height: 0,
+            bank_hash: Hash::default(),
+        };
+        assert_eq!(
+            vote(&vote_id, &mut vote_account, zero_vote),
+            Err(ProgramError::InvalidArgument)
+        );
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert!(vote_state.votes.is_empty());
+        assert_eq!(vote_state.credits, 0);
+
+        let first_vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::default(),
+        };
+        vote(&vote_id, &mut vote_account, first_vote.clone()).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.votes, vec![first_vote]);
+    }
+
+    #[test]
+    fn test_register_twice() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+        let new_vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::default(),
+        };
+        vote(&vote_id, &mut vote_account, new_vote).unwrap();
+        let userdata = vote_account.userdata.clone();
+
+        // Neither the same nor another validator can register the account again
+        for other_node_id in &[node_id, Keypair::new().pubkey()] {
+            assert_eq!(
+                register(other_node_id, &vote_id, &mut vote_account),
+                Err(ProgramError::AccountAlreadyInitialized)
+            );
+            assert_eq!(vote_account.userdata, userdata);
+        }
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.node_id, node_id);
+        assert_eq!(vote_state.votes.len(), 1);
+    }
+
+    #[test]
+    fn test_root_tick_height() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        for tick_height in 1..=MAX_VOTE_HISTORY as u64 {
+            let new_vote = Vote {
+                tick_height,
+                bank_hash: Hash::default(),
+            };
+            vote(&vote_id, &mut vote_account, new_vote).unwrap();
+        }
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.root_tick_height, None);
+
+        for tick_height in MAX_VOTE_HISTORY as u64 + 1..=MAX_VOTE_HISTORY as u64 + 2 {
+            let new_vote = Vote {
+                tick_height,
+                bank_hash: Hash::default(),
+            };
+            vote(&vote_id, &mut vote_account, new_vote).unwrap();
+        }
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.root_tick_height, Some(2));
+        assert_eq!(vote_state.votes.len(), MAX_VOTE_HISTORY);
+    }
+
+    #[test]
+    fn test_root_tick_height_never_regresses() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        // An out of order vote at the front of the history, followed by ticks 1, 2, ...
+        let first_tick_height = 10;
+        let tick_heights =
+            std::iter::once(first_tick_height).chain(1..=MAX_VOTE_HISTORY as u64 + 20);
+        for (i, tick_height) in tick_heights.enumerate() {
+            let new_vote = Vote {
+                tick_height,
+                bank_hash: Hash::default(),
+            };
+            vote(&vote_id, &mut vote_account, new_vote).unwrap();
+
+            // The root advances to the trimmed vote, unless that vote is below the root
+            let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+            let num_trimmed = (i + 1).saturating_sub(MAX_VOTE_HISTORY) as u64;
+            let expected_root = match num_trimmed {
+                0 => None,
+                n => Some(std::cmp::max(first_tick_height, n - 1)),
+            };
+            assert_eq!(vote_state.root_tick_height, expected_root);
+            assert!(vote_state.votes.len() <= MAX_VOTE_HISTORY);
+        }
+    }
+
+    fn get_state(
+        vote_id: &Pubkey,
+        vote_account: &mut Account,
+        summary_account: &mut Account,
+    ) -> Result<(), ProgramError> {
+        let summary_id = Keypair::new().pubkey();
+        let mut keyed_accounts = [
+            KeyedAccount::new(vote_id, false, vote_account),
+            KeyedAccount::new(&summary_id, true, summary_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::GetState).unwrap();
+        entrypoint(&id(), &mut keyed_accounts, &data, 0)
+    }
+
+    #[test]
+    fn test_get_state() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+        for tick_height in 1..=2 {
+            let new_vote = Vote {
+                tick_height,
+                bank_hash: Hash::default(),
+            };
+            vote(&vote_id, &mut vote_account, new_vote).unwrap();
+        }
+
+        let mut summary_account = Account::new(1, 64, id());
+        get_state(&vote_id, &mut vote_account, &mut summary_account).unwrap();
+        let summary: VoteStateSummary = bincode::deserialize(&summary_account.userdata).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(
+            summary,
+            VoteStateSummary {
+                node_id,
+                last_vote_tick_height: Some(2),
+                credits: vote_state.credits,
+            }
+        );
+        assert_eq!(vote_state.credits, 2);
+
+        let mut summary_account = Account::new(1, 1, id());
+        assert_eq!(
+            get_state(&vote_id, &mut vote_account, &mut summary_account),
+            Err(ProgramError::UserdataTooSmall)
+        );
+
+        // The summary account has to sign, so other accounts can't be overwritten
+        let mut summary_account = Account::new(1, 64, id());
+        let summary_id = Keypair::new().pubkey();
+        let mut keyed_accounts = [
+            KeyedAccount::new(&vote_id, false, &mut vote_account),
+            KeyedAccount::new(&summary_id, false, &mut summary_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::GetState).unwrap();
+        assert_eq!(
+            entrypoint(&id(), &mut keyed_accounts, &data, 0),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_credits_saturate() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        let mut vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        vote_state.credits = std::u64::MAX - 1;
+        vote_state.serialize(&mut vote_account.userdata).unwrap();
+
+        for tick_height in 1..=2 {
+            let new_vote = Vote {
+                tick_height,
+                bank_hash: Hash::default(),
+            };
+            vote(&vote_id, &mut vote_account, new_vote).unwrap();
+        }
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.credits, std::u64::MAX);
+    }
+
+    #[test]
+    fn test_wrong_program_id() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        let mut node_account = Account::default();
+        let mut keyed_accounts = [
+            KeyedAccount::new(&node_id, true, &mut node_account),
+            KeyedAccount::new(&vote_id, false, &mut vote_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::RegisterAccount).unwrap();
+        assert_eq!(
+            entrypoint(&Keypair::new().pubkey(), &mut keyed_accounts, &data, 0),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(vote_account.userdata, vec![0; get_max_size()]);
+    }
+
+    #[test]
+    fn test_configured_program_id() {
+        let program_id = Keypair::new().pubkey();
+        let node_id = Keypair::new().pubkey();
+        let vote_id = Keypair::new().pubkey();
+        let mut node_account = Account::default();
+        let mut vote_account = Account::new(1, get_max_size(), program_id);
+        let data = bincode::serialize(&VoteInstruction::RegisterAccount).unwrap();
+
+        // Accounts owned by the id the program was invoked under identify it as the
+        // configured vote program id
+        let mut keyed_accounts = [
+            KeyedAccount::new(&node_id, true, &mut node_account),
+            KeyedAccount::new(&vote_id, false, &mut vote_account),
+        ];
+        entrypoint(&program_id, &mut keyed_accounts, &data, 0).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.node_id, node_id);
+
+        // Its accounts are no vote accounts to the default vote program id
+        let vote_for_tick_one = Vote {
+            tick_height: 1,
+            bank_hash: Hash::default(),
+        };
+        assert_eq!(
+            vote(&vote_id, &mut vote_account, vote_for_tick_one.clone()),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        let data = bincode::serialize(&VoteInstruction::NewVote(vote_for_tick_one)).unwrap();
+        let mut keyed_accounts = [KeyedAccount::new(&vote_id, true, &mut vote_account)];
+        entrypoint(&program_id, &mut keyed_accounts, &data, 0).unwrap();
+    }
+
+    #[test]
+    fn test_register_rent_exempt() {
+        let node_id = Keypair::new().pubkey();
+        let vote_id = Keypair::new().pubkey();
+        let minimum = rent::rent_exempt_minimum(get_max_size());
+
+        let mut vote_account = Account::new(minimum - 1, get_max_size(), id());
+        assert_eq!(
+            register(&node_id, &vote_id, &mut vote_account),
+            Err(ProgramError::NotRentExempt)
+        );
+        assert_eq!(vote_account.userdata, vec![0; get_max_size()]);
+
+        let mut vote_account = Account::new(minimum, get_max_size(), id());
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.node_id, node_id);
+    }
+
+    #[test]
+    fn test_set_info() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        set_info(&node_id, &vote_id, &mut vote_account, b"first").unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.info(), b"first");
+
+        set_info(&node_id, &vote_id, &mut vote_account, b"second").unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.info(), b"second");
+
+        assert_eq!(
+            set_info(
+                &node_id,
+                &vote_id,
+                &mut vote_account,
+                &[0; MAX_INFO_SIZE + 1]
+            ),
+            Err(ProgramError::InvalidArgument)
+        );
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.info(), b"second");
+
+        // Only the registered validator may set the info
+        let other_node_id = Keypair::new().pubkey();
+        assert_eq!(
+            set_info(&other_node_id, &vote_id, &mut vote_account, b"other"),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_set_commission() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        let set_commission = |node_id: &Pubkey, vote_account: &mut Account, commission| {
+            let mut node_account = Account::default();
+            let mut keyed_accounts = [
+                KeyedAccount::new(node_id, true, &mut node_account),
+                KeyedAccount::new(&vote_id, false, vote_account),
+            ];
+            let data = bincode::serialize(&VoteInstruction::SetCommission(commission)).unwrap();
+            entrypoint(&id(), &mut keyed_accounts, &data, 0)
+        };
+
+        set_commission(&node_id, &mut vote_account, MAX_COMMISSION).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.commission, MAX_COMMISSION);
+
+        assert_eq!(
+            set_commission(&node_id, &mut vote_account, MAX_COMMISSION + 1),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        // Only the registered validator may set the commission
+        let other_node_id = Keypair::new().pubkey();
+        assert_eq!(
+            set_commission(&other_node_id, &mut vote_account, 0),
+            Err(ProgramError::InvalidArgument)
+        );
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.commission, MAX_COMMISSION);
+    }
+
+    fn close(
+        node_id: &Pubkey,
+        vote_id: &Pubkey,
+        vote_account: &mut Account,
+        to_account: &mut Account,
+    ) -> Result<(), ProgramError> {
+        let mut node_account = Account::default();
+        let to_id = Keypair::new().pubkey();
+        let mut keyed_accounts = [
+            KeyedAccount::new(node_id, true, &mut node_account),
+            KeyedAccount::new(vote_id, false, vote_account),
+            KeyedAccount::new(&to_id, false, to_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::CloseAccount).unwrap();
+        entrypoint(&id(), &mut keyed_accounts, &data, 0)
+    }
+
+    #[test]
+    fn test_close_account() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+        let new_vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::default(),
+        };
+        vote(&vote_id, &mut vote_account, new_vote).unwrap();
+
+        let mut to_account = Account::new(2, 0, Pubkey::default());
+        close(&node_id, &vote_id, &mut vote_account, &mut to_account).unwrap();
+        assert_eq!(vote_account.tokens, 0);
+        assert_eq!(to_account.tokens, 3);
+        assert!(vote_account.userdata.iter().all(|byte| *byte == 0));
+        assert!(VoteProgram::deserialize(&vote_account.userdata).is_err());
+
+        // There's nothing left to close
+        assert!(close(&node_id, &vote_id, &mut vote_account, &mut to_account).is_err());
+    }
+
+    #[test]
+    fn test_close_account_with_delegated_stake() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        let stake_id = Keypair::new().pubkey();
+        delegate(&stake_id, &vote_id, &mut vote_account, &node_id, true).unwrap();
+
+        let mut to_account = Account::default();
+        assert_eq!(
+            close(&node_id, &vote_id, &mut vote_account, &mut to_account),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(vote_account.tokens, 1);
+        assert_eq!(to_account.tokens, 0);
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.delegates, vec![stake_id]);
+
+        // Once the stake is withdrawn the account can be closed
+        undelegate(&stake_id, &vote_id, &mut vote_account).unwrap();
+        close(&node_id, &vote_id, &mut vote_account, &mut to_account).unwrap();
+        assert_eq!(to_account.tokens, 1);
+    }
+
+    #[test]
+    fn test_delegate_stake_unauthorized() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+        let stake_id = Keypair::new().pubkey();
+
+        // The registered validator has to sign for the delegation
+        assert_eq!(
+            delegate(&stake_id, &vote_id, &mut vote_account, &node_id, false),
+            Err(ProgramError::InvalidArgument)
+        );
+        let other_node_id = Keypair::new().pubkey();
+        assert_eq!(
+            delegate(&stake_id, &vote_id, &mut vote_account, &other_node_id, true),
+            Err(ProgramError::InvalidArgument)
+        );
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert!(vote_state.delegates.is_empty());
+    }
+
+    #[test]
+    fn test_undelegate_stake() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+        let stake_ids: Vec<_> = (0..2).map(|_| Keypair::new().pubkey()).collect();
+        for stake_id in &stake_ids {
+            delegate(stake_id, &vote_id, &mut vote_account, &node_id, true).unwrap();
+        }
+
+        // The stake account withdraws its delegation without the validator
+        undelegate(&stake_ids[0], &vote_id, &mut vote_account).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.delegates, vec![stake_ids[1]]);
+
+        // A stake account that isn't delegated has nothing to withdraw
+        assert_eq!(
+            undelegate(&stake_ids[0], &vote_id, &mut vote_account),
+            Err(ProgramError::InvalidArgument)
+        );
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.delegates, vec![stake_ids[1]]);
+    }
+
+    #[test]
+    fn test_close_account_unauthorized() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        // Only the registered validator may close the account
+        let mut to_account = Account::default();
+        let other_node_id = Keypair::new().pubkey();
+        assert_eq!(
+            close(&other_node_id, &vote_id, &mut vote_account, &mut to_account),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        // ...and it must sign the instruction
+        let mut node_account = Account::default();
+        let to_id = Keypair::new().pubkey();
+        let mut keyed_accounts = [
+            KeyedAccount::new(&node_id, false, &mut node_account),
+            KeyedAccount::new(&vote_id, false, &mut vote_account),
+            KeyedAccount::new(&to_id, false, &mut to_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::CloseAccount).unwrap();
+        assert_eq!(
+            entrypoint(&id(), &mut keyed_accounts, &data, 0),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        assert_eq!(vote_account.tokens, 1);
+        assert_eq!(to_account.tokens, 0);
+        assert!(VoteProgram::deserialize(&vote_account.userdata).is_ok());
+    }
+
+    fn merge(
+        node_id: &Pubkey,
+        source_id: &Pubkey,
+        source_account: &mut Account,
+        destination_id: &Pubkey,
+        destination_account: &mut Account,
+    ) -> Result<(), ProgramError> {
+        let mut node_account = Account::default();
+        let mut keyed_accounts = [
+            KeyedAccount::new(node_id, true, &mut node_account),
+            KeyedAccount::new(source_id, true, source_account),
+            KeyedAccount::new(destination_id, false, destination_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::Merge).unwrap();
+        entrypoint(&id(), &mut keyed_accounts, &data, 0)
+    }
+
+    fn vote_at(vote_id: &Pubkey, vote_account: &mut Account, tick_heights: &[u64]) {
+        for tick_height in tick_heights {
+            let new_vote = Vote {
+                tick_height: *tick_height,
+                bank_hash: Hash::default(),
+            };
+            vote(vote_id, vote_account, new_vote).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_merge_accounts() {
+        let node_id = Keypair::new().pubkey();
+        let (source_id, mut source_account) = create_vote_account();
+        let (destination_id, mut destination_account) = create_vote_account();
+        register(&node_id, &source_id, &mut source_account).unwrap();
+        register(&node_id, &destination_id, &mut destination_account).unwrap();
+        vote_at(&source_id, &mut source_account, &[1, 3, 5]);
+        vote_at(&destination_id, &mut destination_account, &[2, 3]);
+
+        merge(
+            &node_id,
+            &source_id,
+            &mut source_account,
+            &destination_id,
+            &mut destination_account,
+        )
+        .unwrap();
+        let vote_state = VoteProgram::deserialize(&destination_account.userdata).unwrap();
+        let tick_heights: Vec<_> = vote_state
+            .votes
+            .iter()
+            .map(|vote| vote.tick_height)
+            .collect();
+        assert_eq!(tick_heights, vec![1, 2, 3, 5]);
+        assert_eq!(vote_state.node_id, node_id);
+        assert_eq!(destination_account.tokens, 2);
+
+        // The source is left zeroed
+        assert_eq!(source_account.tokens, 0);
+        assert!(source_account.userdata.iter().all(|byte| *byte == 0));
+        assert!(VoteProgram::deserialize(&source_account.userdata).is_err());
+    }
+
+    #[test]
+    fn test_merge_accounts_sums_credits() {
+        let node_id = Keypair::new().pubkey();
+        let (source_id, mut source_account) = create_vote_account();
+        let (destination_id, mut destination_account) = create_vote_account();
+        register(&node_id, &source_id, &mut source_account).unwrap();
+        register(&node_id, &destination_id, &mut destination_account).unwrap();
+
+        // Together the accounts hold more votes than the history keeps, the oldest are rooted
+        let source_ticks: Vec<_> = (1..=MAX_VOTE_HISTORY as u64).collect();
+        vote_at(&source_id, &mut source_account, &source_ticks);
+        vote_at(&destination_id, &mut destination_account, &[100, 101, 102]);
+
+        merge(
+            &node_id,
+            &source_id,
+            &mut source_account,
+            &destination_id,
+            &mut destination_account,
+        )
+        .unwrap();
+        let vote_state = VoteProgram::deserialize(&destination_account.userdata).unwrap();
+        assert_eq!(vote_state.credits, MAX_VOTE_HISTORY as u64 + 3);
+        assert_eq!(vote_state.votes.len(), MAX_VOTE_HISTORY);
+        assert_eq!(vote_state.votes.front().unwrap().tick_height, 4);
+        assert_eq!(vote_state.votes.back().unwrap().tick_height, 102);
+        assert_eq!(vote_state.root_tick_height, Some(3));
+    }
+
+    #[test]
+    fn test_merge_accounts_mismatched_node_ids() {
+        let node_id = Keypair::new().pubkey();
+        let other_node_id = Keypair::new().pubkey();
+        let (source_id, mut source_account) = create_vote_account();
+        let (destination_id, mut destination_account) = create_vote_account();
+        register(&node_id, &source_id, &mut source_account).unwrap();
+        register(&other_node_id, &destination_id, &mut destination_account).unwrap();
+        vote_at(&source_id, &mut source_account, &[1]);
+
+        for signer in &[node_id, other_node_id] {
+            assert_eq!(
+                merge(
+                    signer,
+                    &source_id,
+                    &mut source_account,
+                    &destination_id,
+                    &mut destination_account,
+                ),
+                Err(ProgramError::InvalidArgument)
+            );
+        }
+        assert_eq!(source_account.tokens, 1);
+        let vote_state = VoteProgram::deserialize(&source_account.userdata).unwrap();
+        assert_eq!(vote_state.credits, 1);
+        let vote_state = VoteProgram::deserialize(&destination_account.userdata).unwrap();
+        assert_eq!(vote_state.credits, 0);
+    }
+
+    #[cfg(not(feature = "metrics"))]
+    #[test]
+    fn test_vote_without_metrics() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        let votes: Vec<_> = (1..=3)
+            .map(|tick_height| Vote {
+                tick_height,
+                bank_hash: Hash::default(),
+            })
+            .collect();
+        for new_vote in &votes {
+            vote(&vote_id, &mut vote_account, new_vote.clone()).unwrap();
+        }
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.votes, votes);
+        assert_eq!(vote_state.credits, 3);
+    }
+
+    #[test]
+    fn test_rate_limit_log() {
+        let last_log_ms = AtomicUsize::new(0);
+        let suppressed = AtomicUsize::new(0);
+        let logged: Vec<_> = (1000..3500)
+            .filter_map(|now| rate_limit_log(&last_log_ms, &suppressed, now))
+            .collect();
+        assert_eq!(logged, vec![0, 999, 999]);
+        assert_eq!(suppressed.load(Ordering::Relaxed), 499);
+    }
+
+    #[test]
+    fn test_invalid_userdata() {
+        let (vote_id, mut vote_account) = create_vote_account();
+        for _ in 0..100 {
+            let mut keyed_accounts = [KeyedAccount::new(&vote_id, true, &mut vote_account)];
+            assert_eq!(
+                entrypoint(&id(), &mut keyed_accounts, &[0xff; 64], 0),
+                Err(ProgramError::InvalidUserdata)
+            );
+        }
+        // Only the first of the back-to-back failures was logged
+        assert!(SUPPRESSED_INVALID_USERDATA_LOGS.load(Ordering::Relaxed) >= 99);
+    }
+
+    #[test]
+    fn test_register_with_activation() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        let mut node_account = Account::default();
+        let mut keyed_accounts = [
+            KeyedAccount::new(&node_id, true, &mut node_account),
+            KeyedAccount::new(&vote_id, false, &mut vote_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::RegisterAccountWithActivation(5)).unwrap();
+        entrypoint(&id(), &mut keyed_accounts, &data, 0).unwrap();
+
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(vote_state.node_id, node_id);
+        assert_eq!(vote_state.activation_tick_height, Some(5));
+        assert_eq!(
+            vote_state.votes.into_iter().collect::<Vec<_>>(),
+            vec![Vote {
+                tick_height: 5,
+                bank_hash: Hash::default(),
+            }]
+        );
+
+        // A real vote at the activation tick takes the activation vote's place, after which
+        // voting for another bank hash there conflicts
+        let first_vote = Vote {
+            tick_height: 5,
+            bank_hash: Hash::new(&[1; 32]),
+        };
+        vote(&vote_id, &mut vote_account, first_vote.clone()).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert!(!vote_state.is_conflicted());
+        assert_eq!(vote_state.activation_tick_height, None);
+        assert_eq!(
+            vote_state.votes.into_iter().collect::<Vec<_>>(),
+            vec![first_vote.clone()]
+        );
+
+        let second_vote = Vote {
+            tick_height: 5,
+            bank_hash: Hash::default(),
+        };
+        vote(&vote_id, &mut vote_account, second_vote.clone()).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert_eq!(
+            vote_state.conflicting_votes,
+            Some((first_vote, second_vote))
+        );
+
+        // Without an activation tick the history starts out empty
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert!(vote_state.votes.is_empty());
+
+        // An activation vote for the genesis is rejected like a NewVote for it
+        let (vote_id, mut vote_account) = create_vote_account();
+        let mut keyed_accounts = [
+            KeyedAccount::new(&node_id, true, &mut node_account),
+            KeyedAccount::new(&vote_id, false, &mut vote_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::RegisterAccountWithActivation(0)).unwrap();
+        assert_eq!(
+            entrypoint(&id(), &mut keyed_accounts, &data, 0),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert!(VoteProgram::deserialize(&vote_account.userdata).is_err());
+    }
+
+    #[test]
+    fn test_register_default_node_id() {
+        let (vote_id, mut vote_account) = create_vote_account();
+        assert_eq!(
+            register(&Pubkey::default(), &vote_id, &mut vote_account),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert!(VoteProgram::deserialize(&vote_account.userdata).is_err());
+
+        let node_id = Keypair::new().pubkey();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+    }
+
+    #[test]
+    fn test_conflicting_votes() {
+        let node_id = Keypair::new().pubkey();
+        let (vote_id, mut vote_account) = create_vote_account();
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        let first_vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::new(&[1; 32]),
+        };
+        vote(&vote_id, &mut vote_account, first_vote.clone()).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert!(!vote_state.is_conflicted());
+
+        let second_vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::new(&[2; 32]),
+        };
+        vote(&vote_id, &mut vote_account, second_vote.clone()).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert!(vote_state.is_conflicted());
+        assert_eq!(
+            vote_state.conflicting_votes,
+            Some((first_vote, second_vote))
+        );
+    }
+
+    fn slash_account(
+        vote_id: &Pubkey,
+        vote_account: &mut Account,
+        signed_vote: SignedVote,
+    ) -> Result<(), ProgramError> {
+        let reporter_id = Keypair::new().pubkey();
+        let mut reporter_account = Account::default();
+        let mut keyed_accounts = [
+            KeyedAccount::new(&reporter_id, true, &mut reporter_account),
+            KeyedAccount::new(vote_id, false, vote_account),
+        ];
+        let data = bincode::serialize(&VoteInstruction::Slash(signed_vote)).unwrap();
+        entrypoint(&id(), &mut keyed_accounts, &data, 0)
+    }
+
+    #[test]
+    fn test_slash() {
+        let node_id = Keypair::new().pubkey();
+        let vote_keypair = Keypair::new();
+        let vote_id = vote_keypair.pubkey();
+        let mut vote_account = Account::new(1, get_max_size(), id());
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+
+        let signed_vote = |keypair: &Keypair, tick_height, bank_hash| {
+            let vote = Vote {
+                tick_height,
+                bank_hash,
+            };
+            let vote_tx = Transaction::vote_new(keypair, vote, Hash::default(), 0);
+            SignedVote::from_transaction(&vote_tx).unwrap()
+        };
+        let first_hash = Hash::new(&[1; 32]);
+        let second_hash = Hash::new(&[2; 32]);
+        let first_vote = Vote {
+            tick_height: 5,
+            bank_hash: first_hash,
+        };
+        vote(&vote_id, &mut vote_account, first_vote).unwrap();
+
+        // The vote already in the history, a vote at another tick, and a vote signed by
+        // another account aren't conflicts
+        for invalid in vec![
+            signed_vote(&vote_keypair, 5, first_hash),
+            signed_vote(&vote_keypair, 6, second_hash),
+            signed_vote(&Keypair::new(), 5, second_hash),
+        ] {
+            assert_eq!(
+                slash_account(&vote_id, &mut vote_account, invalid),
+                Err(ProgramError::InvalidArgument)
+            );
+        }
+
+        // A signature over a different vote
+        let mut forged = signed_vote(&vote_keypair, 5, first_hash);
+        forged.vote.bank_hash = second_hash;
+        assert_eq!(
+            slash_account(&vote_id, &mut vote_account, forged),
+            Err(ProgramError::InvalidArgument)
+        );
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert!(!vote_state.slashed);
+
+        let conflict = signed_vote(&vote_keypair, 5, second_hash);
+        slash_account(&vote_id, &mut vote_account, conflict.clone()).unwrap();
+        let vote_state = VoteProgram::deserialize(&vote_account.userdata).unwrap();
+        assert!(vote_state.slashed);
+
+        // An account can't be slashed twice
+        assert_eq!(
+            slash_account(&vote_id, &mut vote_account, conflict),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_failed_write_leaves_account_intact() {
+        let node_id = Keypair::new().pubkey();
+        let vote_id = Keypair::new().pubkey();
+
+        // Too small to hold even an empty vote state
+        let mut vote_account = Account::new(1, 4, id());
+        vote_account.userdata = vec![1, 2, 3, 4];
+        assert_eq!(
+            register(&node_id, &vote_id, &mut vote_account),
+            Err(ProgramError::UserdataTooSmall)
+        );
+        assert_eq!(vote_account.userdata, vec![1, 2, 3, 4]);
+
+        // Exactly large enough for an empty vote state, but not for one holding a vote
+        let empty_state = VoteProgram::new(node_id);
+        let space = bincode::serialized_size(&empty_state).unwrap() as usize + 2;
+        let mut vote_account = Account::new(1, space, id());
+        register(&node_id, &vote_id, &mut vote_account).unwrap();
+        let registered_userdata = vote_account.userdata.clone();
+
+        let vote_for_tick_one = Vote {
+            tick_height: 1,
+            bank_hash: Hash::default(),
+        };
+        assert_eq!(
+            vote(&vote_id, &mut vote_account, vote_for_tick_one),
+            Err(ProgramError::UserdataTooSmall)
+        );
+        assert_eq!(vote_account.userdata, registered_userdata);
+        assert_eq!(
+            VoteProgram::deserialize(&vote_account.userdata).unwrap(),
+            empty_state
+        );
+    }
+}
@@ -12,6 +12,8 @@ pub mod native_program;
 pub mod packet;
 pub mod payment_plan;
 pub mod pubkey;
+pub mod recent_hashes;
+pub mod rent;
 pub mod signature;
 pub mod storage_program;
 pub mod system_instruction;
@@ -37,6 +37,16 @@ pub enum ProgramError {
     /// SystemInstruction::Spawn was attempted on an account that was not finalized by
     /// LoaderInstruction::Finalize
     AccountNotFinalized,
+
+    /// An account's balance is below the rent-exempt minimum for its userdata
+    NotRentExempt,
+
+    /// An instruction tried to initialize an account that was already initialized
+    AccountAlreadyInitialized,
+
+    /// An instruction modified an account that transactions may only read, e.g. the recent
+    /// hashes account
+    ReadonlyAccountModified,
 }
 
 impl std::fmt::Display for ProgramError {
@@ -0,0 +1,76 @@
+//! The `recent_hashes` module describes an account, maintained by the bank, holding the
+//! ids of the most recent ticks. Programs read it to check that an instruction refers to
+//! real chain state, see `VoteInstruction::NewVoteWithRecentHash`.
+
+use crate::hash::Hash;
+use crate::pubkey::Pubkey;
+use bincode::{deserialize, serialize};
+use std::collections::VecDeque;
+
+pub const RECENT_HASHES_ID: [u8; 32] = [
+    133, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0,
+];
+
+pub fn check_id(id: &Pubkey) -> bool {
+    id.as_ref() == RECENT_HASHES_ID
+}
+
+pub fn id() -> Pubkey {
+    Pubkey::new(&RECENT_HASHES_ID)
+}
+
+// Number of tick ids kept in the account
+pub const MAX_RECENT_HASHES: usize = 128;
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct RecentHashes {
+    hashes: VecDeque<Hash>,
+}
+
+impl RecentHashes {
+    /// Adds the id of a new tick, forgetting the oldest one once MAX_RECENT_HASHES are kept
+    pub fn push(&mut self, hash: Hash) {
+        if self.hashes.len() == MAX_RECENT_HASHES {
+            self.hashes.pop_front();
+        }
+        self.hashes.push_back(hash);
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.hashes.contains(hash)
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        serialize(self).unwrap()
+    }
+
+    pub fn deserialize(input: &[u8]) -> bincode::Result<Self> {
+        deserialize(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::hash;
+
+    #[test]
+    fn test_recent_hashes() {
+        let mut recent_hashes = RecentHashes::default();
+        let hashes: Vec<_> = (0..=MAX_RECENT_HASHES)
+            .map(|i| hash(&serialize(&i).unwrap()))
+            .collect();
+        for hash in &hashes {
+            recent_hashes.push(*hash);
+        }
+        assert!(!recent_hashes.contains(&hashes[0]));
+        assert!(hashes[1..].iter().all(|hash| recent_hashes.contains(hash)));
+
+        let serialized = recent_hashes.serialize();
+        assert_eq!(
+            RecentHashes::deserialize(&serialized).unwrap(),
+            recent_hashes
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! The `rent` module describes the balance an account must hold to be exempt from being
+//! reaped for the storage its userdata occupies.
+
+/// Bytes of account userdata that a single token pays for
+pub const BYTES_PER_TOKEN: u64 = 4096;
+
+/// Returns the minimum balance an account with `userdata_len` bytes of userdata must hold to
+/// be rent-exempt. Every account needs at least one token, however small its userdata.
+pub fn rent_exempt_minimum(userdata_len: usize) -> u64 {
+    let userdata_len = userdata_len as u64;
+    let tokens = userdata_len / BYTES_PER_TOKEN + (userdata_len % BYTES_PER_TOKEN != 0) as u64;
+    tokens.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rent_exempt_minimum() {
+        assert_eq!(rent_exempt_minimum(0), 1);
+        assert_eq!(rent_exempt_minimum(1), 1);
+        assert_eq!(rent_exempt_minimum(BYTES_PER_TOKEN as usize), 1);
+        assert_eq!(rent_exempt_minimum(BYTES_PER_TOKEN as usize + 1), 2);
+    }
+}
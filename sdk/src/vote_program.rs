@@ -1,8 +1,12 @@
 //! Vote program
 //! Receive and processes votes from validators
 
+use crate::hash::Hash;
 use crate::native_program::ProgramError;
 use crate::pubkey::Pubkey;
+use crate::signature::Signature;
+use crate::transaction::{Instruction, Transaction};
+use crate::vote_transaction::VoteTransaction;
 use bincode::{deserialize, serialize};
 use byteorder::{ByteOrder, LittleEndian};
 use std::collections::VecDeque;
@@ -17,6 +21,31 @@ pub fn check_id(program_id: &Pubkey) -> bool {
     program_id.as_ref() == VOTE_PROGRAM_ID
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VoteError {
+    /// The vote state couldn't be read from an account's userdata, and why
+    StateDeserializeFailed(String),
+}
+
+impl std::fmt::Display for VoteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            VoteError::StateDeserializeFailed(reason) => {
+                write!(f, "failed to deserialize vote state: {}", reason)
+            }
+        }
+    }
+}
+impl std::error::Error for VoteError {}
+
+impl From<VoteError> for ProgramError {
+    fn from(err: VoteError) -> Self {
+        match err {
+            VoteError::StateDeserializeFailed(_) => ProgramError::InvalidUserdata,
+        }
+    }
+}
+
 pub fn id() -> Pubkey {
     Pubkey::new(&VOTE_PROGRAM_ID)
 }
@@ -24,11 +53,29 @@ pub fn id() -> Pubkey {
 // Maximum number of votes to keep around
 pub const MAX_VOTE_HISTORY: usize = 32;
 
-#[derive(Serialize, Default, Deserialize, Debug, PartialEq, Eq, Clone)]
+// Maximum size of the validator info blob
+pub const MAX_INFO_SIZE: usize = 128;
+
+// Maximum number of stake accounts that can delegate to a single vote account
+pub const MAX_DELEGATES: usize = 8;
+
+// Maximum commission, a percentage of rewards
+pub const MAX_COMMISSION: u32 = 100;
+
+// Maximum number of votes in a single NewVotes instruction, bounding the work it can cause
+pub const MAX_VOTES_PER_BATCH: usize = 8;
+
+/// Votes are equal if they are for the same bank hash at the same tick_height. They are
+/// ordered by tick_height, and votes at the same tick_height by bank hash, which only serves
+/// to make sorting deterministic.
+#[derive(Serialize, Default, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
 pub struct Vote {
     // TODO: add signature of the state here as well
+    // The ordering derived above depends on tick_height being declared first
     /// A vote for height tick_height
     pub tick_height: u64,
+    /// The id of the bank state being voted on at tick_height
+    pub bank_hash: Hash,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -40,52 +87,592 @@ pub enum VoteInstruction {
     /// identified by keys[0] for voting
     RegisterAccount,
     NewVote(Vote),
+    /// Attach an opaque, human readable info blob of at most MAX_INFO_SIZE bytes to a
+    /// "vote account"
+    /// * Transaction::keys[0] - the validator id
+    /// * Transaction::keys[1] - the "vote account" registered by the validator
+    SetInfo(Vec<u8>),
+    /// Same as RegisterAccount, but also seeds the vote history of the new "vote account"
+    /// with an activation vote at the given tick height
+    /// * Transaction::keys[0] - the validator id
+    /// * Transaction::keys[1] - the new "vote account" to be associated with the validator
+    /// identified by keys[0] for voting
+    RegisterAccountWithActivation(u64),
+    /// Delegate the balance of a stake account to a "vote account", with the consent of the
+    /// validator registered to it
+    /// * Transaction::keys[0] - the stake account, whose balance is delegated
+    /// * Transaction::keys[1] - the "vote account" receiving the delegation
+    /// * Transaction::keys[2] - the validator id, which must be a signer
+    DelegateStake,
+    /// Write a `VoteStateSummary` of a "vote account" into the userdata of another account
+    /// * Transaction::keys[0] - the "vote account" to query
+    /// * Transaction::keys[1] - the account receiving the summary, which must be a signer
+    GetState,
+    /// Set the percentage, at most MAX_COMMISSION, of rewards the validator keeps
+    /// * Transaction::keys[0] - the validator id
+    /// * Transaction::keys[1] - the "vote account" registered by the validator
+    SetCommission(u32),
+    /// Same as NewVote, but only accepted if the given hash is one of the ids of the most
+    /// recent ticks, tying the vote to real chain state
+    /// * Transaction::keys[0] - the "vote account"
+    /// * Transaction::keys[1] - the recent hashes account, see `recent_hashes::id()`
+    NewVoteWithRecentHash(Vote, Hash),
+    /// Same as NewVote, but for a batch of at most MAX_VOTES_PER_BATCH votes, applied in order
+    /// * Transaction::keys[0] - the "vote account"
+    NewVotes(Vec<Vote>),
+    /// Close a "vote account" that no stake is delegated to, zeroing its userdata and moving
+    /// all of its tokens to another account
+    /// * Transaction::keys[0] - the validator id
+    /// * Transaction::keys[1] - the "vote account" registered by the validator
+    /// * Transaction::keys[2] - the account receiving the tokens
+    CloseAccount,
+    /// Merge a "vote account" into another registered to the same validator, see
+    /// `VoteProgram::merge`. The source account's userdata is zeroed and its tokens are
+    /// moved to the destination.
+    /// * Transaction::keys[0] - the validator id
+    /// * Transaction::keys[1] - the source "vote account", which must be a signer
+    /// * Transaction::keys[2] - the destination "vote account"
+    Merge,
+    /// Withdraw the delegation of a stake account from a "vote account", see DelegateStake
+    /// * Transaction::keys[0] - the stake account delegated to keys[1]
+    /// * Transaction::keys[1] - the "vote account"
+    UndelegateStake,
+    /// Flag a "vote account" as slashed, given a vote it signed for a different bank hash
+    /// than a vote at the same tick_height in its vote history, see
+    /// `VoteProgram::conflicting_vote`. An account can only be slashed once.
+    /// * Transaction::keys[0] - the account reporting the conflict
+    /// * Transaction::keys[1] - the "vote account" that cast the conflicting votes
+    Slash(SignedVote),
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+/// A vote as cast by `VoteTransaction::vote_new`, with just enough of the transaction that
+/// carried it to check its signature, so that a VoteInstruction::Slash carrying it still fits
+/// in a packet
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct SignedVote {
+    pub vote: Vote,
+    pub last_id: Hash,
+    pub fee: u64,
+    pub signature: Signature,
+}
+
+impl SignedVote {
+    /// Returns the vote in `tx`, or None if `tx` isn't a single vote as built by
+    /// `VoteTransaction::vote_new`
+    pub fn from_transaction(tx: &Transaction) -> Option<Self> {
+        let (vote_id, signed_vote) = match (tx.get_votes().as_slice(), tx.signatures.as_slice()) {
+            ([(vote_id, vote, _)], [signature]) => (
+                *vote_id,
+                SignedVote {
+                    vote: vote.clone(),
+                    last_id: tx.last_id,
+                    fee: tx.fee,
+                    signature: *signature,
+                },
+            ),
+            _ => return None,
+        };
+        if signed_vote.transaction(&vote_id) == *tx {
+            Some(signed_vote)
+        } else {
+            None
+        }
+    }
+
+    /// Rebuilds the transaction `vote_id` cast this vote in
+    pub fn transaction(&self, vote_id: &Pubkey) -> Transaction {
+        let instruction = VoteInstruction::NewVote(self.vote.clone());
+        Transaction {
+            signatures: vec![self.signature],
+            account_keys: vec![*vote_id],
+            last_id: self.last_id,
+            fee: self.fee,
+            program_ids: vec![id()],
+            instructions: vec![Instruction::new(0, &instruction, vec![0])],
+        }
+    }
+
+    /// Returns true if this vote was signed by `vote_id`
+    pub fn verify(&self, vote_id: &Pubkey) -> bool {
+        self.transaction(vote_id).verify_signature()
+    }
+}
+
+/// A compact summary of a "vote account", see VoteInstruction::GetState
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct VoteStateSummary {
+    pub node_id: Pubkey,
+    pub last_vote_tick_height: Option<u64>,
+    pub credits: u64,
+}
+
+/// A view of a "vote account" for RPC clients, which stays the same when the layout of
+/// `VoteProgram` changes, see `VoteProgram::summary`
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq, Clone)]
+pub struct VoteAccountInfo {
+    pub node_id: Pubkey,
+    /// The key allowed to vote on behalf of the account, or None if votes must be signed by
+    /// the "vote account" itself, which is currently always the case
+    pub authorized_voter: Option<Pubkey>,
+    pub commission: u32,
+    pub credits: u64,
+    pub last_vote_tick: Option<u64>,
+    pub root_tick: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq, Clone)]
 pub struct VoteProgram {
+    /// Vote history, oldest first; serialization preserves this order
     pub votes: VecDeque<Vote>,
     pub node_id: Pubkey,
+    /// The first pair of votes seen for the same tick_height but with different
+    /// bank hashes, kept as evidence for slashing
+    pub conflicting_votes: Option<(Vote, Vote)>,
+    /// Validator info, see VoteInstruction::SetInfo
+    info: Vec<u8>,
+    /// Stake accounts delegated to this "vote account", see VoteInstruction::DelegateStake
+    pub delegates: Vec<Pubkey>,
+    /// The highest tick_height of the votes aged out of `votes`, which this account can no
+    /// longer revisit
+    pub root_tick_height: Option<u64>,
+    /// Credits earned by this account, one per accepted vote. Saturates at u64::MAX
+    /// rather than wrapping around.
+    pub credits: u64,
+    /// Percentage of rewards kept by the validator, see VoteInstruction::SetCommission
+    pub commission: u32,
+    /// The value of `credits` when rewards were last paid out to this account
+    pub credits_at_last_reward: u64,
+    /// Set once this account has been slashed for conflicting votes, see
+    /// VoteInstruction::Slash
+    pub slashed: bool,
+    /// The tick_height of the activation vote seeded by VoteInstruction::RegisterAccount,
+    /// while that vote is still in `votes`. It only stands in for a missing first vote, so it
+    /// never conflicts with a real one.
+    pub activation_tick_height: Option<u64>,
+}
+
+/// The layout of `VoteProgram` before `activation_tick_height` was added
+#[derive(Deserialize)]
+struct VoteProgramV1 {
+    votes: VecDeque<Vote>,
+    node_id: Pubkey,
+    conflicting_votes: Option<(Vote, Vote)>,
+    info: Vec<u8>,
+    delegates: Vec<Pubkey>,
+    root_tick_height: Option<u64>,
+    credits: u64,
+    commission: u32,
+    credits_at_last_reward: u64,
+    slashed: bool,
+}
+
+/// Vote states in a layout without `activation_tick_height` didn't record which vote was the
+/// activation vote. Being pushed first, it is the oldest vote until trimmed, and it was the only
+/// vote those states ever held with a default bank hash.
+fn legacy_activation_tick_height(votes: &VecDeque<Vote>) -> Option<u64> {
+    votes
+        .front()
+        .filter(|vote| vote.bank_hash == Hash::default())
+        .map(|vote| vote.tick_height)
+}
+
+impl From<VoteProgramV1> for VoteProgram {
+    fn from(vote_state: VoteProgramV1) -> Self {
+        VoteProgram {
+            activation_tick_height: legacy_activation_tick_height(&vote_state.votes),
+            votes: vote_state.votes,
+            node_id: vote_state.node_id,
+            conflicting_votes: vote_state.conflicting_votes,
+            info: vote_state.info,
+            delegates: vote_state.delegates,
+            root_tick_height: vote_state.root_tick_height,
+            credits: vote_state.credits,
+            commission: vote_state.commission,
+            credits_at_last_reward: vote_state.credits_at_last_reward,
+            slashed: vote_state.slashed,
+        }
+    }
+}
+
+/// The layout of `VoteProgram` before `slashed` was added. Vote states still in it are read
+/// as not slashed, and are written back in the current layout by the next update.
+#[derive(Deserialize)]
+struct VoteProgramV0 {
+    votes: VecDeque<Vote>,
+    node_id: Pubkey,
+    conflicting_votes: Option<(Vote, Vote)>,
+    info: Vec<u8>,
+    delegates: Vec<Pubkey>,
+    root_tick_height: Option<u64>,
+    credits: u64,
+    commission: u32,
+    credits_at_last_reward: u64,
+}
+
+impl From<VoteProgramV0> for VoteProgram {
+    fn from(vote_state: VoteProgramV0) -> Self {
+        VoteProgram {
+            activation_tick_height: legacy_activation_tick_height(&vote_state.votes),
+            votes: vote_state.votes,
+            node_id: vote_state.node_id,
+            conflicting_votes: vote_state.conflicting_votes,
+            info: vote_state.info,
+            delegates: vote_state.delegates,
+            root_tick_height: vote_state.root_tick_height,
+            credits: vote_state.credits,
+            commission: vote_state.commission,
+            credits_at_last_reward: vote_state.credits_at_last_reward,
+            slashed: false,
+        }
+    }
+}
+
+/// Reads a vote state in the current layout, or failing that in one of the older layouts.
+/// Each layout is at least one byte longer than the one before, so it never reads an older state.
+fn deserialize_any_layout(input: &[u8]) -> Result<VoteProgram, VoteError> {
+    deserialize(input)
+        .or_else(|err| {
+            deserialize::<VoteProgramV1>(input)
+                .map(VoteProgram::from)
+                .or_else(|_| deserialize::<VoteProgramV0>(input).map(VoteProgram::from))
+                .map_err(|_| err)
+        })
+        .map_err(|err| VoteError::StateDeserializeFailed(err.to_string()))
+}
+
+/// First byte of a vote state written by `VoteProgram::serialize_compressed`, right after
+/// the length prefix. An uncompressed vote state starts with the low byte of its vote count
+/// instead, which never exceeds MAX_VOTE_HISTORY, so the two can't be confused.
+const COMPRESSED_VOTE_STATE_VERSION: u8 = 0xff;
+
+/// Appends `value` as a LEB128 varint
+fn write_varint(output: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+/// Reads a LEB128 varint from the front of `input`, advancing it past the varint
+fn read_varint(input: &mut &[u8]) -> Option<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let (byte, rest) = input.split_first()?;
+        *input = rest;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
 }
 
 pub fn get_max_size() -> usize {
     // Upper limit on the size of the Vote State. Equal to
     // sizeof(VoteProgram) + MAX_VOTE_HISTORY * sizeof(Vote) +
-    // 32 (the size of the Pubkey) + 2 (2 bytes for the size)
+    // MAX_INFO_SIZE + MAX_DELEGATES * sizeof(Pubkey) + 32 (the size of the Pubkey) +
+    // 2 (2 bytes for the size).
+    // sizeof(VoteProgram) already covers the inline conflicting votes.
     mem::size_of::<VoteProgram>()
         + MAX_VOTE_HISTORY * mem::size_of::<Vote>()
+        + MAX_INFO_SIZE
+        + MAX_DELEGATES * mem::size_of::<Pubkey>()
         + mem::size_of::<Pubkey>()
         + mem::size_of::<u16>()
 }
 
 impl VoteProgram {
-    pub fn deserialize(input: &[u8]) -> Result<VoteProgram, ProgramError> {
-        let len = LittleEndian::read_u16(&input[0..2]) as usize;
+    pub fn new(node_id: Pubkey) -> Self {
+        VoteProgram {
+            votes: VecDeque::new(),
+            node_id,
+            conflicting_votes: None,
+            info: vec![],
+            delegates: vec![],
+            root_tick_height: None,
+            credits: 0,
+            commission: 0,
+            credits_at_last_reward: 0,
+            slashed: false,
+            activation_tick_height: None,
+        }
+    }
+
+    /// Seeds the vote history with an activation vote at `tick_height`, so the account's first
+    /// real vote has a predecessor
+    pub fn push_activation_vote(&mut self, tick_height: u64) {
+        self.activation_tick_height = Some(tick_height);
+        self.votes.extend(self.activation_vote());
+    }
+
+    /// Drops the activation vote from the vote history, if it's still there
+    pub fn remove_activation_vote(&mut self) {
+        if let Some(activation_vote) = self.activation_vote() {
+            self.votes.retain(|vote| *vote != activation_vote);
+        }
+        self.activation_tick_height = None;
+    }
+
+    fn activation_vote(&self) -> Option<Vote> {
+        self.activation_tick_height.map(|tick_height| Vote {
+            tick_height,
+            bank_hash: Hash::default(),
+        })
+    }
+
+    pub fn info(&self) -> &[u8] {
+        &self.info
+    }
+
+    pub fn set_info(&mut self, info: Vec<u8>) -> Result<(), ProgramError> {
+        if info.len() > MAX_INFO_SIZE {
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.info = info;
+        Ok(())
+    }
+
+    pub fn set_commission(&mut self, commission: u32) -> Result<(), ProgramError> {
+        if commission > MAX_COMMISSION {
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.commission = commission;
+        Ok(())
+    }
+
+    /// Credits earned since rewards were last paid out to this account
+    pub fn credits_since_last_reward(&self) -> u64 {
+        self.credits.saturating_sub(self.credits_at_last_reward)
+    }
+
+    pub fn add_delegate(&mut self, delegate: Pubkey) -> Result<(), ProgramError> {
+        if self.delegates.contains(&delegate) {
+            return Ok(());
+        }
+        if self.delegates.len() == MAX_DELEGATES {
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.delegates.push(delegate);
+        Ok(())
+    }
+
+    pub fn remove_delegate(&mut self, delegate: &Pubkey) -> Result<(), ProgramError> {
+        match self.delegates.iter().position(|key| key == delegate) {
+            Some(index) => {
+                self.delegates.remove(index);
+                Ok(())
+            }
+            None => Err(ProgramError::InvalidArgument),
+        }
+    }
 
-        if len == 0 || input.len() < len + 2 {
-            Err(ProgramError::InvalidUserdata)
+    /// Merges `source` into this account: the vote histories are combined in order, keeping
+    /// the most recent MAX_VOTE_HISTORY votes and rooting the rest, and credits and delegates
+    /// are added up. The merged account is slashed if either was. Both accounts must be
+    /// registered to the same validator.
+    pub fn merge(&mut self, mut source: VoteProgram) -> Result<(), ProgramError> {
+        if source.node_id != self.node_id {
+            return Err(ProgramError::InvalidArgument);
+        }
+        // Only one activation vote is tracked, and this account's comes first
+        if self.activation_tick_height.is_some() {
+            source.remove_activation_vote();
         } else {
-            deserialize(&input[2..=len + 1]).map_err(|_| ProgramError::InvalidUserdata)
+            self.activation_tick_height = source.activation_tick_height;
+        }
+        for delegate in source.delegates {
+            self.add_delegate(delegate)?;
+        }
+
+        let mut votes: Vec<_> = self.votes.drain(..).chain(source.votes).collect();
+        votes.sort();
+        votes.dedup();
+        let trimmed = votes.len().saturating_sub(MAX_VOTE_HISTORY);
+        let root_tick_height = std::cmp::max(self.root_tick_height, source.root_tick_height);
+        self.root_tick_height = std::cmp::max(
+            root_tick_height,
+            votes[..trimmed].last().map(|vote| vote.tick_height),
+        );
+        self.votes = votes.into_iter().skip(trimmed).collect();
+        if let Some(activation_vote) = self.activation_vote() {
+            // The activation vote is trimmed like any other, and superseded by a real vote
+            // from the other account at its tick_height
+            let votes_at_activation = self
+                .votes
+                .iter()
+                .filter(|vote| vote.tick_height == activation_vote.tick_height)
+                .count();
+            if !self.votes.contains(&activation_vote) || votes_at_activation > 1 {
+                self.remove_activation_vote();
+            }
+        }
+
+        self.credits = self.credits.saturating_add(source.credits);
+        self.credits_at_last_reward = self
+            .credits_at_last_reward
+            .saturating_add(source.credits_at_last_reward);
+        if self.conflicting_votes.is_none() {
+            self.conflicting_votes = source.conflicting_votes;
+        }
+        self.slashed |= source.slashed;
+        Ok(())
+    }
+
+    pub fn summary(&self) -> VoteAccountInfo {
+        VoteAccountInfo {
+            node_id: self.node_id,
+            authorized_voter: None,
+            commission: self.commission,
+            credits: self.credits,
+            last_vote_tick: self.votes.back().map(|vote| vote.tick_height),
+            root_tick: self.root_tick_height,
+        }
+    }
+
+    /// Returns true if this account has voted twice at the same tick_height
+    /// with different bank hashes
+    pub fn is_conflicted(&self) -> bool {
+        self.conflicting_votes.is_some()
+    }
+
+    /// Returns the vote in the vote history at `vote.tick_height` with a different bank
+    /// hash, if any. The activation vote never conflicts.
+    pub fn conflicting_vote(&self, vote: &Vote) -> Option<&Vote> {
+        if self.activation_tick_height == Some(vote.tick_height) {
+            return None;
+        }
+        self.votes
+            .iter()
+            .find(|prev| prev.tick_height == vote.tick_height && *prev != vote)
+    }
+
+    /// Returns true if a previous vote at `vote.tick_height` has a different bank hash.
+    /// The first such pair is recorded in `conflicting_votes` as evidence.
+    pub fn check_conflict(&mut self, vote: &Vote) -> bool {
+        let conflict = self.conflicting_vote(vote).cloned();
+        match conflict {
+            Some(prev) => {
+                if self.conflicting_votes.is_none() {
+                    self.conflicting_votes = Some((prev, vote.clone()));
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reads a vote state written by `serialize` or `serialize_compressed`. The vote history
+    /// comes back with room for MAX_VOTE_HISTORY votes, so the votes a program adds to it
+    /// before storing the state again don't reallocate it.
+    pub fn deserialize(input: &[u8]) -> Result<VoteProgram, VoteError> {
+        if input.len() < 2 {
+            return Err(VoteError::StateDeserializeFailed(format!(
+                "userdata of {} bytes is too short for the length prefix",
+                input.len()
+            )));
+        }
+        let len = LittleEndian::read_u16(&input[0..2]) as usize;
+
+        if len == 0 {
+            return Err(VoteError::StateDeserializeFailed(
+                "vote state is empty".to_string(),
+            ));
+        } else if input.len() < len + 2 {
+            return Err(VoteError::StateDeserializeFailed(format!(
+                "vote state of {} bytes is truncated to {} bytes",
+                len,
+                input.len() - 2
+            )));
         }
+
+        let mut vote_state = if input[2] == COMPRESSED_VOTE_STATE_VERSION {
+            Self::deserialize_compressed(&input[3..=len + 1])?
+        } else {
+            deserialize_any_layout(&input[2..=len + 1])?
+        };
+        let reserved = MAX_VOTE_HISTORY.saturating_sub(vote_state.votes.len());
+        vote_state.votes.reserve(reserved);
+        Ok(vote_state)
     }
 
     pub fn serialize(self: &VoteProgram, output: &mut [u8]) -> Result<(), ProgramError> {
         let self_serialized = serialize(self).unwrap();
+        Self::write_with_len(&self_serialized, output)
+    }
+
+    /// Same as `serialize`, but with the vote history compressed: the tick heights, which
+    /// mostly ascend in small steps, are written as varint deltas from the previous vote.
+    /// `deserialize` reads either format.
+    pub fn serialize_compressed(&self, output: &mut [u8]) -> Result<(), ProgramError> {
+        let mut rest = self.clone();
+        let votes = mem::replace(&mut rest.votes, VecDeque::new());
+
+        let mut compressed = vec![COMPRESSED_VOTE_STATE_VERSION];
+        write_varint(&mut compressed, votes.len() as u64);
+        let mut prev_tick_height = 0u64;
+        for vote in &votes {
+            // Votes may be out of order, zigzag encode the delta so small steps back stay small
+            let delta = vote.tick_height.wrapping_sub(prev_tick_height) as i64;
+            write_varint(&mut compressed, ((delta << 1) ^ (delta >> 63)) as u64);
+            compressed.extend_from_slice(vote.bank_hash.as_ref());
+            prev_tick_height = vote.tick_height;
+        }
+        compressed.extend_from_slice(&serialize(&rest).unwrap());
+        Self::write_with_len(&compressed, output)
+    }
 
-        if output.len() + 2 < self_serialized.len() {
+    fn write_with_len(serialized: &[u8], output: &mut [u8]) -> Result<(), ProgramError> {
+        if output.len() < serialized.len() + 2 {
             return Err(ProgramError::UserdataTooSmall);
         }
 
-        let serialized_len = self_serialized.len() as u16;
+        let serialized_len = serialized.len() as u16;
         LittleEndian::write_u16(&mut output[0..2], serialized_len);
-        output[2..=serialized_len as usize + 1].clone_from_slice(&self_serialized);
+        output[2..=serialized_len as usize + 1].clone_from_slice(serialized);
         Ok(())
     }
+
+    fn deserialize_compressed(mut input: &[u8]) -> Result<VoteProgram, VoteError> {
+        let truncated =
+            || VoteError::StateDeserializeFailed("compressed votes are truncated".to_string());
+        let num_votes = read_varint(&mut input).ok_or_else(truncated)?;
+        if num_votes > MAX_VOTE_HISTORY as u64 {
+            return Err(VoteError::StateDeserializeFailed(format!(
+                "{} compressed votes exceed the vote history",
+                num_votes
+            )));
+        }
+
+        let mut votes = VecDeque::with_capacity(num_votes as usize);
+        let mut prev_tick_height = 0u64;
+        for _ in 0..num_votes {
+            let zigzag = read_varint(&mut input).ok_or_else(truncated)?;
+            let delta = (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+            if input.len() < mem::size_of::<Hash>() {
+                return Err(truncated());
+            }
+            let (bank_hash, rest) = input.split_at(mem::size_of::<Hash>());
+            input = rest;
+            let tick_height = prev_tick_height.wrapping_add(delta as u64);
+            votes.push_back(Vote {
+                tick_height,
+                bank_hash: Hash::new(bank_hash),
+            });
+            prev_tick_height = tick_height;
+        }
+
+        let mut vote_state: VoteProgram = deserialize_any_layout(input)?;
+        vote_state.votes = votes;
+        Ok(vote_state)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hash::hash;
+    use crate::signature::{Keypair, KeypairUtil};
 
     #[test]
     fn test_serde() {
@@ -95,4 +682,395 @@ mod tests {
         vote_program.serialize(&mut buffer).unwrap();
         assert_eq!(VoteProgram::deserialize(&buffer).unwrap(), vote_program);
     }
+
+    #[test]
+    fn test_deserialize_reserves_history() {
+        let mut buffer = vec![0; get_max_size()];
+        VoteProgram::new(Keypair::new().pubkey())
+            .serialize(&mut buffer)
+            .unwrap();
+        let mut vote_program = VoteProgram::deserialize(&buffer).unwrap();
+        let capacity = vote_program.votes.capacity();
+        assert!(capacity >= MAX_VOTE_HISTORY);
+
+        for tick_height in 0..MAX_VOTE_HISTORY as u64 {
+            vote_program.votes.push_back(Vote {
+                tick_height,
+                bank_hash: Hash::default(),
+            });
+        }
+        assert_eq!(vote_program.votes.capacity(), capacity);
+    }
+
+    #[test]
+    fn test_serde_compressed() {
+        let mut vote_program = VoteProgram::new(Keypair::new().pubkey());
+        vote_program.credits = 7;
+        vote_program.root_tick_height = Some(3);
+        let mut seed = hash(b"compressed");
+        vote_program.votes = (4..4 + MAX_VOTE_HISTORY as u64)
+            .map(|tick_height| {
+                seed = hash(seed.as_ref());
+                Vote {
+                    tick_height,
+                    bank_hash: seed,
+                }
+            })
+            .collect();
+
+        let mut uncompressed = vec![0; get_max_size()];
+        vote_program.serialize(&mut uncompressed).unwrap();
+        let mut compressed = vec![0; get_max_size()];
+        vote_program.serialize_compressed(&mut compressed).unwrap();
+
+        // Every tick height but the first shrinks from 8 bytes to 1
+        let uncompressed_len = LittleEndian::read_u16(&uncompressed[0..2]) as usize;
+        let compressed_len = LittleEndian::read_u16(&compressed[0..2]) as usize;
+        assert!(compressed_len + 7 * (MAX_VOTE_HISTORY - 1) <= uncompressed_len);
+
+        assert_eq!(VoteProgram::deserialize(&compressed).unwrap(), vote_program);
+        assert_eq!(
+            VoteProgram::deserialize(&uncompressed).unwrap(),
+            vote_program
+        );
+    }
+
+    #[test]
+    fn test_serde_compressed_out_of_order() {
+        let mut vote_program = VoteProgram::default();
+        for tick_height in &[5, 2, std::u64::MAX, 0, 1 << 40] {
+            vote_program.votes.push_back(Vote {
+                tick_height: *tick_height,
+                bank_hash: hash(&serialize(tick_height).unwrap()),
+            });
+        }
+        let mut buffer = vec![0; get_max_size()];
+        vote_program.serialize_compressed(&mut buffer).unwrap();
+        assert_eq!(VoteProgram::deserialize(&buffer).unwrap(), vote_program);
+
+        // A truncated history is rejected
+        let len = LittleEndian::read_u16(&buffer[0..2]);
+        LittleEndian::write_u16(&mut buffer[0..2], len.min(20));
+        assert!(VoteProgram::deserialize(&buffer).is_err());
+    }
+
+    #[test]
+    fn test_serde_preserves_vote_order() {
+        // Pseudo-random histories, derived from a hash chain so the test is reproducible
+        let mut seed = hash(b"vote order");
+        for _ in 0..100 {
+            seed = hash(seed.as_ref());
+            let num_votes = seed.as_ref()[0] as usize % (2 * MAX_VOTE_HISTORY + 1);
+
+            // Age votes out as NewVote does, so the deque's ring buffer wraps around
+            let mut vote_program = VoteProgram::default();
+            for i in 0..num_votes {
+                if vote_program.votes.len() == MAX_VOTE_HISTORY {
+                    vote_program.votes.pop_front();
+                }
+                let bank_hash = hash(&[seed.as_ref(), &serialize(&i).unwrap()].concat());
+                vote_program.votes.push_back(Vote {
+                    tick_height: u64::from(bank_hash.as_ref()[0]),
+                    bank_hash,
+                });
+            }
+
+            let mut buffer: Vec<u8> = vec![0; get_max_size()];
+            vote_program.serialize(&mut buffer).unwrap();
+            let deserialized = VoteProgram::deserialize(&buffer).unwrap();
+            assert!(deserialized.votes.iter().eq(vote_program.votes.iter()));
+        }
+    }
+
+    #[test]
+    fn test_vote_ordering() {
+        let vote = |tick_height, bank_hash| Vote {
+            tick_height,
+            bank_hash,
+        };
+        let low_hash = Hash::new(&[0; 32]);
+        let high_hash = Hash::new(&[1; 32]);
+
+        assert_eq!(vote(1, low_hash), vote(1, low_hash));
+        assert_ne!(vote(1, low_hash), vote(1, high_hash));
+        assert_ne!(vote(1, low_hash), vote(2, low_hash));
+
+        // tick_height takes precedence over the bank hash
+        assert!(vote(1, high_hash) < vote(2, low_hash));
+        assert!(vote(1, low_hash) < vote(1, high_hash));
+
+        let mut votes = vec![vote(2, low_hash), vote(1, high_hash), vote(1, low_hash)];
+        votes.sort();
+        assert_eq!(
+            votes,
+            vec![vote(1, low_hash), vote(1, high_hash), vote(2, low_hash)]
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let node_id = Keypair::new().pubkey();
+        let mut vote_program = VoteProgram::new(node_id);
+        assert_eq!(
+            vote_program.summary(),
+            VoteAccountInfo {
+                node_id,
+                ..VoteAccountInfo::default()
+            }
+        );
+
+        for tick_height in 1..=3 {
+            vote_program.votes.push_back(Vote {
+                tick_height,
+                bank_hash: Hash::default(),
+            });
+        }
+        vote_program.root_tick_height = Some(1);
+        vote_program.credits = 3;
+        vote_program.set_commission(20).unwrap();
+        assert_eq!(
+            vote_program.summary(),
+            VoteAccountInfo {
+                node_id,
+                authorized_voter: None,
+                commission: 20,
+                credits: 3,
+                last_vote_tick: Some(3),
+                root_tick: Some(1),
+            }
+        );
+    }
+
+    #[test]
+    fn test_serialized_size() {
+        // Existing "vote accounts" hold state in this layout. If a change to VoteProgram
+        // legitimately alters it, update these sizes along with a migration of those accounts.
+        const DEFAULT_SERIALIZED_SIZE: usize = 80;
+        const FULL_HISTORY_SERIALIZED_SIZE: usize = 1360;
+
+        let mut vote_program = VoteProgram::default();
+        assert_eq!(
+            serialize(&vote_program).unwrap().len(),
+            DEFAULT_SERIALIZED_SIZE
+        );
+
+        vote_program.votes = (0..MAX_VOTE_HISTORY).map(|_| Vote::default()).collect();
+        assert_eq!(
+            serialize(&vote_program).unwrap().len(),
+            FULL_HISTORY_SERIALIZED_SIZE
+        );
+    }
+
+    #[test]
+    fn test_deserialize_legacy_layout() {
+        let mut vote_program = VoteProgram::new(Keypair::new().pubkey());
+        vote_program.votes.push_back(Vote {
+            tick_height: 1,
+            bank_hash: hash(b"legacy"),
+        });
+        vote_program.slashed = true;
+
+        let legacy_buffer = |trailing_bytes: usize| {
+            let mut legacy = serialize(&vote_program).unwrap();
+            legacy.truncate(legacy.len() - trailing_bytes);
+            let mut buffer = vec![0; 2];
+            LittleEndian::write_u16(&mut buffer[0..2], legacy.len() as u16);
+            buffer.extend_from_slice(&legacy);
+            buffer
+        };
+
+        // A state written before `activation_tick_height` was added lacks its trailing byte,
+        // and one written before `slashed` was added lacks the byte before that too
+        let before_activation = legacy_buffer(1);
+        let before_slashed = legacy_buffer(2);
+        assert_eq!(
+            VoteProgram::deserialize(&before_activation).unwrap(),
+            vote_program
+        );
+        vote_program.slashed = false;
+        assert_eq!(
+            VoteProgram::deserialize(&before_slashed).unwrap(),
+            vote_program
+        );
+    }
+
+    #[test]
+    fn test_deserialize_legacy_activation_vote() {
+        let mut vote_program = VoteProgram::new(Keypair::new().pubkey());
+        vote_program.push_activation_vote(5);
+
+        let mut legacy = serialize(&vote_program).unwrap();
+        legacy.truncate(legacy.len() - serialize(&Some(5u64)).unwrap().len());
+        let mut buffer = vec![0; 2];
+        LittleEndian::write_u16(&mut buffer[0..2], legacy.len() as u16);
+        buffer.extend_from_slice(&legacy);
+        assert_eq!(VoteProgram::deserialize(&buffer).unwrap(), vote_program);
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        let mut buffer: Vec<u8> = vec![0; get_max_size()];
+        let mut vote_program = VoteProgram::default();
+        vote_program.votes = (0..MAX_VOTE_HISTORY).map(|_| Vote::default()).collect();
+        vote_program.serialize(&mut buffer).unwrap();
+
+        let assert_reason = |input: &[u8], expected: &str| match VoteProgram::deserialize(input) {
+            Err(VoteError::StateDeserializeFailed(reason)) => {
+                assert!(reason.contains(expected), "{}", reason)
+            }
+            result => panic!("unexpected result {:?}", result),
+        };
+
+        assert_reason(&[], "too short");
+        assert_reason(&[0; 8], "empty");
+        assert_reason(&buffer[..10], "truncated to 8 bytes");
+
+        // A length prefix cutting the vote state short
+        let mut short_prefix = buffer.clone();
+        LittleEndian::write_u16(&mut short_prefix[0..2], 4);
+        assert_reason(&short_prefix, "io error");
+    }
+
+    #[test]
+    fn test_serde_with_conflict() {
+        let mut buffer: Vec<u8> = vec![0; get_max_size()];
+        let mut vote_program = VoteProgram::default();
+        vote_program.votes = (0..MAX_VOTE_HISTORY).map(|_| Vote::default()).collect();
+        vote_program.conflicting_votes = Some((Vote::default(), Vote::default()));
+        vote_program.set_info(vec![0; MAX_INFO_SIZE]).unwrap();
+        for _ in 0..MAX_DELEGATES {
+            vote_program.add_delegate(Keypair::new().pubkey()).unwrap();
+        }
+        vote_program.serialize(&mut buffer).unwrap();
+        assert_eq!(VoteProgram::deserialize(&buffer).unwrap(), vote_program);
+    }
+
+    #[test]
+    fn test_set_info() {
+        let mut vote_program = VoteProgram::default();
+        assert!(vote_program.info().is_empty());
+        vote_program.set_info(b"validator".to_vec()).unwrap();
+        assert_eq!(vote_program.info(), b"validator");
+        assert_eq!(
+            vote_program.set_info(vec![0; MAX_INFO_SIZE + 1]),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(vote_program.info(), b"validator");
+    }
+
+    #[test]
+    fn test_add_delegate() {
+        let mut vote_program = VoteProgram::default();
+        let delegate = Keypair::new().pubkey();
+        vote_program.add_delegate(delegate).unwrap();
+        vote_program.add_delegate(delegate).unwrap();
+        assert_eq!(vote_program.delegates, vec![delegate]);
+
+        for _ in 1..MAX_DELEGATES {
+            vote_program.add_delegate(Keypair::new().pubkey()).unwrap();
+        }
+        assert_eq!(
+            vote_program.add_delegate(Keypair::new().pubkey()),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(vote_program.delegates.len(), MAX_DELEGATES);
+    }
+
+    #[test]
+    fn test_remove_delegate() {
+        let mut vote_program = VoteProgram::default();
+        let delegates: Vec<_> = (0..3).map(|_| Keypair::new().pubkey()).collect();
+        for delegate in &delegates {
+            vote_program.add_delegate(*delegate).unwrap();
+        }
+
+        vote_program.remove_delegate(&delegates[1]).unwrap();
+        assert_eq!(vote_program.delegates, vec![delegates[0], delegates[2]]);
+        assert_eq!(
+            vote_program.remove_delegate(&delegates[1]),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(vote_program.delegates, vec![delegates[0], delegates[2]]);
+    }
+
+    #[test]
+    fn test_check_conflict() {
+        let mut vote_program = VoteProgram::default();
+        let vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::new(&[1; 32]),
+        };
+        assert!(!vote_program.check_conflict(&vote));
+        vote_program.votes.push_back(vote.clone());
+
+        // Voting again for the same state is not a conflict
+        assert!(!vote_program.check_conflict(&vote));
+        assert!(!vote_program.is_conflicted());
+
+        let conflicting_vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::new(&[2; 32]),
+        };
+        assert!(vote_program.check_conflict(&conflicting_vote));
+        assert!(vote_program.is_conflicted());
+        assert_eq!(
+            vote_program.conflicting_votes,
+            Some((vote, conflicting_vote))
+        );
+    }
+
+    #[test]
+    fn test_check_conflict_activation_vote() {
+        let mut vote_program = VoteProgram::default();
+        vote_program.push_activation_vote(1);
+        let vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::new(&[1; 32]),
+        };
+        assert!(!vote_program.check_conflict(&vote));
+        assert!(!vote_program.is_conflicted());
+
+        // Once it's gone, a vote with a default bank hash is like any other
+        vote_program.remove_activation_vote();
+        assert!(vote_program.votes.is_empty());
+        vote_program.votes.push_back(vote.clone());
+        let default_hash_vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::default(),
+        };
+        assert!(vote_program.check_conflict(&default_hash_vote));
+        assert_eq!(
+            vote_program.conflicting_votes,
+            Some((vote, default_hash_vote))
+        );
+    }
+
+    #[test]
+    fn test_merge_activation_vote() {
+        let node_id = Keypair::new().pubkey();
+        let mut vote_program = VoteProgram::new(node_id);
+        vote_program.push_activation_vote(1);
+        let mut source = VoteProgram::new(node_id);
+        source.push_activation_vote(2);
+        let vote = Vote {
+            tick_height: 1,
+            bank_hash: Hash::new(&[1; 32]),
+        };
+        source.votes.push_back(vote.clone());
+
+        // The source's activation vote is dropped, and its real vote at tick 1 supersedes
+        // this account's
+        vote_program.merge(source).unwrap();
+        assert_eq!(vote_program.activation_tick_height, None);
+        assert_eq!(
+            vote_program.votes.into_iter().collect::<Vec<_>>(),
+            vec![vote]
+        );
+
+        let mut vote_program = VoteProgram::new(node_id);
+        let mut source = VoteProgram::new(node_id);
+        source.push_activation_vote(2);
+        vote_program.merge(source.clone()).unwrap();
+        assert_eq!(vote_program, source);
+    }
 }
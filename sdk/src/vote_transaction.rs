@@ -2,15 +2,23 @@
 
 use crate::hash::Hash;
 use crate::pubkey::Pubkey;
+use crate::recent_hashes;
 use crate::signature::Keypair;
 use crate::system_instruction::SystemInstruction;
 use crate::system_program;
 use crate::transaction::{Instruction, Transaction};
-use crate::vote_program::{self, Vote, VoteInstruction};
+use crate::vote_program::{self, SignedVote, Vote, VoteInstruction};
 use bincode::deserialize;
 
 pub trait VoteTransaction {
     fn vote_new(vote_account: &Keypair, vote: Vote, last_id: Hash, fee: u64) -> Self;
+    fn vote_new_with_recent_hash(
+        vote_account: &Keypair,
+        vote: Vote,
+        recent_hash: Hash,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self;
     fn vote_account_new(
         validator_id: &Keypair,
         vote_account_id: Pubkey,
@@ -18,6 +26,60 @@ pub trait VoteTransaction {
         num_tokens: u64,
         fee: u64,
     ) -> Self;
+    fn vote_set_info(
+        validator_id: &Keypair,
+        vote_account_id: Pubkey,
+        info: Vec<u8>,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self;
+    fn vote_delegate_stake(
+        stake_account: &Keypair,
+        validator_id: &Keypair,
+        vote_account_id: Pubkey,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self;
+    fn vote_undelegate_stake(
+        stake_account: &Keypair,
+        vote_account_id: Pubkey,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self;
+    fn vote_slash(
+        reporter: &Keypair,
+        vote_account_id: Pubkey,
+        vote: SignedVote,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self;
+    fn vote_get_state(
+        summary_account: &Keypair,
+        vote_account_id: Pubkey,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self;
+    fn vote_set_commission(
+        validator_id: &Keypair,
+        vote_account_id: Pubkey,
+        commission: u32,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self;
+    fn vote_close_account(
+        validator_id: &Keypair,
+        vote_account_id: Pubkey,
+        to: Pubkey,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self;
+    fn vote_merge(
+        validator_id: &Keypair,
+        source: &Keypair,
+        destination: Pubkey,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self;
 
     fn get_votes(&self) -> Vec<(Pubkey, Vote, Hash)>;
 }
@@ -35,6 +97,24 @@ impl VoteTransaction for Transaction {
         )
     }
 
+    fn vote_new_with_recent_hash(
+        vote_account: &Keypair,
+        vote: Vote,
+        recent_hash: Hash,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self {
+        let instruction = VoteInstruction::NewVoteWithRecentHash(vote, recent_hash);
+        Transaction::new(
+            vote_account,
+            &[recent_hashes::id()],
+            vote_program::id(),
+            &instruction,
+            last_id,
+            fee,
+        )
+    }
+
     fn vote_account_new(
         validator_id: &Keypair,
         vote_account_id: Pubkey,
@@ -63,13 +143,166 @@ impl VoteTransaction for Transaction {
         )
     }
 
+    fn vote_set_info(
+        validator_id: &Keypair,
+        vote_account_id: Pubkey,
+        info: Vec<u8>,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self {
+        let instruction = VoteInstruction::SetInfo(info);
+        Transaction::new(
+            validator_id,
+            &[vote_account_id],
+            vote_program::id(),
+            &instruction,
+            last_id,
+            fee,
+        )
+    }
+
+    fn vote_set_commission(
+        validator_id: &Keypair,
+        vote_account_id: Pubkey,
+        commission: u32,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self {
+        let instruction = VoteInstruction::SetCommission(commission);
+        Transaction::new(
+            validator_id,
+            &[vote_account_id],
+            vote_program::id(),
+            &instruction,
+            last_id,
+            fee,
+        )
+    }
+
+    fn vote_delegate_stake(
+        stake_account: &Keypair,
+        validator_id: &Keypair,
+        vote_account_id: Pubkey,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self {
+        // Both signers come first in account_keys, but the instruction expects the
+        // "vote account" second
+        Transaction::new_with_instructions(
+            &[stake_account, validator_id],
+            &[vote_account_id],
+            last_id,
+            fee,
+            vec![vote_program::id()],
+            vec![Instruction::new(
+                0,
+                &VoteInstruction::DelegateStake,
+                vec![0, 2, 1],
+            )],
+        )
+    }
+
+    fn vote_undelegate_stake(
+        stake_account: &Keypair,
+        vote_account_id: Pubkey,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self {
+        Transaction::new(
+            stake_account,
+            &[vote_account_id],
+            vote_program::id(),
+            &VoteInstruction::UndelegateStake,
+            last_id,
+            fee,
+        )
+    }
+
+    fn vote_slash(
+        reporter: &Keypair,
+        vote_account_id: Pubkey,
+        vote: SignedVote,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self {
+        Transaction::new(
+            reporter,
+            &[vote_account_id],
+            vote_program::id(),
+            &VoteInstruction::Slash(vote),
+            last_id,
+            fee,
+        )
+    }
+
+    fn vote_close_account(
+        validator_id: &Keypair,
+        vote_account_id: Pubkey,
+        to: Pubkey,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self {
+        Transaction::new(
+            validator_id,
+            &[vote_account_id, to],
+            vote_program::id(),
+            &VoteInstruction::CloseAccount,
+            last_id,
+            fee,
+        )
+    }
+
+    fn vote_merge(
+        validator_id: &Keypair,
+        source: &Keypair,
+        destination: Pubkey,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self {
+        Transaction::new_with_instructions(
+            &[validator_id, source],
+            &[destination],
+            last_id,
+            fee,
+            vec![vote_program::id()],
+            vec![Instruction::new(0, &VoteInstruction::Merge, vec![0, 1, 2])],
+        )
+    }
+
+    fn vote_get_state(
+        summary_account: &Keypair,
+        vote_account_id: Pubkey,
+        last_id: Hash,
+        fee: u64,
+    ) -> Self {
+        // The signing summary account is account_keys[0], but the instruction expects the
+        // "vote account" first
+        Transaction::new_with_instructions(
+            &[summary_account],
+            &[vote_account_id],
+            last_id,
+            fee,
+            vec![vote_program::id()],
+            vec![Instruction::new(0, &VoteInstruction::GetState, vec![1, 0])],
+        )
+    }
+
     fn get_votes(&self) -> Vec<(Pubkey, Vote, Hash)> {
         let mut votes = vec![];
         for i in 0..self.instructions.len() {
             let tx_program_id = self.program_id(i);
             if vote_program::check_id(&tx_program_id) {
-                if let Ok(Some(VoteInstruction::NewVote(vote))) = deserialize(&self.userdata(i)) {
-                    votes.push((self.account_keys[0], vote, self.last_id))
+                match deserialize(&self.userdata(i)) {
+                    Ok(Some(VoteInstruction::NewVote(vote)))
+                    | Ok(Some(VoteInstruction::NewVoteWithRecentHash(vote, _))) => {
+                        votes.push((self.account_keys[0], vote, self.last_id))
+                    }
+                    Ok(Some(VoteInstruction::NewVotes(batch))) => votes.extend(
+                        batch
+                            .into_iter()
+                            .map(|vote| (self.account_keys[0], vote, self.last_id)),
+                    ),
+                    _ => (),
                 }
             }
         }
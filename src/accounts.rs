@@ -9,6 +9,7 @@ use log::Level;
 use solana_sdk::account::Account;
 use solana_sdk::hash::{hash, Hash};
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::recent_hashes;
 use solana_sdk::transaction::Transaction;
 use std::collections::BTreeMap;
 use std::collections::VecDeque;
@@ -95,7 +96,8 @@ impl AccountsDB {
         None
     }
     pub fn store(&mut self, pubkey: &Pubkey, account: &Account) {
-        if account.tokens == 0 {
+        // The recent hashes account holds no tokens, see `Bank::register_tick`
+        if account.tokens == 0 && !recent_hashes::check_id(pubkey) {
             if self.checkpoints.is_empty() {
                 // purge if balance is 0 and no checkpoints
                 self.accounts.remove(pubkey);
@@ -121,18 +123,26 @@ impl AccountsDB {
             let tx = &txs[i];
             let acc = racc.as_ref().unwrap();
             for (key, account) in tx.account_keys.iter().zip(acc.iter()) {
+                // Transactions that modify the recent hashes account are rejected, so it's
+                // unchanged here, and storing it back could undo an update by the bank in the
+                // meantime
+                if recent_hashes::check_id(key) {
+                    continue;
+                }
                 self.store(key, account);
             }
         }
     }
-    fn load_account(
+    /// Checks that `tx` can pay its fee and that its last_id is recent enough, then copies
+    /// the accounts it references, with the fee taken from the first. Doesn't reserve the
+    /// signature.
+    fn check_and_load_account(
         &self,
         tx: &Transaction,
-        last_ids: &mut StatusDeque<Result<()>>,
+        last_ids: &StatusDeque<Result<()>>,
         max_age: usize,
         error_counters: &mut ErrorCounters,
     ) -> Result<Vec<Account>> {
-        // Copy all the accounts
         if tx.signatures.is_empty() && tx.fee != 0 {
             Err(BankError::MissingSignatureForFee)
         } else if self.load(&tx.account_keys[0]).is_none() {
@@ -141,27 +151,11 @@ impl AccountsDB {
         } else if self.load(&tx.account_keys[0]).unwrap().tokens < tx.fee {
             error_counters.insufficient_funds += 1;
             Err(BankError::InsufficientFundsForFee)
+        } else if !last_ids.check_entry_id_age(tx.last_id, max_age) {
+            error_counters.last_id_not_found += 1;
+            Err(BankError::LastIdNotFound)
         } else {
-            if !last_ids.check_entry_id_age(tx.last_id, max_age) {
-                error_counters.last_id_not_found += 1;
-                return Err(BankError::LastIdNotFound);
-            }
-
-            // There is no way to predict what program will execute without an error
-            // If a fee can pay for execution then the program will be scheduled
-            last_ids
-                .reserve_signature_with_last_id(&tx.last_id, &tx.signatures[0])
-                .map_err(|err| match err {
-                    StatusDequeError::LastIdNotFound => {
-                        error_counters.reserve_last_id += 1;
-                        BankError::LastIdNotFound
-                    }
-                    StatusDequeError::DuplicateSignature => {
-                        error_counters.duplicate_signature += 1;
-                        BankError::DuplicateSignature
-                    }
-                })?;
-
+            // Copy all the accounts
             let mut called_accounts: Vec<Account> = tx
                 .account_keys
                 .iter()
@@ -171,6 +165,31 @@ impl AccountsDB {
             Ok(called_accounts)
         }
     }
+    fn load_account(
+        &self,
+        tx: &Transaction,
+        last_ids: &mut StatusDeque<Result<()>>,
+        max_age: usize,
+        error_counters: &mut ErrorCounters,
+    ) -> Result<Vec<Account>> {
+        let called_accounts = self.check_and_load_account(tx, last_ids, max_age, error_counters)?;
+
+        // There is no way to predict what program will execute without an error
+        // If a fee can pay for execution then the program will be scheduled
+        last_ids
+            .reserve_signature_with_last_id(&tx.last_id, &tx.signatures[0])
+            .map_err(|err| match err {
+                StatusDequeError::LastIdNotFound => {
+                    error_counters.reserve_last_id += 1;
+                    BankError::LastIdNotFound
+                }
+                StatusDequeError::DuplicateSignature => {
+                    error_counters.duplicate_signature += 1;
+                    BankError::DuplicateSignature
+                }
+            })?;
+        Ok(called_accounts)
+    }
     fn load_accounts(
         &self,
         txs: &[Transaction],
@@ -213,14 +232,16 @@ impl Accounts {
         keys: &[Pubkey],
         error_counters: &mut ErrorCounters,
     ) -> Result<()> {
-        // Copy all the accounts
-        for k in keys {
+        // Copy all the accounts. The recent hashes account is only read by transactions, so
+        // it needn't be locked
+        let keys = || keys.iter().filter(|k| !recent_hashes::check_id(k));
+        for k in keys() {
             if account_locks.contains(k) {
                 error_counters.account_in_use += 1;
                 return Err(BankError::AccountInUse);
             }
         }
-        for k in keys {
+        for k in keys() {
             account_locks.insert(*k);
         }
         Ok(())
@@ -285,6 +306,23 @@ impl Accounts {
         )
     }
 
+    /// Same as `load_accounts` for a single transaction, except that its signature isn't
+    /// reserved, e.g. to simulate it without committing anything
+    pub fn check_and_load_account(
+        &self,
+        tx: &Transaction,
+        last_ids: &StatusDeque<Result<()>>,
+        max_age: usize,
+        error_counters: &mut ErrorCounters,
+    ) -> Result<Vec<Account>> {
+        self.accounts_db.read().unwrap().check_and_load_account(
+            tx,
+            last_ids,
+            max_age,
+            error_counters,
+        )
+    }
+
     pub fn store_accounts(
         &self,
         txs: &[Transaction],
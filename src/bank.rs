@@ -5,6 +5,7 @@
 
 use crate::accounts::{Accounts, ErrorCounters};
 use crate::checkpoint::Checkpoint;
+use crate::compute_leader_finality_service::{supermajority_threshold, StakeSource};
 use crate::counter::Counter;
 use crate::entry::Entry;
 use crate::jsonrpc_macros::pubsub::Sink;
@@ -14,10 +15,11 @@ use crate::mint::Mint;
 use crate::poh_recorder::PohRecorder;
 use crate::rpc::RpcSignatureStatus;
 use crate::runtime::{self, RuntimeError};
+use crate::stake::Stake;
 use crate::status_deque::{Status, StatusDeque, MAX_ENTRY_IDS};
 use crate::storage_stage::StorageState;
 use bincode::deserialize;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use itertools::Itertools;
 use log::Level;
 use rayon::prelude::*;
@@ -29,21 +31,25 @@ use solana_sdk::hash::Hash;
 use solana_sdk::native_program::ProgramError;
 use solana_sdk::payment_plan::Payment;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::recent_hashes::{self, RecentHashes};
 use solana_sdk::signature::Keypair;
 use solana_sdk::signature::Signature;
 use solana_sdk::storage_program;
 use solana_sdk::system_instruction::SystemInstruction;
 use solana_sdk::system_program;
 use solana_sdk::system_transaction::SystemTransaction;
-use solana_sdk::timing::duration_as_us;
+use solana_sdk::timing::{self, duration_as_us};
 use solana_sdk::token_program;
 use solana_sdk::transaction::Transaction;
-use solana_sdk::vote_program;
+use solana_sdk::vote_program::{self, VoteProgram};
+use solana_sdk::vote_transaction::VoteTransaction;
 use std;
+use std::collections::VecDeque;
 use std::result;
+use std::slice;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::prelude::Future;
 
 /// Reasons a transaction might be rejected.
@@ -81,12 +87,94 @@ pub enum BankError {
 
     /// Transaction has a fee but has no signature present
     MissingSignatureForFee,
+
+    /// The validator already registered the maximum number of vote accounts
+    TooManyVoteAccounts,
+
+    /// The stake account is already delegated to another vote account
+    StakeAlreadyDelegated,
 }
 
 pub type Result<T> = result::Result<T, BankError>;
 
+/// Reasons no finality time can be reported, see `Bank::finality_if_voted`
+#[derive(Debug, PartialEq, Eq)]
+pub enum FinalityError {
+    NoValidSupermajority,
+    /// A supermajority confirmed the tick at this height, but its timestamp is no longer (or
+    /// was never) recorded, so there is no finality time to report
+    MissingTimestamp(u64),
+}
+
+impl FinalityError {
+    /// A stable numeric code for the error, e.g. for RPC responses. Codes must never be
+    /// reused or renumbered.
+    pub fn code(&self) -> i32 {
+        match self {
+            FinalityError::NoValidSupermajority => 1,
+            FinalityError::MissingTimestamp(_) => 2,
+        }
+    }
+}
+
+impl From<FinalityError> for i32 {
+    fn from(err: FinalityError) -> Self {
+        err.code()
+    }
+}
+
+impl std::fmt::Display for FinalityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FinalityError::NoValidSupermajority => {
+                write!(
+                    f,
+                    "no tick has been confirmed by a supermajority of the stake"
+                )
+            }
+            FinalityError::MissingTimestamp(tick_height) => write!(
+                f,
+                "tick {} was confirmed by a supermajority of the stake but has no timestamp",
+                tick_height
+            ),
+        }
+    }
+}
+impl std::error::Error for FinalityError {}
+
 pub const VERIFY_BLOCK_SIZE: usize = 16;
 
+/// Number of finality time updates kept for `Bank::average_finality`
+pub const MAX_FINALITY_HISTORY: usize = 1024;
+
+/// Number of vote accounts a validator may register, see `Bank::set_max_vote_accounts`
+pub const DEFAULT_MAX_VOTE_ACCOUNTS: usize = 1;
+
+/// Percentage of a validator's stake slashed for conflicting votes, see `Bank::vote_stake`
+pub const DEFAULT_SLASH_PERCENTAGE: u64 = 10;
+
+/// Stake of the nodes with a registered vote account, split by whether their votes
+/// currently count towards finality, see `Bank::stake_summary`. Slashed nodes carry no
+/// stake, see `Bank::vote_stakes`.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct StakeSummary {
+    /// Stake of every node with a registered vote account
+    pub total: Stake,
+    /// Stake of the nodes whose latest vote is for a tick the bank still tracks
+    pub active: Stake,
+    /// Stake of the nodes that haven't voted, or whose latest vote is stale or for a tick
+    /// the bank hasn't registered yet
+    pub delinquent: Stake,
+}
+
+/// Outcome of `Bank::simulate_transaction`
+#[derive(Debug, PartialEq)]
+pub struct SimResult {
+    /// The accounts the transaction would modify, in `account_keys` order, with the state
+    /// they would be left in
+    pub accounts: Vec<(Pubkey, Account)>,
+}
+
 /// Manager for the state of all accounts and programs after processing its entries.
 pub struct Bank {
     pub accounts: Accounts,
@@ -97,6 +185,12 @@ pub struct Bank {
     // The latest finality time for the network
     finality_time: AtomicUsize,
 
+    // Timestamp of the last update of `finality_time`
+    finality_timestamp: AtomicUsize,
+
+    // The most recent `(timestamp, finality_time)` updates, oldest first
+    finality_history: RwLock<VecDeque<(u64, usize)>>,
+
     // Mapping of account ids to Subscriber ids and sinks to notify on userdata update
     account_subscriptions: RwLock<HashMap<Pubkey, HashMap<Pubkey, Sink<Account>>>>,
 
@@ -108,6 +202,15 @@ pub struct Bank {
     pub leader_scheduler: Arc<RwLock<LeaderScheduler>>,
 
     pub storage_state: StorageState,
+
+    /// The id the vote program is loaded under, see `Mint::vote_program_id`
+    vote_program_id: Pubkey,
+
+    /// Percentage of a validator's stake slashed for conflicting votes
+    slash_percentage: u64,
+
+    /// Number of vote accounts a single validator may register
+    max_vote_accounts: usize,
 }
 
 impl Default for Bank {
@@ -116,10 +219,15 @@ impl Default for Bank {
             accounts: Accounts::default(),
             last_ids: RwLock::new(StatusDeque::default()),
             finality_time: AtomicUsize::new(std::usize::MAX),
+            finality_timestamp: AtomicUsize::new(timing::timestamp() as usize),
+            finality_history: RwLock::new(VecDeque::new()),
             account_subscriptions: RwLock::new(HashMap::new()),
             signature_subscriptions: RwLock::new(HashMap::new()),
             leader_scheduler: Arc::new(RwLock::new(LeaderScheduler::default())),
             storage_state: StorageState::new(),
+            vote_program_id: vote_program::id(),
+            slash_percentage: DEFAULT_SLASH_PERCENTAGE,
+            max_vote_accounts: DEFAULT_MAX_VOTE_ACCOUNTS,
         }
     }
 }
@@ -134,7 +242,15 @@ impl Bank {
 
     /// Create an Bank using a deposit.
     pub fn new_from_deposits(deposits: &[Payment]) -> Self {
-        let bank = Self::default();
+        Self::new_from_deposits_with_vote_program_id(deposits, vote_program::id())
+    }
+
+    fn new_from_deposits_with_vote_program_id(
+        deposits: &[Payment],
+        vote_program_id: Pubkey,
+    ) -> Self {
+        let mut bank = Self::default();
+        bank.vote_program_id = vote_program_id;
         for deposit in deposits {
             let mut account = Account::default();
             account.tokens += deposit.tokens;
@@ -192,7 +308,7 @@ impl Bank {
         } else {
             vec![mint_deposit]
         };
-        let bank = Self::new_from_deposits(&deposits);
+        let bank = Self::new_from_deposits_with_vote_program_id(&deposits, mint.vote_program_id);
         bank.register_tick(&mint.last_id());
         bank
     }
@@ -215,13 +331,13 @@ impl Bank {
         // Vote program
         let vote_program_account = Account {
             tokens: 1,
-            owner: vote_program::id(),
+            owner: self.vote_program_id,
             userdata: b"solana_vote_program".to_vec(),
             executable: true,
             loader: solana_native_loader::id(),
         };
         self.accounts
-            .store_slow(&vote_program::id(), &vote_program_account);
+            .store_slow(&self.vote_program_id, &vote_program_account);
 
         // Storage program
         let storage_program_account = Account {
@@ -315,25 +431,411 @@ impl Bank {
         last_ids.count_valid_ids(ids)
     }
 
+    /// Looks through a list of tick heights and stakes, and finds the latest
+    /// tick that has achieved finality, whether or not it has a recorded timestamp
+    pub fn get_finality_tick(
+        &self,
+        ticks_and_stakes: &mut [(u64, Stake)],
+        supermajority_stake: Stake,
+    ) -> Option<u64> {
+        let last_ids = self.last_ids.read().unwrap();
+        last_ids.get_finality_tick(ticks_and_stakes, supermajority_stake)
+    }
+
     /// Looks through a list of tick heights and stakes, and finds the latest
     /// tick that has achieved finality
     pub fn get_finality_timestamp(
         &self,
-        ticks_and_stakes: &mut [(u64, u64)],
-        supermajority_stake: u64,
+        ticks_and_stakes: &mut [(u64, Stake)],
+        supermajority_stake: Stake,
     ) -> Option<u64> {
         let last_ids = self.last_ids.read().unwrap();
         last_ids.get_finality_timestamp(ticks_and_stakes, supermajority_stake)
     }
 
+    /// Looks through a list of tick heights and stakes, and finds the deepest tick
+    /// confirmed by a supermajority
+    pub fn get_confirmed_tick(
+        &self,
+        ticks_and_stakes: &mut [(u64, Stake)],
+        supermajority_stake: Stake,
+    ) -> Option<u64> {
+        let last_ids = self.last_ids.read().unwrap();
+        last_ids.get_confirmed_tick(ticks_and_stakes, supermajority_stake)
+    }
+
+    /// Returns true if a vote for `tick_height` counts towards finality, see
+    /// `StatusDeque::is_recent_tick`
+    pub fn is_recent_vote(&self, tick_height: u64) -> bool {
+        self.last_ids.read().unwrap().is_recent_tick(tick_height)
+    }
+
+    /// Returns the timestamp recorded by `register_tick` for `tick_height`, if that tick
+    /// is still tracked by the bank
+    pub fn timestamp_for_tick(&self, tick_height: u64) -> Option<u64> {
+        let last_ids = self.last_ids.read().unwrap();
+        last_ids.tick_height_to_timestamp(tick_height)
+    }
+
+    /// The id the vote program is loaded under
+    pub fn vote_program_id(&self) -> Pubkey {
+        self.vote_program_id
+    }
+
+    /// Returns every vote account, excluding the vote program itself
+    pub fn vote_accounts(&self) -> Vec<(Pubkey, Account)> {
+        self.with_vote_accounts_locked(|vote_accounts| vote_accounts.to_vec())
+    }
+
+    /// Returns the accounts owned by the vote program that don't hold a vote state, e.g.
+    /// created for the vote program but never registered with `VoteInstruction::RegisterAccount`
+    pub fn uninitialized_vote_accounts(&self) -> Vec<Pubkey> {
+        self.with_vote_accounts_locked(|vote_accounts| {
+            vote_accounts
+                .iter()
+                .filter(|(_, account)| VoteProgram::deserialize(&account.userdata).is_err())
+                .map(|(pubkey, _)| *pubkey)
+                .collect()
+        })
+    }
+
+    /// Calls `f` with every vote account while holding the accounts read lock, so that no
+    /// transaction can modify a vote account until `f` returns
+    pub fn with_vote_accounts_locked<R>(&self, f: impl FnOnce(&[(Pubkey, Account)]) -> R) -> R {
+        let accounts = self.accounts.accounts_db.read().unwrap();
+        let vote_accounts: Vec<_> = accounts
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.owner == self.vote_program_id && !account.executable)
+            .map(|(pubkey, account)| (*pubkey, account.clone()))
+            .collect();
+        f(&vote_accounts)
+    }
+
+    /// Remove every vote account, so tests reusing a bank start from a clean set of voters
+    #[cfg(any(test, feature = "test"))]
+    pub fn clear_vote_accounts(&self) {
+        for (pubkey, _) in self.vote_accounts() {
+            self.accounts.store_slow(&pubkey, &Account::default());
+        }
+    }
+
+    /// Returns the latest vote tick_height, or None if it hasn't voted yet, of every node
+    /// with a registered vote account, skipping `exclude`. A single node could register
+    /// multiple vote accounts (see github issue 1654), so each node is only counted once,
+    /// at its most recent vote. Vote accounts that were never registered have no node to
+    /// attribute them to and are skipped, see `uninitialized_vote_accounts`.
+    pub fn latest_vote_ticks(&self, exclude: Option<Pubkey>) -> HashMap<Pubkey, Option<u64>> {
+        self.with_vote_accounts_locked(|vote_accounts| {
+            let mut latest_vote_ticks = HashMap::new();
+            for (_, account) in vote_accounts {
+                if let Ok(vote_state) = VoteProgram::deserialize(&account.userdata) {
+                    if exclude == Some(vote_state.node_id) {
+                        continue;
+                    }
+                    let tick_height = vote_state.votes.back().map(|vote| vote.tick_height);
+                    let latest = latest_vote_ticks.entry(vote_state.node_id).or_insert(None);
+                    *latest = std::cmp::max(*latest, tick_height);
+                }
+            }
+            latest_vote_ticks
+        })
+    }
+
+    /// Returns the tick_height of the leader's latest vote, or None if it hasn't voted. The
+    /// leader's votes are usually left out of finality, see `ComputeLeaderFinalityService`,
+    /// so this tells how far behind the cluster's frontier the leader itself is.
+    pub fn leader_self_vote(&self, leader_id: &Pubkey) -> Option<u64> {
+        self.latest_vote_ticks(None)
+            .get(leader_id)
+            .and_then(|tick_height| *tick_height)
+    }
+
+    /// Returns the root tick_height, or None if it has no root yet, of every node with a
+    /// registered vote account, skipping `exclude`. A node with multiple vote accounts is
+    /// counted once, at its highest root, see `latest_vote_ticks`.
+    pub fn root_ticks(&self, exclude: Option<Pubkey>) -> HashMap<Pubkey, Option<u64>> {
+        self.with_vote_accounts_locked(|vote_accounts| {
+            let mut root_ticks = HashMap::new();
+            for (_, account) in vote_accounts {
+                if let Ok(vote_state) = VoteProgram::deserialize(&account.userdata) {
+                    if exclude == Some(vote_state.node_id) {
+                        continue;
+                    }
+                    let root = root_ticks.entry(vote_state.node_id).or_insert(None);
+                    *root = std::cmp::max(*root, vote_state.root_tick_height);
+                }
+            }
+            root_ticks
+        })
+    }
+
+    /// Weighs each node in `vote_ticks` by the stake its votes carry towards finality, its
+    /// effective stake in the current epoch as reported by `stake_source`, keeping its vote
+    /// tick. Slashed nodes and nodes without effective stake count for nothing, so they are
+    /// left out. Everything that adds up voted stake collects it here, so that it agrees
+    /// with `ComputeLeaderFinalityService::get_last_supermajority_timestamp`.
+    pub fn vote_stakes(
+        &self,
+        vote_ticks: HashMap<Pubkey, Option<u64>>,
+        stake_source: &dyn StakeSource,
+    ) -> Vec<(Pubkey, Option<u64>, Stake)> {
+        let epoch = self.epoch();
+        let slashed_nodes = self.slashed_nodes();
+        vote_ticks
+            .into_iter()
+            .filter(|(node_id, _)| !slashed_nodes.contains(node_id))
+            .map(|(node_id, tick_height)| {
+                let stake = stake_source.effective_stake(&node_id, epoch);
+                (node_id, tick_height, stake)
+            })
+            .filter(|(_, _, stake)| *stake > Stake::default())
+            .collect()
+    }
+
+    /// Returns the `(tick_height, stake)` of the latest vote of every node that has voted,
+    /// skipping `exclude`, see `vote_stakes`
+    pub fn vote_tick_stakes(&self, exclude: Option<Pubkey>) -> Vec<(u64, Stake)> {
+        self.vote_stakes(self.latest_vote_ticks(exclude), self)
+            .into_iter()
+            .filter_map(|(_, tick_height, stake)| {
+                tick_height.map(|tick_height| (tick_height, stake))
+            })
+            .collect()
+    }
+
+    /// Splits the tick heights from the oldest to the newest latest vote into at most
+    /// `buckets` ranges of equal width, returning the first tick height of each range along
+    /// with the stake of the nodes whose latest vote falls in it, oldest range first. Shows how
+    /// spread out the validators are behind the frontier.
+    pub fn vote_tick_histogram(&self, buckets: usize) -> Vec<(u64, Stake)> {
+        let vote_tick_stakes = self.vote_tick_stakes(None);
+        let min_tick = vote_tick_stakes.iter().map(|(tick, _)| *tick).min();
+        let max_tick = vote_tick_stakes.iter().map(|(tick, _)| *tick).max();
+        let (min_tick, max_tick) = match (min_tick, max_tick) {
+            (Some(min_tick), Some(max_tick)) if buckets > 0 => (min_tick, max_tick),
+            _ => return vec![],
+        };
+
+        // The range holds `max_offset + 1` ticks, which doesn't fit in a u64 when the votes
+        // cover every tick height, so round the width and bucket count up from the offset.
+        let max_offset = max_tick - min_tick;
+        let width = match (max_offset / buckets as u64).checked_add(1) {
+            Some(width) => width,
+            None => {
+                // A single range covering every tick height
+                let mut total = Stake::default();
+                for (_, stake) in vote_tick_stakes {
+                    total += stake;
+                }
+                return vec![(min_tick, total)];
+            }
+        };
+        let num_buckets = max_offset / width + 1;
+        let mut histogram: Vec<_> = (0..num_buckets)
+            .map(|i| (min_tick + i * width, Stake::default()))
+            .collect();
+        for (tick_height, stake) in vote_tick_stakes {
+            histogram[((tick_height - min_tick) / width) as usize].1 += stake;
+        }
+        histogram
+    }
+
+    /// Returns the `(node_id, stake)` of every node whose vote history includes a vote at
+    /// `tick_height`, ordered by node_id, see `vote_stakes`. A node is listed once however
+    /// many of its vote accounts voted at `tick_height`. Votes that have aged out into the
+    /// root are not included.
+    pub fn voters_for_tick(&self, tick_height: u64) -> Vec<(Pubkey, Stake)> {
+        let vote_ticks: HashMap<_, _> = self.with_vote_accounts_locked(|vote_accounts| {
+            vote_accounts
+                .iter()
+                .filter_map(|(_, account)| VoteProgram::deserialize(&account.userdata).ok())
+                .filter(|vote_state| {
+                    vote_state
+                        .votes
+                        .iter()
+                        .any(|vote| vote.tick_height == tick_height)
+                })
+                .map(|vote_state| (vote_state.node_id, Some(tick_height)))
+                .collect()
+        });
+        let mut voters: Vec<_> = self
+            .vote_stakes(vote_ticks, self)
+            .into_iter()
+            .map(|(node_id, _, stake)| (node_id, stake))
+            .collect();
+        voters.sort();
+        voters
+    }
+
+    /// Returns the total stake of every node with a registered vote account, whether or
+    /// not it has voted, skipping `exclude`
+    pub fn vote_total_stake(&self, exclude: Option<Pubkey>) -> Stake {
+        self.latest_vote_ticks(exclude)
+            .keys()
+            .map(|node_id| self.get_stake(node_id))
+            .sum()
+    }
+
+    /// Returns the total stake of the nodes with a registered vote account, along with how
+    /// much of it is actively voting, see `vote_stakes`
+    pub fn stake_summary(&self) -> StakeSummary {
+        let mut summary = StakeSummary::default();
+        for (_, tick_height, stake) in self.vote_stakes(self.latest_vote_ticks(None), self) {
+            let active = tick_height.map_or(false, |tick_height| self.is_recent_vote(tick_height));
+            summary.total += stake;
+            if active {
+                summary.active += stake;
+            } else {
+                summary.delinquent += stake;
+            }
+        }
+        summary
+    }
+
+    /// Projects finality, in milliseconds, as if `node_id` had just voted at `tick`, without
+    /// modifying the bank. Like `ComputeLeaderFinalityService`, only nodes that have voted
+    /// count towards the total stake, weighed by `vote_stakes`, but no leader is excluded.
+    pub fn finality_if_voted(
+        &self,
+        node_id: &Pubkey,
+        tick: u64,
+    ) -> result::Result<u64, FinalityError> {
+        let mut latest_vote_ticks = self.latest_vote_ticks(None);
+        latest_vote_ticks.insert(*node_id, Some(tick));
+
+        let mut ticks_and_stakes: Vec<_> = self
+            .vote_stakes(latest_vote_ticks, self)
+            .into_iter()
+            .filter_map(|(_, tick_height, stake)| {
+                tick_height.map(|tick_height| (tick_height, stake))
+            })
+            .collect();
+        let super_majority_stake = supermajority_threshold(&ticks_and_stakes);
+
+        let finality_tick = self
+            .get_finality_tick(&mut ticks_and_stakes, super_majority_stake)
+            .ok_or(FinalityError::NoValidSupermajority)?;
+        let timestamp = self
+            .timestamp_for_tick(finality_tick)
+            .ok_or(FinalityError::MissingTimestamp(finality_tick))?;
+        Ok(timing::timestamp().saturating_sub(timestamp))
+    }
+
+    /// Returns how many ticks the latest vote of `node_id` is behind `finalized_tick`, the
+    /// frontier reported by `ComputeLeaderFinalityService::last_finalized_tick`, or zero if it
+    /// voted at or past it. None if `node_id` hasn't voted.
+    pub fn validator_lag(&self, node_id: &Pubkey, finalized_tick: u64) -> Option<u64> {
+        let latest_vote_tick = self
+            .latest_vote_ticks(None)
+            .get(node_id)
+            .and_then(|tick_height| *tick_height)?;
+        Some(finalized_tick.saturating_sub(latest_vote_tick))
+    }
+
+    /// Returns the number of votes, across all vote accounts, with a tick_height between
+    /// `start_tick` and `end_tick` inclusive. Only votes still in each account's vote history
+    /// are counted.
+    pub fn votes_in_range(&self, start_tick: u64, end_tick: u64) -> usize {
+        self.with_vote_accounts_locked(|vote_accounts| {
+            vote_accounts
+                .iter()
+                .filter_map(|(_, account)| VoteProgram::deserialize(&account.userdata).ok())
+                .map(|vote_state| {
+                    vote_state
+                        .votes
+                        .iter()
+                        .filter(|vote| {
+                            start_tick <= vote.tick_height && vote.tick_height <= end_tick
+                        })
+                        .count()
+                })
+                .sum()
+        })
+    }
+
+    /// Returns the highest tick rooted by at least 2/3 of the total stake of the nodes with
+    /// a registered vote account, or None if no such tick exists
+    pub fn cluster_root_tick(&self) -> Option<u64> {
+        let root_ticks = self.root_ticks(None);
+
+        let total_stake: Stake = root_ticks
+            .keys()
+            .map(|node_id| self.get_stake(node_id))
+            .sum();
+        let mut roots_and_stakes: Vec<_> = root_ticks
+            .into_iter()
+            .filter_map(|(node_id, root)| root.map(|root| (root, self.get_stake(&node_id))))
+            .collect();
+        // Highest root first, so the first root reaching 2/3 of the stake is the deepest one
+        roots_and_stakes.sort_by(|a, b| b.0.cmp(&a.0));
+        let mut rooted_stake = Stake::default();
+        for (root, stake) in roots_and_stakes {
+            rooted_stake += stake;
+            if total_stake > Stake::default() && rooted_stake * 3 >= total_stake * 2 {
+                return Some(root);
+            }
+        }
+        None
+    }
+
+    /// Timestamp every subsequently registered tick with the next value of a counter
+    /// starting after `start`, so tests get distinct timestamps without sleeping
+    #[cfg(any(test, feature = "test"))]
+    pub fn set_test_clock(&self, start: u64) {
+        self.last_ids.write().unwrap().set_test_clock(start);
+    }
+
+    /// Registers `n` ticks, each with an id hashed from the previous one and timestamped by
+    /// the test clock, so tests can move the tick height forward without rolling PoH or
+    /// sleeping. Starts the test clock from the wall clock unless it is already running.
+    #[cfg(any(test, feature = "test"))]
+    pub fn advance_ticks(&self, n: u64) {
+        self.last_ids
+            .write()
+            .unwrap()
+            .start_test_clock(timing::timestamp());
+        for _ in 0..n {
+            let last_id = solana_sdk::hash::hash(self.last_id().as_ref());
+            self.register_tick(&last_id);
+        }
+    }
+
     /// Tell the bank which Entry IDs exist on the ledger. This function
     /// assumes subsequent calls correspond to later entries, and will boot
     /// the oldest ones once its internal cache is full. Once boot, the
     /// bank will reject transactions using that `last_id`.
     pub fn register_tick(&self, last_id: &Hash) {
-        let mut last_ids = self.last_ids.write().unwrap();
-        inc_new_counter_info!("bank-register_tick-registered", 1);
-        last_ids.register_tick(last_id)
+        {
+            let mut last_ids = self.last_ids.write().unwrap();
+            inc_new_counter_info!("bank-register_tick-registered", 1);
+            last_ids.register_tick(last_id)
+        }
+        self.update_recent_hashes(last_id);
+    }
+
+    /// Same as `register_tick`, but timestamps the tick with `timestamp` rather than the time
+    /// it's registered at, so that a tick registered by a busy consumer still reports when
+    /// PoH produced it to `get_finality_timestamp`
+    pub fn register_tick_at(&self, last_id: &Hash, timestamp: u64) {
+        {
+            let mut last_ids = self.last_ids.write().unwrap();
+            inc_new_counter_info!("bank-register_tick-registered", 1);
+            last_ids.register_tick_at(last_id, timestamp)
+        }
+        self.update_recent_hashes(last_id);
+    }
+
+    /// Adds a tick id to the recent hashes account, see `recent_hashes`. The account is
+    /// created without tokens, since there is no one to take them from.
+    fn update_recent_hashes(&self, last_id: &Hash) {
+        let mut account = self
+            .get_account(&recent_hashes::id())
+            .unwrap_or_else(|| Account::new(0, 0, recent_hashes::id()));
+        let mut hashes = RecentHashes::deserialize(&account.userdata).unwrap_or_default();
+        hashes.push(*last_id);
+        account.userdata = hashes.serialize();
+        self.accounts.store_slow(&recent_hashes::id(), &account);
     }
 
     /// Process a Transaction. This is used for unit tests and simply calls the vector Bank::process_transactions method.
@@ -401,6 +903,9 @@ impl Bank {
         txs: &[Transaction],
         poh: &PohRecorder,
     ) -> Result<()> {
+        // Transactions the recorder won't accept are dropped before they are executed
+        let filtered_txs = poh.filter_transactions(txs);
+        let txs = &filtered_txs[..];
         let now = Instant::now();
         // Once accounts are locked, other threads cannot encode transactions that will modify the
         // same account state
@@ -490,18 +995,30 @@ impl Bank {
 
         let load_elapsed = now.elapsed();
         let now = Instant::now();
+        let mut vote_account_counts = self.vote_account_counts(txs);
+        let mut stake_delegations = self.stake_delegations(txs);
         let executed: Vec<Result<()>> = loaded_accounts
             .iter_mut()
             .zip(txs.iter())
             .map(|(acc, tx)| match acc {
                 Err(e) => Err(e.clone()),
                 Ok(ref mut accounts) => {
+                    self.check_vote_account_limit(tx, &vote_account_counts)?;
+                    let delegated = self.check_stake_delegations(tx, &stake_delegations)?;
                     let mut loaders = self.load_loaders(tx)?;
                     runtime::execute_transaction(tx, &mut loaders, accounts, tick_height).map_err(
                         |RuntimeError::ProgramError(index, err)| {
                             BankError::ProgramError(index, err)
                         },
-                    )
+                    )?;
+                    // Count the vote accounts this transaction registered towards the limit
+                    // of the rest of the batch, and hold the rest of the batch to the stake
+                    // delegations it left behind
+                    for node_id in self.registered_node_ids(tx) {
+                        *vote_account_counts.entry(node_id).or_insert(0) += 1;
+                    }
+                    stake_delegations.extend(delegated);
+                    Ok(())
                 }
             })
             .collect();
@@ -574,6 +1091,35 @@ impl Bank {
         executed
     }
 
+    /// Run a transaction against copies of the accounts it references and return the ones it
+    /// would modify, without committing anything or reserving its signature.
+    pub fn simulate_transaction(&self, tx: &Transaction) -> Result<SimResult> {
+        let mut accounts = self.accounts.check_and_load_account(
+            tx,
+            &self.last_ids.read().unwrap(),
+            MAX_ENTRY_IDS,
+            &mut ErrorCounters::default(),
+        )?;
+
+        let vote_account_counts = self.vote_account_counts(slice::from_ref(tx));
+        self.check_vote_account_limit(tx, &vote_account_counts)?;
+        let stake_delegations = self.stake_delegations(slice::from_ref(tx));
+        self.check_stake_delegations(tx, &stake_delegations)?;
+        let mut loaders = self.load_loaders(tx)?;
+        runtime::execute_transaction(tx, &mut loaders, &mut accounts, self.tick_height()).map_err(
+            |RuntimeError::ProgramError(index, err)| BankError::ProgramError(index, err),
+        )?;
+
+        let accounts = tx
+            .account_keys
+            .iter()
+            .zip(accounts.into_iter())
+            .filter(|(key, account)| self.get_account(key).as_ref() != Some(account))
+            .map(|(key, account)| (*key, account))
+            .collect();
+        Ok(SimResult { accounts })
+    }
+
     #[must_use]
     pub fn process_transactions(&self, txs: &[Transaction]) -> Vec<Result<()>> {
         let locked_accounts = self.lock_accounts(txs);
@@ -582,6 +1128,23 @@ impl Bank {
         results
     }
 
+    /// Process a batch of vote transactions. Votes for distinct vote accounts touch disjoint
+    /// state, so the batch is split up and executed in parallel.
+    #[must_use]
+    pub fn process_vote_transactions(&self, txs: &[Transaction]) -> Vec<Result<()>> {
+        let locked_accounts = self.lock_accounts(txs);
+        let chunk_size = std::cmp::max(1, txs.len() / rayon::current_num_threads());
+        let results: Vec<Result<()>> = txs
+            .par_chunks(chunk_size)
+            .zip(locked_accounts.par_chunks(chunk_size))
+            .flat_map(|(txs, locked_accounts)| {
+                self.execute_and_commit_transactions(txs, locked_accounts.to_vec(), MAX_ENTRY_IDS)
+            })
+            .collect();
+        self.unlock_accounts(txs, &results);
+        results
+    }
+
     pub fn process_entry(&self, entry: &Entry) -> Result<()> {
         if !entry.is_tick() {
             for result in self.process_transactions(&entry.transactions) {
@@ -829,10 +1392,210 @@ impl Bank {
             .unwrap_or(0)
     }
 
+    /// The current epoch, i.e. the number of `seed_rotation_interval`s of the leader
+    /// scheduler the bank's tick height has gone through
+    pub fn epoch(&self) -> u64 {
+        let seed_rotation_interval = self.leader_scheduler.read().unwrap().seed_rotation_interval;
+        self.tick_height() / std::cmp::max(1, seed_rotation_interval)
+    }
+
     /// TODO: Need to implement a real staking program to hold node stake.
     /// Right now this just gets the account balances. See github issue #1655.
-    pub fn get_stake(&self, pubkey: &Pubkey) -> u64 {
-        self.get_balance(pubkey)
+    pub fn get_stake(&self, pubkey: &Pubkey) -> Stake {
+        Stake(self.get_balance(pubkey))
+    }
+
+    /// Returns the stake backing a vote account: the sum of the balances of the stake
+    /// accounts delegated to it, or the stake of its validator if it has no delegates. Once
+    /// the account has been slashed, that stake is reduced by the slash percentage.
+    pub fn vote_stake(&self, vote_account: &Pubkey) -> Stake {
+        let vote_state = match self
+            .get_account(vote_account)
+            .filter(|account| account.owner == self.vote_program_id)
+            .and_then(|account| VoteProgram::deserialize(&account.userdata).ok())
+        {
+            Some(vote_state) => vote_state,
+            None => return Stake::default(),
+        };
+        let stake = if vote_state.delegates.is_empty() {
+            self.get_stake(&vote_state.node_id)
+        } else {
+            vote_state
+                .delegates
+                .iter()
+                .map(|delegate| self.get_stake(delegate))
+                .sum()
+        };
+        if vote_state.slashed {
+            let kept = u128::from(stake.0) * u128::from(100 - self.slash_percentage) / 100;
+            Stake(kept as u64)
+        } else {
+            stake
+        }
+    }
+
+    /// Returns the credits a vote account has earned since rewards were last paid out to it,
+    /// paired with the commission to split them with, or None if it isn't a vote account
+    pub fn vote_reward_base(&self, vote_account: &Pubkey) -> Option<(u64, u32)> {
+        self.get_account(vote_account)
+            .filter(|account| account.owner == self.vote_program_id)
+            .and_then(|account| VoteProgram::deserialize(&account.userdata).ok())
+            .map(|vote_state| {
+                (
+                    vote_state.credits_since_last_reward(),
+                    vote_state.commission,
+                )
+            })
+    }
+
+    pub fn set_max_vote_accounts(&mut self, max_vote_accounts: usize) {
+        self.max_vote_accounts = max_vote_accounts;
+    }
+
+    /// Returns the validator of every vote account `tx` registers, once per registration
+    fn registered_node_ids(&self, tx: &Transaction) -> Vec<Pubkey> {
+        (0..tx.instructions.len())
+            .filter(|index| *tx.program_id(*index) == self.vote_program_id)
+            .filter(|index| match deserialize(tx.userdata(*index)) {
+                Ok(vote_program::VoteInstruction::RegisterAccount)
+                | Ok(vote_program::VoteInstruction::RegisterAccountWithActivation(_)) => true,
+                _ => false,
+            })
+            .filter_map(|index| tx.key(index, 0).cloned())
+            .collect()
+    }
+
+    /// Counts the committed vote accounts of every validator `txs` register a vote account
+    /// for. The vote accounts are only scanned if some transaction registers one, and then
+    /// only once for the whole batch.
+    fn vote_account_counts(&self, txs: &[Transaction]) -> HashMap<Pubkey, usize> {
+        let mut vote_account_counts: HashMap<_, _> = txs
+            .iter()
+            .flat_map(|tx| self.registered_node_ids(tx))
+            .map(|node_id| (node_id, 0))
+            .collect();
+        if vote_account_counts.is_empty() {
+            return vote_account_counts;
+        }
+        self.with_vote_accounts_locked(|vote_accounts| {
+            for (_, account) in vote_accounts {
+                if let Ok(vote_state) = VoteProgram::deserialize(&account.userdata) {
+                    if let Some(count) = vote_account_counts.get_mut(&vote_state.node_id) {
+                        *count += 1;
+                    }
+                }
+            }
+        });
+        vote_account_counts
+    }
+
+    /// Rejects a transaction registering a vote account for a validator that already has
+    /// `max_vote_accounts` of them, so one validator can't clutter the accounts structure.
+    /// `vote_account_counts` holds the vote accounts of each validator registered before the
+    /// transaction, see `vote_account_counts`, and the transaction's own registrations count
+    /// towards the limit of the ones after them.
+    fn check_vote_account_limit(
+        &self,
+        tx: &Transaction,
+        vote_account_counts: &HashMap<Pubkey, usize>,
+    ) -> Result<()> {
+        let mut registered = HashMap::new();
+        for node_id in self.registered_node_ids(tx) {
+            let count = registered
+                .entry(node_id)
+                .or_insert_with(|| vote_account_counts.get(&node_id).cloned().unwrap_or(0));
+            if *count >= self.max_vote_accounts {
+                return Err(BankError::TooManyVoteAccounts);
+            }
+            *count += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns the stake account of every DelegateStake and UndelegateStake instruction in
+    /// `tx`, in order, paired with the vote account it delegates to, or None if it undelegates
+    fn stake_delegation_changes(&self, tx: &Transaction) -> Vec<(Pubkey, Option<Pubkey>)> {
+        (0..tx.instructions.len())
+            .filter(|index| *tx.program_id(*index) == self.vote_program_id)
+            .filter_map(|index| {
+                let stake_id = *tx.key(index, 0)?;
+                match deserialize(tx.userdata(index)) {
+                    Ok(vote_program::VoteInstruction::DelegateStake) => {
+                        Some((stake_id, Some(*tx.key(index, 1)?)))
+                    }
+                    Ok(vote_program::VoteInstruction::UndelegateStake) => Some((stake_id, None)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the committed delegation of every stake account `txs` delegate or undelegate,
+    /// or None for those that aren't delegated. As with `vote_account_counts`, the vote
+    /// accounts are only scanned if some transaction changes a delegation.
+    fn stake_delegations(&self, txs: &[Transaction]) -> HashMap<Pubkey, Option<Pubkey>> {
+        let mut stake_delegations: HashMap<_, _> = txs
+            .iter()
+            .flat_map(|tx| self.stake_delegation_changes(tx))
+            .map(|(stake_id, _)| (stake_id, None))
+            .collect();
+        if stake_delegations.is_empty() {
+            return stake_delegations;
+        }
+        self.with_vote_accounts_locked(|vote_accounts| {
+            for (vote_id, account) in vote_accounts {
+                if let Ok(vote_state) = VoteProgram::deserialize(&account.userdata) {
+                    for delegate in &vote_state.delegates {
+                        if let Some(delegation) = stake_delegations.get_mut(delegate) {
+                            *delegation = Some(*vote_id);
+                        }
+                    }
+                }
+            }
+        });
+        stake_delegations
+    }
+
+    /// Rejects a transaction delegating a stake account that is already delegated to another
+    /// vote account, so its balance only ever backs one vote account, see `vote_stake`.
+    /// `stake_delegations` holds the delegations before the transaction, see
+    /// `stake_delegations`. Returns the delegations the transaction leaves behind.
+    fn check_stake_delegations(
+        &self,
+        tx: &Transaction,
+        stake_delegations: &HashMap<Pubkey, Option<Pubkey>>,
+    ) -> Result<HashMap<Pubkey, Option<Pubkey>>> {
+        let mut delegated = HashMap::new();
+        for (stake_id, vote_id) in self.stake_delegation_changes(tx) {
+            let delegation = delegated
+                .entry(stake_id)
+                .or_insert_with(|| stake_delegations.get(&stake_id).cloned().unwrap_or(None));
+            match (*delegation, vote_id) {
+                (Some(current), Some(vote_id)) if current != vote_id => {
+                    return Err(BankError::StakeAlreadyDelegated);
+                }
+                _ => *delegation = vote_id,
+            }
+        }
+        Ok(delegated)
+    }
+
+    pub fn set_slash_percentage(&mut self, slash_percentage: u64) {
+        assert!(slash_percentage <= 100);
+        self.slash_percentage = slash_percentage;
+    }
+
+    /// Returns every validator with a vote account that has been slashed for conflicting
+    /// votes, whose votes no longer count towards finality, see `VoteInstruction::Slash`
+    pub fn slashed_nodes(&self) -> HashSet<Pubkey> {
+        self.with_vote_accounts_locked(|vote_accounts| {
+            vote_accounts
+                .iter()
+                .filter_map(|(_, account)| VoteProgram::deserialize(&account.userdata).ok())
+                .filter(|vote_state| vote_state.slashed)
+                .map(|vote_state| vote_state.node_id)
+                .collect()
+        })
     }
 
     pub fn get_account(&self, pubkey: &Pubkey) -> Option<Account> {
@@ -875,8 +1638,60 @@ impl Bank {
         self.finality_time.load(Ordering::Relaxed)
     }
 
+    /// Returns the finality time in milliseconds, or None if finality hasn't been computed
+    pub fn finality_ms(&self) -> Option<u64> {
+        match self.finality() {
+            std::usize::MAX => None,
+            finality => Some(finality as u64),
+        }
+    }
+
+    /// Sets the finality time in milliseconds, whether or not it improves on the current one,
+    /// and adds it to the history `average_finality` reads. Tests can call it directly to set
+    /// up an exact finality without running `ComputeLeaderFinalityService`.
     pub fn set_finality(&self, finality: usize) {
+        let now = self.last_ids.read().unwrap().now();
         self.finality_time.store(finality, Ordering::Relaxed);
+        self.finality_timestamp
+            .store(now as usize, Ordering::Relaxed);
+
+        let mut finality_history = self.finality_history.write().unwrap();
+        if finality_history.len() == MAX_FINALITY_HISTORY {
+            finality_history.pop_front();
+        }
+        finality_history.push_back((now, finality));
+    }
+
+    /// Returns the average of the finality times set within the last `window`, or None if
+    /// none were
+    pub fn average_finality(&self, window: Duration) -> Option<usize> {
+        let since = self
+            .last_ids
+            .read()
+            .unwrap()
+            .now()
+            .saturating_sub(timing::duration_as_ms(&window));
+        let finality_history = self.finality_history.read().unwrap();
+        let (sum, count) = finality_history
+            .iter()
+            .rev()
+            .take_while(|(timestamp, _)| *timestamp >= since)
+            .fold((0, 0), |(sum, count), (_, finality)| {
+                (sum + finality, count + 1)
+            });
+        if count == 0 {
+            None
+        } else {
+            Some(sum / count)
+        }
+    }
+
+    /// Returns the finality time along with how long ago it was last updated
+    pub fn finality_with_age(&self) -> (usize, Duration) {
+        let finality = self.finality_time.load(Ordering::Relaxed);
+        let updated = self.finality_timestamp.load(Ordering::Relaxed) as u64;
+        let age = self.last_ids.read().unwrap().now().saturating_sub(updated);
+        (finality, Duration::from_millis(age))
     }
 
     fn send_account_notifications(
@@ -995,10 +1810,12 @@ impl Bank {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::create_vote_account::create_vote_account;
     use crate::entry::next_entry;
     use crate::entry::Entry;
     use crate::jsonrpc_macros::pubsub::{Subscriber, SubscriptionId};
     use crate::ledger;
+    use crate::packet::PACKET_DATA_SIZE;
     use crate::signature::GenKeys;
     use crate::status_deque;
     use crate::status_deque::StatusDequeError;
@@ -1010,9 +1827,31 @@ mod tests {
     use solana_sdk::signature::KeypairUtil;
     use solana_sdk::system_transaction::SystemTransaction;
     use solana_sdk::transaction::Instruction;
+    use solana_sdk::vote_program::{SignedVote, Vote, VoteInstruction};
+    use solana_sdk::vote_transaction::VoteTransaction;
     use std;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::mpsc::channel;
     use tokio::prelude::{Async, Stream};
 
+    #[test]
+    fn test_finality_error_code() {
+        let err = FinalityError::NoValidSupermajority;
+        assert_eq!(err.code(), 1);
+        assert_eq!(
+            err.to_string(),
+            "no tick has been confirmed by a supermajority of the stake"
+        );
+        assert_eq!(i32::from(err), 1);
+
+        let err = FinalityError::MissingTimestamp(5);
+        assert_eq!(err.code(), 2);
+        assert_eq!(
+            err.to_string(),
+            "tick 5 was confirmed by a supermajority of the stake but has no timestamp"
+        );
+    }
+
     #[test]
     fn test_bank_new() {
         let mint = Mint::new(10_000);
@@ -1402,6 +2241,7 @@ mod tests {
             .unwrap();
         assert_eq!(bank0.hash_internal_state(), bank1.hash_internal_state());
     }
+
     #[test]
     fn test_finality() {
         let def_bank = Bank::default();
@@ -1409,6 +2249,1209 @@ mod tests {
         def_bank.set_finality(90);
         assert_eq!(def_bank.finality(), 90);
     }
+
+    #[test]
+    fn test_finality_with_age() {
+        let bank = Bank::default();
+        bank.set_finality(90);
+        std::thread::sleep(Duration::from_millis(50));
+        let (finality, age) = bank.finality_with_age();
+        assert_eq!(finality, 90);
+        assert!(age >= Duration::from_millis(50));
+        assert!(age < Duration::from_secs(5));
+
+        bank.set_finality(80);
+        let (finality, age) = bank.finality_with_age();
+        assert_eq!(finality, 80);
+        assert!(age < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_finality_ms() {
+        let bank = Bank::default();
+        assert_eq!(bank.finality(), std::usize::MAX);
+        assert_eq!(bank.finality_ms(), None);
+
+        bank.set_finality(100);
+        assert_eq!(bank.finality_ms(), Some(100));
+    }
+
+    #[test]
+    fn test_average_finality() {
+        let bank = Bank::default();
+        assert_eq!(bank.average_finality(Duration::from_secs(10)), None);
+
+        bank.set_test_clock(0);
+        bank.set_finality(100);
+        bank.advance_ticks(100);
+        bank.set_finality(40);
+        bank.set_finality(60);
+        assert_eq!(bank.average_finality(Duration::from_millis(50)), Some(50));
+        assert_eq!(
+            bank.average_finality(Duration::from_secs(10)),
+            Some(200 / 3)
+        );
+    }
+
+    #[test]
+    fn test_timestamp_for_tick() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        let tick_height = bank.tick_height();
+        bank.set_test_clock(bank.timestamp_for_tick(tick_height).unwrap());
+        for i in 0..5 {
+            bank.register_tick(&hash(&serialize(&i).unwrap()));
+        }
+
+        let timestamps: Vec<_> = (tick_height..=tick_height + 5)
+            .map(|tick_height| bank.timestamp_for_tick(tick_height).unwrap())
+            .collect();
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(bank.timestamp_for_tick(tick_height + 6), None);
+    }
+
+    #[test]
+    fn test_max_vote_accounts() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+
+        create_vote_account(&validator, &bank, 1, last_id).unwrap();
+        assert_matches!(
+            create_vote_account(&validator, &bank, 1, last_id),
+            Err(crate::result::Error::BankError(
+                BankError::TooManyVoteAccounts
+            ))
+        );
+
+        let mut bank = Bank::new(&mint);
+        bank.set_max_vote_accounts(3);
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        for _ in 0..3 {
+            create_vote_account(&validator, &bank, 1, last_id).unwrap();
+        }
+        assert_matches!(
+            create_vote_account(&validator, &bank, 1, last_id),
+            Err(crate::result::Error::BankError(
+                BankError::TooManyVoteAccounts
+            ))
+        );
+        assert_eq!(bank.vote_accounts().len(), 3);
+    }
+
+    #[test]
+    fn test_max_vote_accounts_in_one_transaction() {
+        let mint = Mint::new(1_000);
+        let register_two = |bank: &Bank| {
+            let last_id = bank.last_id();
+            let validator = Keypair::new();
+            bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+                .unwrap();
+            let create_account = |vote_account_index| {
+                Instruction::new(
+                    0,
+                    &SystemInstruction::CreateAccount {
+                        tokens: 1,
+                        space: vote_program::get_max_size() as u64,
+                        program_id: vote_program::id(),
+                    },
+                    vec![0, vote_account_index],
+                )
+            };
+            let tx = Transaction::new_with_instructions(
+                &[&validator],
+                &[Keypair::new().pubkey(), Keypair::new().pubkey()],
+                last_id,
+                0,
+                vec![system_program::id(), vote_program::id()],
+                vec![
+                    create_account(1),
+                    create_account(2),
+                    Instruction::new(1, &VoteInstruction::RegisterAccount, vec![0, 1]),
+                    Instruction::new(1, &VoteInstruction::RegisterAccount, vec![0, 2]),
+                ],
+            );
+            bank.process_transaction(&tx)
+        };
+
+        // The first registration counts towards the limit of the second
+        let bank = Bank::new(&mint);
+        assert_eq!(register_two(&bank), Err(BankError::TooManyVoteAccounts));
+        assert!(bank.vote_accounts().is_empty());
+
+        let mut bank = Bank::new(&mint);
+        bank.set_max_vote_accounts(2);
+        assert_eq!(register_two(&bank), Ok(()));
+        assert_eq!(bank.vote_accounts().len(), 2);
+    }
+
+    #[test]
+    fn test_vote_tick_stakes() {
+        let mint = Mint::new(1_000);
+        let mut bank = Bank::new(&mint);
+        bank.set_max_vote_accounts(2);
+        let last_id = bank.last_id();
+
+        let mut validators = vec![];
+        for i in 0..3 {
+            let validator = Keypair::new();
+            bank.transfer(10 * (i + 1), &mint.keypair(), validator.pubkey(), last_id)
+                .unwrap();
+            let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+            validators.push((validator, vote_account));
+        }
+
+        // A second vote account for the first validator must not count its stake twice
+        let duplicate_vote_account =
+            create_vote_account(&validators[0].0, &bank, 1, last_id).unwrap();
+
+        // Only the first two validators vote
+        for (i, vote_account) in [&validators[0].1, &duplicate_vote_account, &validators[1].1]
+            .iter()
+            .enumerate()
+        {
+            let vote = Vote {
+                tick_height: (i + 1) as u64,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+
+        // Stakes are what remains after funding each vote account
+        let mut tick_stakes = bank.vote_tick_stakes(None);
+        tick_stakes.sort();
+        assert_eq!(tick_stakes, vec![(2, Stake(8)), (3, Stake(19))]);
+        assert_eq!(bank.vote_total_stake(None), Stake(56));
+
+        let mut tick_stakes = bank.vote_tick_stakes(Some(validators[1].0.pubkey()));
+        tick_stakes.sort();
+        assert_eq!(tick_stakes, vec![(2, Stake(8))]);
+        assert_eq!(
+            bank.vote_total_stake(Some(validators[1].0.pubkey())),
+            Stake(37)
+        );
+    }
+
+    #[test]
+    fn test_stake_summary() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+
+        // One validator that keeps voting, one that never votes, and one that stops voting
+        let validators: Vec<_> = [10, 20, 30]
+            .iter()
+            .map(|stake| {
+                let validator = Keypair::new();
+                bank.transfer(stake + 1, &mint.keypair(), validator.pubkey(), last_id)
+                    .unwrap();
+                create_vote_account(&validator, &bank, 1, last_id).unwrap()
+            })
+            .collect();
+        let vote = |vote_account, tick_height, last_id| {
+            let vote = Vote {
+                tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        };
+        vote(&validators[0], 1, last_id);
+        vote(&validators[2], 1, last_id);
+        assert_eq!(
+            bank.stake_summary(),
+            StakeSummary {
+                total: Stake(60),
+                active: Stake(40),
+                delinquent: Stake(20),
+            }
+        );
+
+        for _ in 0..MAX_ENTRY_IDS {
+            bank.register_tick(&hash(bank.last_id().as_ref()));
+        }
+        vote(&validators[0], bank.tick_height(), bank.last_id());
+        assert_eq!(
+            bank.stake_summary(),
+            StakeSummary {
+                total: Stake(60),
+                active: Stake(10),
+                delinquent: Stake(50),
+            }
+        );
+    }
+
+    #[test]
+    fn test_finality_if_voted() {
+        let mint = Mint::new(1_000);
+        let mut bank = Bank::new(&mint);
+        bank.set_max_vote_accounts(2);
+        let last_id = bank.last_id();
+        for _ in 0..3 {
+            bank.register_tick(&hash(bank.last_id().as_ref()));
+        }
+
+        let validator0 = Keypair::new();
+        let validator1 = Keypair::new();
+        bank.transfer(11, &mint.keypair(), validator0.pubkey(), last_id)
+            .unwrap();
+        bank.transfer(31, &mint.keypair(), validator1.pubkey(), last_id)
+            .unwrap();
+        let vote_account0 = create_vote_account(&validator0, &bank, 1, last_id).unwrap();
+        let vote_account1 = create_vote_account(&validator1, &bank, 1, last_id).unwrap();
+
+        // The second validator voted for a tick the bank hasn't registered yet, so only the
+        // first validator's 10 tokens count towards the 30 needed
+        for (vote_account, tick_height) in &[(&vote_account0, 2), (&vote_account1, 10)] {
+            let vote = Vote {
+                tick_height: *tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+        assert_eq!(
+            bank.finality_if_voted(&validator0.pubkey(), 3),
+            Err(FinalityError::NoValidSupermajority)
+        );
+
+        // Had the second validator voted for a registered tick, it would be finalized
+        let mut vote_tick_stakes = bank.vote_tick_stakes(None);
+        vote_tick_stakes.sort();
+        assert_eq!(vote_tick_stakes, vec![(2, Stake(10)), (10, Stake(30))]);
+        assert!(bank.finality_if_voted(&validator1.pubkey(), 3).is_ok());
+
+        // ...without the bank being modified
+        let mut vote_tick_stakes = bank.vote_tick_stakes(None);
+        vote_tick_stakes.sort();
+        assert_eq!(vote_tick_stakes, vec![(2, Stake(10)), (10, Stake(30))]);
+    }
+
+    #[test]
+    fn test_finality_if_voted_without_timestamp() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        let tick_height = bank.tick_height();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        assert!(bank
+            .finality_if_voted(&validator.pubkey(), tick_height)
+            .is_ok());
+
+        // Registering the same id again moves its entry, and timestamp, to the next tick
+        bank.register_tick(&last_id);
+        assert_eq!(
+            bank.finality_if_voted(&validator.pubkey(), tick_height),
+            Err(FinalityError::MissingTimestamp(tick_height))
+        );
+    }
+
+    #[test]
+    fn test_validator_lag() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        for _ in 0..10 {
+            bank.register_tick(&hash(bank.last_id().as_ref()));
+        }
+
+        let validators: Vec<_> = (0..4).map(|_| Keypair::new()).collect();
+        let vote_accounts: Vec<_> = validators
+            .iter()
+            .zip(&[11, 11, 11, 2])
+            .map(|(validator, tokens)| {
+                bank.transfer(*tokens, &mint.keypair(), validator.pubkey(), last_id)
+                    .unwrap();
+                create_vote_account(validator, &bank, 1, last_id).unwrap()
+            })
+            .collect();
+        assert_eq!(bank.validator_lag(&validators[0].pubkey(), 8), None);
+
+        // The 10 token validators finalize tick 8, while the 1 token validator is stuck at 2
+        for (vote_account, tick_height) in vote_accounts.iter().zip(&[8, 8, 9, 2]) {
+            let vote = Vote {
+                tick_height: *tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+        assert_eq!(bank.validator_lag(&validators[3].pubkey(), 8), Some(6));
+        assert_eq!(bank.validator_lag(&validators[0].pubkey(), 8), Some(0));
+
+        // Saturates for a validator ahead of the finalized tick
+        assert_eq!(bank.validator_lag(&validators[2].pubkey(), 8), Some(0));
+        assert_eq!(bank.validator_lag(&Keypair::new().pubkey(), 8), None);
+    }
+
+    #[test]
+    fn test_simulate_transaction() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+        let vote = Vote {
+            tick_height: 1,
+            bank_hash: last_id,
+        };
+        let vote_tx = Transaction::vote_new(&vote_account, vote.clone(), last_id, 0);
+
+        let before = bank.get_account(&vote_account.pubkey()).unwrap();
+        let hash_before = bank.hash_internal_state();
+        let transaction_count = bank.transaction_count();
+        let sim_result = bank.simulate_transaction(&vote_tx).unwrap();
+        assert_eq!(sim_result.accounts.len(), 1);
+        let (key, simulated) = &sim_result.accounts[0];
+        assert_eq!(*key, vote_account.pubkey());
+        let simulated_state = VoteProgram::deserialize(&simulated.userdata).unwrap();
+        assert_eq!(simulated_state.votes.back(), Some(&vote));
+
+        // Nothing was committed, and the signature is still free to be processed
+        assert_eq!(bank.get_account(&vote_account.pubkey()).unwrap(), before);
+        assert_eq!(bank.hash_internal_state(), hash_before);
+        assert_eq!(bank.transaction_count(), transaction_count);
+
+        bank.process_transaction(&vote_tx).unwrap();
+        let account = bank.get_account(&vote_account.pubkey()).unwrap();
+        assert_eq!(account, *simulated);
+        assert_eq!(
+            VoteProgram::deserialize(&account.userdata).unwrap().votes,
+            simulated_state.votes
+        );
+    }
+
+    #[test]
+    fn test_advance_ticks() {
+        let mint = Mint::new(1);
+        let bank = Bank::new(&mint);
+        let start_tick_height = bank.tick_height();
+        let start_last_id = bank.last_id();
+
+        bank.advance_ticks(5);
+        bank.advance_ticks(3);
+        assert_eq!(bank.tick_height(), start_tick_height + 8);
+        assert_ne!(bank.last_id(), start_last_id);
+
+        let timestamps: Vec<_> = (start_tick_height..=start_tick_height + 8)
+            .filter_map(|tick_height| bank.timestamp_for_tick(tick_height))
+            .collect();
+        assert!(timestamps.len() >= 8);
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_leader_self_vote() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+
+        let leader = Keypair::new();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), leader.pubkey(), last_id)
+            .unwrap();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        assert_eq!(bank.leader_self_vote(&leader.pubkey()), None);
+
+        // Registered but not voted yet
+        let leader_vote_account = create_vote_account(&leader, &bank, 1, last_id).unwrap();
+        let validator_vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+        assert_eq!(bank.leader_self_vote(&leader.pubkey()), None);
+
+        // Another validator's votes don't count as the leader's
+        let vote = Vote {
+            tick_height: 5,
+            bank_hash: last_id,
+        };
+        let vote_tx = Transaction::vote_new(&validator_vote_account, vote, last_id, 0);
+        bank.process_transaction(&vote_tx).unwrap();
+        assert_eq!(bank.leader_self_vote(&leader.pubkey()), None);
+
+        for tick_height in 1..=3 {
+            let vote = Vote {
+                tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(&leader_vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+        assert_eq!(bank.leader_self_vote(&leader.pubkey()), Some(3));
+        assert_eq!(bank.leader_self_vote(&validator.pubkey()), Some(5));
+    }
+
+    #[test]
+    fn test_vote_tick_histogram() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        assert_eq!(bank.vote_tick_histogram(3), vec![]);
+
+        for (tick_height, stake) in &[(1, 10), (2, 20), (5, 30), (10, 40)] {
+            let validator = Keypair::new();
+            bank.transfer(stake + 1, &mint.keypair(), validator.pubkey(), last_id)
+                .unwrap();
+            let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+            let vote = Vote {
+                tick_height: *tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+
+        // Ticks 1 to 10 split into ranges of 4
+        assert_eq!(
+            bank.vote_tick_histogram(3),
+            vec![(1, Stake(30)), (5, Stake(30)), (9, Stake(40))]
+        );
+        assert_eq!(bank.vote_tick_histogram(1), vec![(1, Stake(100))]);
+        // No more buckets than ticks in the range
+        assert_eq!(
+            bank.vote_tick_histogram(20),
+            vec![
+                (1, Stake(10)),
+                (2, Stake(20)),
+                (3, Stake(0)),
+                (4, Stake(0)),
+                (5, Stake(30)),
+                (6, Stake(0)),
+                (7, Stake(0)),
+                (8, Stake(0)),
+                (9, Stake(0)),
+                (10, Stake(40))
+            ]
+        );
+        assert_eq!(bank.vote_tick_histogram(0), vec![]);
+    }
+
+    #[test]
+    fn test_vote_tick_histogram_max_tick_height() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+
+        for (tick_height, stake) in &[(1, 10), (std::u64::MAX, 20)] {
+            let validator = Keypair::new();
+            bank.transfer(stake + 1, &mint.keypair(), validator.pubkey(), last_id)
+                .unwrap();
+            let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+            let vote = Vote {
+                tick_height: *tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+
+        assert_eq!(bank.vote_tick_histogram(1), vec![(1, Stake(30))]);
+        assert_eq!(
+            bank.vote_tick_histogram(2),
+            vec![(1, Stake(10)), (1 + (1 << 63), Stake(20))]
+        );
+    }
+
+    #[test]
+    fn test_voters_for_tick() {
+        let mint = Mint::new(1_000);
+        let mut bank = Bank::new(&mint);
+        bank.set_max_vote_accounts(2);
+        let last_id = bank.last_id();
+
+        let validator0 = Keypair::new();
+        let validator1 = Keypair::new();
+        bank.transfer(12, &mint.keypair(), validator0.pubkey(), last_id)
+            .unwrap();
+        bank.transfer(31, &mint.keypair(), validator1.pubkey(), last_id)
+            .unwrap();
+        // validator0 votes at tick 2 through both of its vote accounts
+        let vote_account0 = create_vote_account(&validator0, &bank, 1, last_id).unwrap();
+        let vote_account1 = create_vote_account(&validator0, &bank, 1, last_id).unwrap();
+        let vote_account2 = create_vote_account(&validator1, &bank, 1, last_id).unwrap();
+        for (vote_account, tick_height) in &[
+            (&vote_account0, 1),
+            (&vote_account0, 2),
+            (&vote_account1, 2),
+            (&vote_account2, 2),
+            (&vote_account2, 3),
+        ] {
+            let vote = Vote {
+                tick_height: *tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+
+        let mut both = vec![
+            (validator0.pubkey(), Stake(10)),
+            (validator1.pubkey(), Stake(30)),
+        ];
+        both.sort();
+        assert_eq!(bank.voters_for_tick(0), vec![]);
+        assert_eq!(
+            bank.voters_for_tick(1),
+            vec![(validator0.pubkey(), Stake(10))]
+        );
+        assert_eq!(bank.voters_for_tick(2), both);
+        assert_eq!(
+            bank.voters_for_tick(3),
+            vec![(validator1.pubkey(), Stake(30))]
+        );
+        assert_eq!(bank.voters_for_tick(4), vec![]);
+    }
+
+    #[test]
+    fn test_close_vote_account() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+        let vote = Vote {
+            tick_height: 1,
+            bank_hash: last_id,
+        };
+        bank.process_transaction(&Transaction::vote_new(&vote_account, vote, last_id, 0))
+            .unwrap();
+        assert_eq!(bank.leader_self_vote(&validator.pubkey()), Some(1));
+
+        let to = Keypair::new().pubkey();
+        let tx = Transaction::vote_close_account(&validator, vote_account.pubkey(), to, last_id, 0);
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(bank.get_balance(&to), 1);
+        assert_eq!(bank.get_account(&vote_account.pubkey()), None);
+        assert!(bank.vote_accounts().is_empty());
+        assert_eq!(bank.leader_self_vote(&validator.pubkey()), None);
+    }
+
+    #[test]
+    fn test_vote_on_forked_banks() {
+        // Two banks forked from the same genesis run the same vote program code, but must not
+        // share any vote state through it
+        let mint = Mint::new(1_000);
+        let banks = [Bank::new(&mint), Bank::new(&mint)];
+        let last_id = banks[0].last_id();
+        assert_eq!(banks[1].last_id(), last_id);
+
+        let validator = Keypair::new();
+        let vote_account = Keypair::new();
+        let register_tx =
+            Transaction::vote_account_new(&validator, vote_account.pubkey(), last_id, 1, 0);
+        for bank in &banks {
+            bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+                .unwrap();
+            bank.process_transaction(&register_tx).unwrap();
+        }
+
+        let vote_state = |bank: &Bank| {
+            let account = bank.get_account(&vote_account.pubkey()).unwrap();
+            VoteProgram::deserialize(&account.userdata).unwrap()
+        };
+        let tick_heights = |bank: &Bank| -> Vec<_> {
+            vote_state(bank)
+                .votes
+                .iter()
+                .map(|vote| vote.tick_height)
+                .collect()
+        };
+        let vote_tx = |tick_height| {
+            let vote = Vote {
+                tick_height,
+                bank_hash: last_id,
+            };
+            Transaction::vote_new(&vote_account, vote, last_id, 0)
+        };
+
+        // A vote on one fork leaves the other untouched
+        banks[0].process_transaction(&vote_tx(1)).unwrap();
+        assert_eq!(tick_heights(&banks[0]), vec![1]);
+        assert_eq!(vote_state(&banks[0]).credits, 1);
+        assert!(tick_heights(&banks[1]).is_empty());
+        assert_eq!(vote_state(&banks[1]).credits, 0);
+
+        // Replayed on the other fork, the same transaction applies to that fork's own state
+        banks[1].process_transaction(&vote_tx(2)).unwrap();
+        banks[1].process_transaction(&vote_tx(1)).unwrap();
+        assert_eq!(tick_heights(&banks[1]), vec![2, 1]);
+        assert_eq!(vote_state(&banks[1]).credits, 2);
+
+        // ...while the first fork rejects it as a duplicate and is unaffected by the second
+        assert!(banks[0].process_transaction(&vote_tx(1)).is_err());
+        assert_eq!(tick_heights(&banks[0]), vec![1]);
+        assert_eq!(vote_state(&banks[0]).credits, 1);
+    }
+
+    #[test]
+    fn test_clear_vote_accounts() {
+        let mint = Mint::new(1_000);
+        let mut bank = Bank::new(&mint);
+        bank.set_max_vote_accounts(3);
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_accounts: HashSet<_> = (0..3)
+            .map(|_| {
+                create_vote_account(&validator, &bank, 1, last_id)
+                    .unwrap()
+                    .pubkey()
+            })
+            .collect();
+        let found: HashSet<_> = bank
+            .vote_accounts()
+            .into_iter()
+            .map(|(pubkey, _)| pubkey)
+            .collect();
+        assert_eq!(found, vote_accounts);
+
+        bank.clear_vote_accounts();
+        assert!(bank.vote_accounts().is_empty());
+        assert!(bank.vote_tick_stakes(None).is_empty());
+
+        // The vote program itself is untouched
+        assert!(bank.get_account(&vote_program::id()).is_some());
+    }
+
+    #[test]
+    fn test_vote_stake() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+
+        // Without delegations the validator's own stake backs the vote account
+        assert_eq!(bank.vote_stake(&vote_account.pubkey()), Stake(9));
+
+        let mut delegators = vec![];
+        for tokens in &[5, 7] {
+            let delegator = Keypair::new();
+            bank.transfer(*tokens, &mint.keypair(), delegator.pubkey(), last_id)
+                .unwrap();
+            let tx = Transaction::vote_delegate_stake(
+                &delegator,
+                &validator,
+                vote_account.pubkey(),
+                last_id,
+                0,
+            );
+            bank.process_transaction(&tx).unwrap();
+            delegators.push(delegator);
+        }
+        assert_eq!(bank.vote_stake(&vote_account.pubkey()), Stake(12));
+
+        let tx =
+            Transaction::vote_undelegate_stake(&delegators[0], vote_account.pubkey(), last_id, 0);
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(bank.vote_stake(&vote_account.pubkey()), Stake(7));
+
+        assert_eq!(bank.vote_stake(&Keypair::new().pubkey()), Stake(0));
+    }
+
+    #[test]
+    fn test_delegate_stake_once() {
+        let mint = Mint::new(1_000);
+        let mut bank = Bank::new(&mint);
+        bank.set_max_vote_accounts(2);
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_accounts = [
+            create_vote_account(&validator, &bank, 1, last_id).unwrap(),
+            create_vote_account(&validator, &bank, 1, last_id).unwrap(),
+        ];
+        let delegator = Keypair::new();
+        bank.transfer(5, &mint.keypair(), delegator.pubkey(), last_id)
+            .unwrap();
+        let delegate_tx = |vote_account: &Keypair| {
+            Transaction::vote_delegate_stake(
+                &delegator,
+                &validator,
+                vote_account.pubkey(),
+                last_id,
+                0,
+            )
+        };
+
+        // The delegator's balance would otherwise back both vote accounts
+        bank.process_transaction(&delegate_tx(&vote_accounts[0]))
+            .unwrap();
+        assert_eq!(
+            bank.process_transaction(&delegate_tx(&vote_accounts[1])),
+            Err(BankError::StakeAlreadyDelegated)
+        );
+        assert_eq!(bank.vote_stake(&vote_accounts[0].pubkey()), Stake(5));
+        assert_eq!(bank.vote_stake(&vote_accounts[1].pubkey()), Stake(8));
+
+        // Once undelegated it can move, but not to two vote accounts in one transaction
+        let tx =
+            Transaction::vote_undelegate_stake(&delegator, vote_accounts[0].pubkey(), last_id, 0);
+        bank.process_transaction(&tx).unwrap();
+        let tx = Transaction::new_with_instructions(
+            &[&delegator, &validator],
+            &[vote_accounts[0].pubkey(), vote_accounts[1].pubkey()],
+            last_id,
+            0,
+            vec![vote_program::id()],
+            vec![
+                Instruction::new(0, &VoteInstruction::DelegateStake, vec![0, 2, 1]),
+                Instruction::new(0, &VoteInstruction::DelegateStake, vec![0, 3, 1]),
+            ],
+        );
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::StakeAlreadyDelegated)
+        );
+        bank.process_transaction(&delegate_tx(&vote_accounts[1]))
+            .unwrap();
+        assert_eq!(bank.vote_stake(&vote_accounts[0].pubkey()), Stake(8));
+        assert_eq!(bank.vote_stake(&vote_accounts[1].pubkey()), Stake(5));
+    }
+
+    #[test]
+    fn test_vote_with_recent_hash() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), mint.last_id())
+            .unwrap();
+        let vote_account = create_vote_account(&validator, &bank, 1, mint.last_id()).unwrap();
+
+        let last_id = hash(mint.last_id().as_ref());
+        bank.register_tick(&last_id);
+        let vote = Vote {
+            tick_height: bank.tick_height(),
+            bank_hash: last_id,
+        };
+        let tx = Transaction::vote_new_with_recent_hash(&vote_account, vote, last_id, last_id, 0);
+        bank.process_transaction(&tx).unwrap();
+
+        let vote = Vote {
+            tick_height: bank.tick_height(),
+            bank_hash: last_id,
+        };
+        let unknown_hash = hash(b"unknown");
+        let tx =
+            Transaction::vote_new_with_recent_hash(&vote_account, vote, unknown_hash, last_id, 0);
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::ProgramError(0, ProgramError::InvalidArgument))
+        );
+    }
+
+    #[test]
+    fn test_recent_hashes_account_is_readonly() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let recent_hashes_account = bank.get_account(&recent_hashes::id()).unwrap();
+        assert_eq!(recent_hashes_account.tokens, 0);
+        assert!(RecentHashes::deserialize(&recent_hashes_account.userdata)
+            .unwrap()
+            .contains(&mint.last_id()));
+
+        // Crediting the account would otherwise drop the tokens debited from the payer
+        let tx =
+            Transaction::system_move(&mint.keypair(), recent_hashes::id(), 10, mint.last_id(), 0);
+        assert_eq!(
+            bank.process_transaction(&tx),
+            Err(BankError::ProgramError(
+                0,
+                ProgramError::ReadonlyAccountModified
+            ))
+        );
+        assert_eq!(bank.get_balance(&mint.pubkey()), 1_000);
+        assert_eq!(
+            bank.get_account(&recent_hashes::id()).unwrap(),
+            recent_hashes_account
+        );
+    }
+
+    #[test]
+    fn test_vote_reward_base() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+        assert_eq!(bank.vote_reward_base(&vote_account.pubkey()), Some((0, 0)));
+
+        for tick_height in 1..=3 {
+            let vote = Vote {
+                tick_height,
+                bank_hash: last_id,
+            };
+            let tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+            bank.process_transaction(&tx).unwrap();
+        }
+        let tx =
+            Transaction::vote_set_commission(&validator, vote_account.pubkey(), 10, last_id, 0);
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(bank.vote_reward_base(&vote_account.pubkey()), Some((3, 10)));
+
+        assert_eq!(bank.vote_reward_base(&validator.pubkey()), None);
+    }
+
+    #[test]
+    fn test_slash_vote_account() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(101, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+        assert_eq!(bank.vote_stake(&vote_account.pubkey()), Stake(100));
+
+        let vote_tx = |bank_hash| {
+            let vote = Vote {
+                tick_height: 5,
+                bank_hash,
+            };
+            Transaction::vote_new(&vote_account, vote, last_id, 0)
+        };
+        let slash_tx = |vote_tx| {
+            let signed_vote = SignedVote::from_transaction(&vote_tx).unwrap();
+            Transaction::vote_slash(
+                &mint.keypair(),
+                vote_account.pubkey(),
+                signed_vote,
+                last_id,
+                0,
+            )
+        };
+        bank.process_transaction(&vote_tx(last_id)).unwrap();
+
+        // The vote already in the account's history isn't a conflict
+        assert_eq!(
+            bank.process_transaction(&slash_tx(vote_tx(last_id))),
+            Err(BankError::ProgramError(0, ProgramError::InvalidArgument))
+        );
+        assert!(bank.slashed_nodes().is_empty());
+
+        // The evidence fits in a single packet
+        let conflict = slash_tx(vote_tx(hash(b"other")));
+        assert!(serialize(&conflict).unwrap().len() <= PACKET_DATA_SIZE);
+
+        // The slashed flag is kept in the vote account, so it's part of the ledger state
+        bank.process_transaction(&conflict).unwrap();
+        let account = bank.get_account(&vote_account.pubkey()).unwrap();
+        let vote_state = VoteProgram::deserialize(&account.userdata).unwrap();
+        assert!(vote_state.slashed);
+        assert!(bank.slashed_nodes().contains(&validator.pubkey()));
+        assert_eq!(
+            bank.vote_stake(&vote_account.pubkey()),
+            Stake(100 - DEFAULT_SLASH_PERCENTAGE)
+        );
+
+        // The same account can't be slashed twice
+        assert_eq!(
+            bank.process_transaction(&conflict),
+            Err(BankError::ProgramError(0, ProgramError::InvalidArgument))
+        );
+        assert_eq!(
+            bank.vote_stake(&vote_account.pubkey()),
+            Stake(100 - DEFAULT_SLASH_PERCENTAGE)
+        );
+    }
+
+    #[test]
+    fn test_cluster_root_tick() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        assert_eq!(bank.cluster_root_tick(), None);
+
+        // Stakes of 10, 20 and 30, rooting ticks 2, 6 and 4 respectively
+        for (i, last_vote) in [34, 38, 36].iter().enumerate() {
+            let validator = Keypair::new();
+            let tokens = 10 * (i as u64 + 1) + 1;
+            bank.transfer(tokens, &mint.keypair(), validator.pubkey(), last_id)
+                .unwrap();
+            let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+            for tick_height in 1..=*last_vote {
+                let vote = Vote {
+                    tick_height,
+                    bank_hash: last_id,
+                };
+                let tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+                bank.process_transaction(&tx).unwrap();
+            }
+        }
+
+        // Tick 6 is only rooted by 20 of the 60 total stake, tick 4 by 50
+        assert_eq!(bank.cluster_root_tick(), Some(4));
+    }
+
+    #[test]
+    fn test_with_vote_accounts_locked() {
+        let mint = Mint::new(1_000);
+        let bank = Arc::new(Bank::new(&mint));
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+
+        let voted = Arc::new(AtomicBool::new(false));
+        let voter = bank.with_vote_accounts_locked(|vote_accounts| {
+            assert_eq!(vote_accounts.len(), 1);
+
+            // Try to vote while the closure holds the lock
+            let voter = {
+                let bank = bank.clone();
+                let voted = voted.clone();
+                let vote = Vote {
+                    tick_height: 1,
+                    bank_hash: last_id,
+                };
+                let tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+                std::thread::spawn(move || {
+                    bank.process_transaction(&tx).unwrap();
+                    voted.store(true, Ordering::Relaxed);
+                })
+            };
+            std::thread::sleep(Duration::from_millis(100));
+            assert!(!voted.load(Ordering::Relaxed));
+            let vote_state = VoteProgram::deserialize(&vote_accounts[0].1.userdata).unwrap();
+            assert!(vote_state.votes.is_empty());
+            voter
+        });
+
+        // The vote lands once the lock is released
+        voter.join().unwrap();
+        assert!(voted.load(Ordering::Relaxed));
+        let account = bank.get_account(&vote_account.pubkey()).unwrap();
+        let vote_state = VoteProgram::deserialize(&account.userdata).unwrap();
+        assert_eq!(vote_state.votes.len(), 1);
+    }
+
+    #[test]
+    fn test_votes_in_range() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+
+        for vote_ticks in &[vec![1, 3, 5], vec![2, 6]] {
+            let validator = Keypair::new();
+            bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+                .unwrap();
+            let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+            for tick_height in vote_ticks {
+                let vote = Vote {
+                    tick_height: *tick_height,
+                    bank_hash: last_id,
+                };
+                let tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+                bank.process_transaction(&tx).unwrap();
+            }
+        }
+
+        assert_eq!(bank.votes_in_range(2, 5), 3);
+        assert_eq!(bank.votes_in_range(0, 10), 5);
+        assert_eq!(bank.votes_in_range(4, 4), 0);
+        assert_eq!(bank.votes_in_range(7, 10), 0);
+    }
+
+    #[test]
+    fn test_process_and_record_filtered_transactions() {
+        let mint = Mint::new(1_000);
+        let bank = Arc::new(Bank::new(&mint));
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+
+        // A recorder only accepting vote transactions
+        let (entry_sender, entry_receiver) = channel();
+        let mut poh_recorder = PohRecorder::new(bank.clone(), entry_sender, last_id, None);
+        poh_recorder.set_transaction_filter(Arc::new(|tx: &Transaction| {
+            tx.program_ids.iter().all(vote_program::check_id)
+        }));
+
+        let vote = Vote {
+            tick_height: 1,
+            bank_hash: last_id,
+        };
+        let vote_tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+        let recipient = Keypair::new().pubkey();
+        let transfer_tx = Transaction::system_move(&mint.keypair(), recipient, 1, last_id, 0);
+        bank.process_and_record_transactions(&[transfer_tx, vote_tx.clone()], &poh_recorder)
+            .unwrap();
+
+        let entries = entry_receiver.recv().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].transactions, vec![vote_tx]);
+        // The filtered out transfer was never executed
+        assert_eq!(bank.get_balance(&recipient), 0);
+    }
+
+    #[test]
+    fn test_uninitialized_vote_accounts() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let last_id = bank.last_id();
+        let validator = Keypair::new();
+        bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+            .unwrap();
+        let vote_account = create_vote_account(&validator, &bank, 1, last_id).unwrap();
+        let vote = Vote {
+            tick_height: 1,
+            bank_hash: last_id,
+        };
+        let tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+        bank.process_transaction(&tx).unwrap();
+        assert!(bank.uninitialized_vote_accounts().is_empty());
+
+        // A vote account with empty userdata
+        let uninitialized = Keypair::new().pubkey();
+        let tx = Transaction::system_create(
+            &mint.keypair(),
+            uninitialized,
+            last_id,
+            5,
+            0,
+            vote_program::id(),
+            0,
+        );
+        bank.process_transaction(&tx).unwrap();
+        assert_eq!(bank.uninitialized_vote_accounts(), vec![uninitialized]);
+
+        // It's a vote account, but has no say in finality
+        assert_eq!(bank.vote_accounts().len(), 2);
+        assert_eq!(bank.vote_tick_stakes(None), vec![(1, Stake(9))]);
+        assert_eq!(bank.vote_total_stake(None), Stake(9));
+    }
+
+    #[test]
+    fn test_vote_program_id() {
+        let mint = Mint::new(1_000);
+        let bank = Bank::new(&mint);
+        let mut custom_mint = Mint::new(1_000);
+        custom_mint.vote_program_id = Keypair::new().pubkey();
+        let custom_bank = Bank::new(&custom_mint);
+        assert_eq!(custom_bank.vote_program_id(), custom_mint.vote_program_id);
+
+        // Register a vote account in each bank, under its own vote program
+        for (bank, mint) in &[(&bank, &mint), (&custom_bank, &custom_mint)] {
+            let last_id = bank.last_id();
+            let vote_account = Keypair::new();
+            let tx = Transaction::new_with_instructions(
+                &[&mint.keypair()],
+                &[vote_account.pubkey()],
+                last_id,
+                0,
+                vec![system_program::id(), mint.vote_program_id],
+                vec![
+                    Instruction::new(
+                        0,
+                        &SystemInstruction::CreateAccount {
+                            tokens: 1,
+                            space: vote_program::get_max_size() as u64,
+                            program_id: mint.vote_program_id,
+                        },
+                        vec![0, 1],
+                    ),
+                    Instruction::new(1, &VoteInstruction::RegisterAccount, vec![0, 1]),
+                ],
+            );
+            bank.process_transaction(&tx).unwrap();
+
+            // An account owned by the other bank's vote program is not a vote account here
+            let other_vote_program_id = if mint.vote_program_id == vote_program::id() {
+                custom_mint.vote_program_id
+            } else {
+                vote_program::id()
+            };
+            let tx = Transaction::system_create(
+                &mint.keypair(),
+                Keypair::new().pubkey(),
+                last_id,
+                1,
+                vote_program::get_max_size() as u64,
+                other_vote_program_id,
+                0,
+            );
+            bank.process_transaction(&tx).unwrap();
+
+            let vote_accounts: Vec<_> = bank
+                .vote_accounts()
+                .into_iter()
+                .map(|(pubkey, _)| pubkey)
+                .collect();
+            assert_eq!(vote_accounts, vec![vote_account.pubkey()]);
+        }
+    }
+
+    #[test]
+    fn test_process_vote_transactions() {
+        let mint = Mint::new(1_000);
+        let mut sequential_bank = Bank::new(&mint);
+        let mut batch_bank = Bank::new(&mint);
+        sequential_bank.set_max_vote_accounts(8);
+        batch_bank.set_max_vote_accounts(8);
+        let last_id = mint.last_id();
+
+        let vote_accounts: Vec<_> = (0..8).map(|_| Keypair::new()).collect();
+        let setup_txs: Vec<_> = vote_accounts
+            .iter()
+            .map(|vote_account| {
+                Transaction::vote_account_new(&mint.keypair(), vote_account.pubkey(), last_id, 1, 0)
+            })
+            .collect();
+        for bank in &[&sequential_bank, &batch_bank] {
+            for tx in &setup_txs {
+                bank.process_transaction(tx).unwrap();
+            }
+        }
+
+        // Includes a vote from an account that was never registered
+        let vote_txs: Vec<_> = vote_accounts
+            .iter()
+            .chain(Some(&Keypair::new()))
+            .enumerate()
+            .map(|(i, vote_account)| {
+                let vote = Vote {
+                    tick_height: i as u64 + 1,
+                    bank_hash: last_id,
+                };
+                Transaction::vote_new(vote_account, vote, last_id, 0)
+            })
+            .collect();
+
+        let sequential_results: Vec<_> = vote_txs
+            .iter()
+            .map(|tx| sequential_bank.process_transactions(&[tx.clone()])[0].clone())
+            .collect();
+        let batch_results = batch_bank.process_vote_transactions(&vote_txs);
+        assert_eq!(batch_results, sequential_results);
+        assert!(batch_results[..vote_accounts.len()]
+            .iter()
+            .all(|result| result.is_ok()));
+        assert!(batch_results[vote_accounts.len()].is_err());
+        assert_eq!(
+            batch_bank.hash_internal_state(),
+            sequential_bank.hash_internal_state()
+        );
+    }
+
     #[test]
     fn test_interleaving_locks() {
         let mint = Mint::new(3);
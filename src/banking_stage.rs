@@ -3,7 +3,7 @@
 //! can do its processing in parallel with signature verification on the GPU.
 
 use crate::bank::Bank;
-use crate::compute_leader_finality_service::ComputeLeaderFinalityService;
+use crate::compute_leader_finality_service::{ComputeLeaderFinalityService, FinalityMode};
 use crate::counter::Counter;
 use crate::entry::Entry;
 use crate::packet::Packets;
@@ -62,12 +62,16 @@ impl BankingStage {
         // Single thread to generate entries from many banks.
         // This thread talks to poh_service and broadcasts the entries once they have been recorded.
         // Once an entry has been recorded, its last_id is registered with the bank.
-        let poh_service = PohService::new(poh_recorder.clone(), config);
+        let poh_service = PohService::new(poh_recorder.clone(), config, String::new());
 
         // Single thread to compute finality
         let compute_finality_service = ComputeLeaderFinalityService::new(
             bank.clone(),
             leader_id,
+            false,
+            FinalityMode::Optimistic,
+            None,
+            None,
             poh_service.poh_exit.clone(),
         );
 
@@ -241,7 +245,19 @@ impl Service for BankingStage {
 
         self.compute_finality_service.join()?;
 
-        let poh_return_value = self.poh_service.join()?;
+        // Transactions the recorder was still coalescing were already committed to the bank,
+        // but the banking threads are gone, so there's nowhere left to requeue them
+        let (poh_return_value, unrecorded_txs) = self.poh_service.join()?;
+        if !unrecorded_txs.is_empty() {
+            warn!(
+                "dropping {} transactions not yet recorded at shutdown",
+                unrecorded_txs.len()
+            );
+            inc_new_counter_info!(
+                "banking_stage-unrecorded_transactions",
+                unrecorded_txs.len()
+            );
+        }
         match poh_return_value {
             Ok(_) => (),
             Err(Error::PohRecorderError(PohRecorderError::MaxHeightReached)) => {
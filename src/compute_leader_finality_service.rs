@@ -2,79 +2,314 @@
 //! to generate a thread which regularly calculates the last finality times
 //! observed by the leader
 
-use crate::bank::Bank;
-
+use crate::bank::{Bank, FinalityError};
+use crate::poh_service::NUM_TICKS_PER_SECOND;
 use crate::service::Service;
+use crate::stake::Stake;
 use solana_metrics::{influxdb, submit};
 use solana_sdk::pubkey::Pubkey;
 use solana_sdk::timing;
-use solana_sdk::vote_program::{self, VoteProgram};
+use solana_sdk::vote_program::VoteProgram;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
 use std::result;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread::sleep;
 use std::thread::{self, Builder, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+/// Result of looking for a tick confirmed by a supermajority of the stake
 #[derive(Debug, PartialEq, Eq)]
-pub enum FinalityError {
-    NoValidSupermajority,
+pub struct FinalityOutcome {
+    /// True if a supermajority of the stake has voted on a recent tick
+    pub confirmed: bool,
+    /// The timestamp recorded for the confirmed tick, if there is one
+    pub timestamp: Option<u64>,
+    /// The confirmed tick, if any
+    pub tick_height: Option<u64>,
+}
+
+/// Which vote of each validator finality is computed from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinalityMode {
+    /// The latest vote, which the validator may still switch away from
+    Optimistic,
+    /// The root, i.e. the highest vote aged out of the validator's vote history, which
+    /// it can no longer revisit
+    Confirmed,
+}
+
+/// Where finality looks up the stake of each validator
+pub trait StakeSource {
+    /// The validator's full stake
+    fn stake(&self, node_id: &Pubkey) -> Stake;
+
+    /// The part of the validator's stake that counts during `epoch`, as stake warms up
+    /// after it is delegated and cools down after it is withdrawn. Defaults to the full
+    /// stake.
+    fn effective_stake(&self, node_id: &Pubkey, _epoch: u64) -> Stake {
+        self.stake(node_id)
+    }
+}
+
+impl StakeSource for Bank {
+    fn stake(&self, node_id: &Pubkey) -> Stake {
+        self.get_stake(node_id)
+    }
+}
+
+/// Returns the stake that the votes finalizing a tick must hold strictly more of, given the
+/// latest vote tick and stake of every validator counted towards finality, see
+/// `Bank::vote_stakes`
+pub fn supermajority_threshold(ticks_and_stakes: &[(u64, Stake)]) -> Stake {
+    // Validators only count towards the total stake once they have cast a vote, so that
+    // a freshly staked validator doesn't stall finality until it starts voting
+    let total_stake: Stake = ticks_and_stakes.iter().map(|(_, stake)| stake).sum();
+
+    // Finality takes strictly more than this, i.e. more than 2/3 of the total stake
+    total_stake * 2 / 3
 }
 
 pub const COMPUTE_FINALITY_MS: u64 = 100;
 
+/// The finality thread never waits longer than this between computations, however long they
+/// take
+pub const MAX_COMPUTE_FINALITY_MS: u64 = 16 * COMPUTE_FINALITY_MS;
+
+/// `estimated_time_to_finality` measures how fast votes land over this many of the most
+/// recent ticks, about a second's worth
+pub const VOTE_VELOCITY_WINDOW_TICKS: u64 = NUM_TICKS_PER_SECOND as u64;
+
+/// The finality thread submits its loop counters once every this many iterations
+const LOOP_COUNTERS_SUBMIT_ITERATIONS: usize = 100;
+
+/// Alerts operators when finality degrades
+pub struct FinalityAlert {
+    /// Finality in milliseconds above which `on_finality` is invoked
+    pub threshold_ms: u64,
+    /// Invoked with the computed finality in milliseconds when it exceeds `threshold_ms`
+    pub on_finality: Box<dyn Fn(u64) + Send>,
+}
+
+/// Finality updates a subscriber hasn't received yet beyond this many are dropped, oldest
+/// first, so a slow subscriber never stalls the finality thread
+pub const FINALITY_SUBSCRIPTION_CAPACITY: usize = 16;
+
+#[derive(Default)]
+struct FinalityQueue {
+    updates: VecDeque<u64>,
+    // Set once the finality thread exits, after which no more updates are published
+    closed: bool,
+}
+
+type FinalitySubscription = Arc<(Mutex<FinalityQueue>, Condvar)>;
+
+/// Receives the finality, in milliseconds, every time the finality thread computes it, see
+/// `ComputeLeaderFinalityService::subscribe`
+pub struct FinalityReceiver {
+    subscription: FinalitySubscription,
+}
+
+impl FinalityReceiver {
+    /// Returns the oldest pending update, if any, without blocking
+    pub fn try_recv(&self) -> Option<u64> {
+        let (queue, _) = &*self.subscription;
+        queue.lock().unwrap().updates.pop_front()
+    }
+
+    /// Waits up to `timeout` for an update. Fails with `Disconnected` once the finality
+    /// thread has exited and every pending update has been received.
+    pub fn recv_timeout(&self, timeout: Duration) -> result::Result<u64, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let (queue, updated) = &*self.subscription;
+        let mut queue = queue.lock().unwrap();
+        loop {
+            if let Some(finality_ms) = queue.updates.pop_front() {
+                return Ok(finality_ms);
+            }
+            if queue.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            queue = updated.wait_timeout(queue, deadline - now).unwrap().0;
+        }
+    }
+}
+
+// The subscriptions of a finality thread, None once it has exited
+type FinalitySubscriptions = Mutex<Option<Vec<FinalitySubscription>>>;
+
+/// Queues `finality_ms` for every subscriber, dropping its oldest update if it already has
+/// FINALITY_SUBSCRIPTION_CAPACITY pending, and forgets the subscribers that went away
+fn publish_finality(subscriptions: &FinalitySubscriptions, finality_ms: u64) {
+    let mut subscriptions = subscriptions.lock().unwrap();
+    let subscriptions = match &mut *subscriptions {
+        Some(subscriptions) => subscriptions,
+        None => return,
+    };
+    subscriptions.retain(|subscription| Arc::strong_count(subscription) > 1);
+    for subscription in subscriptions.iter() {
+        let (queue, updated) = &**subscription;
+        let mut queue = queue.lock().unwrap();
+        if queue.updates.len() >= FINALITY_SUBSCRIPTION_CAPACITY {
+            queue.updates.pop_front();
+        }
+        queue.updates.push_back(finality_ms);
+        updated.notify_all();
+    }
+}
+
+/// Disconnects every subscriber once the finality thread exits
+fn close_subscriptions(subscriptions: &FinalitySubscriptions) {
+    let subscriptions = subscriptions.lock().unwrap().take().unwrap_or_default();
+    for subscription in subscriptions {
+        let (queue, updated) = &*subscription;
+        queue.lock().unwrap().closed = true;
+        updated.notify_all();
+    }
+}
+
+/// The outcome of the last finality computation, which `compute_finality` reuses until the
+/// bank's tick height or transaction count changes. Votes only land through transactions, so
+/// the outcome can't change while neither does, e.g. between ticks.
+#[derive(Default)]
+pub struct FinalityCache {
+    // Tick height and transaction count of the bank when the outcome was computed
+    key: Option<(u64, u64)>,
+    timestamp: Option<u64>,
+    tick_height: Option<u64>,
+    // Number of computations that actually scanned the vote accounts
+    scans: usize,
+    // Whether the last computation scanned them, rather than reusing the outcome
+    rescanned: bool,
+}
+
+fn vote_account_count_point(vote_accounts: usize, node_ids: usize) -> influxdb::Point {
+    influxdb::Point::new(&"vote-account-count")
+        .add_field(
+            "vote_accounts",
+            influxdb::Value::Integer(vote_accounts as i64),
+        )
+        .add_field("node_ids", influxdb::Value::Integer(node_ids as i64))
+        .to_owned()
+}
+
+/// Paces the finality thread. A computation that takes longer than COMPUTE_FINALITY_MS,
+/// e.g. because there are too many vote accounts to scan, marks the thread overloaded and
+/// doubles the wait before the next one, up to MAX_COMPUTE_FINALITY_MS, so the thread doesn't
+/// spend all its time scanning. The wait is reset once a computation is fast again.
+struct FinalityCadence {
+    interval_ms: u64,
+    overloaded: bool,
+}
+
+impl Default for FinalityCadence {
+    fn default() -> Self {
+        FinalityCadence {
+            interval_ms: COMPUTE_FINALITY_MS,
+            overloaded: false,
+        }
+    }
+}
+
+impl FinalityCadence {
+    /// Runs `scan`, adjusting the cadence to how long it took
+    fn time_scan<T>(&mut self, scan: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = scan();
+        let elapsed_ms = timing::duration_as_ms(&start.elapsed());
+        if elapsed_ms > COMPUTE_FINALITY_MS {
+            let interval_ms = (self.interval_ms * 2).min(MAX_COMPUTE_FINALITY_MS);
+            if interval_ms != self.interval_ms {
+                warn!(
+                    "computing finality took {}ms, backing off to every {}ms",
+                    elapsed_ms, interval_ms
+                );
+            }
+            self.interval_ms = interval_ms;
+            self.overloaded = true;
+        } else {
+            if self.overloaded {
+                info!(
+                    "computing finality took {}ms, no longer overloaded",
+                    elapsed_ms
+                );
+            }
+            *self = FinalityCadence::default();
+        }
+        result
+    }
+}
+
 pub struct ComputeLeaderFinalityService {
     compute_finality_thread: JoinHandle<()>,
+    // Loop iterations of the finality thread, and those in which the bank hadn't changed
+    // since the finality cache was filled, so neither finality nor the vote throughput was
+    // recomputed
+    iterations: Arc<AtomicUsize>,
+    skipped: Arc<AtomicUsize>,
+    subscriptions: Arc<FinalitySubscriptions>,
+    // Whether the last computation took longer than COMPUTE_FINALITY_MS
+    overloaded: Arc<AtomicBool>,
+    // Highest tick confirmed by a supermajority so far
+    last_finalized_tick: Arc<Mutex<Option<u64>>>,
 }
 
 impl ComputeLeaderFinalityService {
-    fn get_last_supermajority_timestamp(
+    /// The node whose votes are left out of finality: the leader, unless `include_leader`
+    fn excluded_node(leader_id: Pubkey, include_leader: bool) -> Option<Pubkey> {
+        if include_leader {
+            None
+        } else {
+            Some(leader_id)
+        }
+    }
+
+    /// Looks for a tick confirmed by a supermajority of the stake, submitting how long it has
+    /// been since `last_valid_validator_timestamp` if there is none. Validators are weighted
+    /// by their effective stake in the bank's current epoch, as reported by `stake_source`.
+    /// Slashed validators count towards neither the supermajority nor the total stake.
+    pub fn get_last_supermajority_timestamp(
         bank: &Arc<Bank>,
+        stake_source: &dyn StakeSource,
         leader_id: Pubkey,
+        include_leader: bool,
+        mode: FinalityMode,
         now: u64,
         last_valid_validator_timestamp: u64,
-    ) -> result::Result<u64, FinalityError> {
-        let mut total_stake = 0;
-
-        let mut ticks_and_stakes: Vec<(u64, u64)> = {
-            let bank_accounts = bank.accounts.accounts_db.read().unwrap();
-            // TODO: Doesn't account for duplicates since a single validator could potentially register
-            // multiple vote accounts. Once that is no longer possible (see the TODO in vote_program.rs,
-            // process_transaction(), case VoteInstruction::RegisterAccount), this will be more accurate.
-            // See github issue 1654.
-            bank_accounts
-                .accounts
-                .values()
-                .filter_map(|account| {
-                    // Filter out any accounts that don't belong to the VoteProgram
-                    // by returning None
-                    if vote_program::check_id(&account.owner) {
-                        if let Ok(vote_state) = VoteProgram::deserialize(&account.userdata) {
-                            if leader_id == vote_state.node_id {
-                                return None;
-                            }
-                            let validator_stake = bank.get_stake(&vote_state.node_id);
-                            total_stake += validator_stake;
-                            // Filter out any validators that don't have at least one vote
-                            // by returning None
-                            return vote_state
-                                .votes
-                                .back()
-                                .map(|vote| (vote.tick_height, validator_stake));
-                        }
-                    }
-
-                    None
-                })
-                .collect()
+    ) -> FinalityOutcome {
+        // The excluded leader is dropped before any stake is added up, so its stake counts
+        // towards neither the supermajority nor the total stake it is 2/3 of. Were it only
+        // left out of the former, a leader holding over 1/3 of the stake could stall finality.
+        let excluded_node = Self::excluded_node(leader_id, include_leader);
+        let vote_ticks = match mode {
+            FinalityMode::Optimistic => bank.latest_vote_ticks(excluded_node),
+            FinalityMode::Confirmed => bank.root_ticks(excluded_node),
         };
+        let mut ticks_and_stakes: Vec<_> = bank
+            .vote_stakes(vote_ticks, stake_source)
+            .into_iter()
+            .filter_map(|(_, tick_height, stake)| {
+                tick_height.map(|tick_height| (tick_height, stake))
+            })
+            .collect();
+        let super_majority_stake = supermajority_threshold(&ticks_and_stakes);
 
-        let super_majority_stake = (2 * total_stake) / 3;
-
-        if let Some(last_valid_validator_timestamp) =
-            bank.get_finality_timestamp(&mut ticks_and_stakes, super_majority_stake)
+        // A supermajority may confirm a tick whose timestamp is no longer (or was never)
+        // recorded, report the consensus anyway
+        if let Some(tick_height) =
+            bank.get_finality_tick(&mut ticks_and_stakes, super_majority_stake)
         {
-            return Ok(last_valid_validator_timestamp);
+            return FinalityOutcome {
+                confirmed: true,
+                timestamp: bank.timestamp_for_tick(tick_height),
+                tick_height: Some(tick_height),
+            };
         }
 
         if last_valid_validator_timestamp != 0 {
@@ -88,21 +323,159 @@ impl ComputeLeaderFinalityService {
             );
         }
 
-        Err(FinalityError::NoValidSupermajority)
+        FinalityOutcome {
+            confirmed: false,
+            timestamp: None,
+            tick_height: None,
+        }
+    }
+
+    /// Returns the validator whose vote pushes the cumulative stake across the supermajority
+    /// threshold, accumulating votes in ascending tick_height order as finality does. When
+    /// finality is missed because stale or future votes don't count, returns the first of
+    /// those validators whose vote would have pushed the stake across instead. Validators are
+    /// weighed as by `Bank::vote_stakes`.
+    pub fn get_pivotal_validator(
+        bank: &Bank,
+        leader_id: Pubkey,
+        include_leader: bool,
+    ) -> Option<Pubkey> {
+        let vote_ticks = bank.latest_vote_ticks(Self::excluded_node(leader_id, include_leader));
+        let mut votes: Vec<_> = bank
+            .vote_stakes(vote_ticks, bank)
+            .into_iter()
+            .filter_map(|(node_id, tick_height, stake)| {
+                tick_height.map(|tick_height| (tick_height, node_id, stake))
+            })
+            .collect();
+        let ticks_and_stakes: Vec<_> = votes
+            .iter()
+            .map(|(tick, _, stake)| (*tick, *stake))
+            .collect();
+        let super_majority_stake = supermajority_threshold(&ticks_and_stakes);
+
+        // Sort by tick height, breaking ties by node_id so the result is deterministic
+        votes.sort();
+        let mut total = Stake::default();
+        let mut would_be_pivotal = None;
+        for (tick_height, node_id, stake) in votes {
+            if bank.is_recent_vote(tick_height) {
+                total += stake;
+                if total > super_majority_stake {
+                    return Some(node_id);
+                }
+            } else if would_be_pivotal.is_none() && total + stake > super_majority_stake {
+                would_be_pivotal = Some(node_id);
+            }
+        }
+        would_be_pivotal
+    }
+
+    /// Computes the deepest tick height confirmed by a supermajority of the stake,
+    /// i.e. the highest tick that 2/3 of the stake has voted at or above, returning
+    /// `(confirmed_tick, finality_ms)`
+    pub fn get_confirmed_tick(
+        bank: &Arc<Bank>,
+        leader_id: Pubkey,
+        include_leader: bool,
+        now: u64,
+    ) -> result::Result<(u64, u64), FinalityError> {
+        let mut ticks_and_stakes =
+            bank.vote_tick_stakes(Self::excluded_node(leader_id, include_leader));
+        let super_majority_stake = supermajority_threshold(&ticks_and_stakes);
+
+        let confirmed_tick = bank
+            .get_confirmed_tick(&mut ticks_and_stakes, super_majority_stake)
+            .ok_or(FinalityError::NoValidSupermajority)?;
+
+        // Finality latency is the time elapsed since the confirmed tick was registered
+        let timestamp = bank
+            .timestamp_for_tick(confirmed_tick)
+            .ok_or(FinalityError::NoValidSupermajority)?;
+        Ok((confirmed_tick, now - timestamp))
     }
 
+    /// Returns a human-readable report of every validator with a vote account, its stake and
+    /// latest vote, along with the supermajority threshold and the resulting finality tick,
+    /// for debugging finality that won't advance. The leader is reported but, as by default,
+    /// excluded from the threshold. Validators are weighed as by `Bank::vote_stakes`, and
+    /// the ones it leaves out, e.g. slashed ones, are reported as not counted.
+    pub fn debug_dump(bank: &Bank, leader_id: Pubkey) -> String {
+        let latest_vote_ticks = bank.latest_vote_ticks(None);
+        let stakes: HashMap<_, _> = bank
+            .vote_stakes(latest_vote_ticks.clone(), bank)
+            .into_iter()
+            .map(|(node_id, _, stake)| (node_id, stake))
+            .collect();
+        let mut latest_vote_ticks: Vec<_> = latest_vote_ticks.into_iter().collect();
+        latest_vote_ticks.sort();
+
+        let mut dump = String::new();
+        writeln!(dump, "finality for leader {}:", leader_id).unwrap();
+        for (node_id, tick_height) in &latest_vote_ticks {
+            let tick_height = tick_height.map_or("none".to_string(), |tick| tick.to_string());
+            let stake = stakes.get(node_id).cloned().unwrap_or_default();
+            write!(
+                dump,
+                "  {} stake={} latest_tick={}",
+                node_id, stake, tick_height
+            )
+            .unwrap();
+            if *node_id == leader_id {
+                write!(dump, " (leader, excluded)").unwrap();
+            } else if !stakes.contains_key(node_id) {
+                write!(dump, " (not counted)").unwrap();
+            }
+            writeln!(dump).unwrap();
+        }
+
+        let mut ticks_and_stakes = bank.vote_tick_stakes(Some(leader_id));
+        let total_stake: Stake = ticks_and_stakes.iter().map(|(_, stake)| stake).sum();
+        let super_majority_stake = supermajority_threshold(&ticks_and_stakes);
+        let finality_tick = bank
+            .get_finality_tick(&mut ticks_and_stakes, super_majority_stake)
+            .map_or("none".to_string(), |tick| tick.to_string());
+        writeln!(
+            dump,
+            "total voted stake={} threshold=>{} finality_tick={}",
+            total_stake, super_majority_stake, finality_tick
+        )
+        .unwrap();
+        dump
+    }
+
+    /// Computes finality and stores it in the bank, returning the finality in milliseconds
+    /// if a supermajority was found. The vote accounts are only scanned again once the bank
+    /// changed since the outcome in `cache` was computed.
     pub fn compute_finality(
         bank: &Arc<Bank>,
         leader_id: Pubkey,
+        include_leader: bool,
+        mode: FinalityMode,
         last_valid_validator_timestamp: &mut u64,
-    ) {
+        cache: &mut FinalityCache,
+    ) -> Option<u64> {
         let now = timing::timestamp();
-        if let Ok(super_majority_timestamp) = Self::get_last_supermajority_timestamp(
-            bank,
-            leader_id,
-            now,
-            *last_valid_validator_timestamp,
-        ) {
+        // Read before scanning, so a bank that changes mid-scan is scanned again next time
+        let key = Some((bank.tick_height(), bank.transaction_count()));
+        cache.rescanned = cache.key != key;
+        if cache.rescanned {
+            let outcome = Self::get_last_supermajority_timestamp(
+                bank,
+                &**bank,
+                leader_id,
+                include_leader,
+                mode,
+                now,
+                *last_valid_validator_timestamp,
+            );
+            Self::submit_vote_account_count(bank);
+            cache.key = key;
+            cache.timestamp = outcome.timestamp;
+            cache.tick_height = outcome.tick_height;
+            cache.scans += 1;
+        }
+        if let Some(super_majority_timestamp) = cache.timestamp {
             let finality_ms = now - super_majority_timestamp;
 
             *last_valid_validator_timestamp = super_majority_timestamp;
@@ -113,27 +486,248 @@ impl ComputeLeaderFinalityService {
                     .add_field("duration_ms", influxdb::Value::Integer(finality_ms as i64))
                     .to_owned(),
             );
+            return Some(finality_ms);
+        }
+        None
+    }
+
+    /// Counts the registered vote accounts and the distinct validators they belong to,
+    /// submitting both as the `vote-account-count` metric. The two differ when validators
+    /// register several vote accounts.
+    pub fn submit_vote_account_count(bank: &Bank) -> (usize, usize) {
+        let (vote_accounts, node_ids) = bank.with_vote_accounts_locked(|vote_accounts| {
+            let node_ids: Vec<_> = vote_accounts
+                .iter()
+                .filter_map(|(_, account)| VoteProgram::deserialize(&account.userdata).ok())
+                .map(|vote_state| vote_state.node_id)
+                .collect();
+            let vote_accounts = node_ids.len();
+            (
+                vote_accounts,
+                node_ids.into_iter().collect::<HashSet<_>>().len(),
+            )
+        });
+        submit(vote_account_count_point(vote_accounts, node_ids));
+        (vote_accounts, node_ids)
+    }
+
+    /// Counts the votes landed across all vote accounts since the previous call, submitting
+    /// them as the `cluster-vote-throughput` metric. `last_vote_ticks` tracks the tick_height
+    /// of each vote account's latest vote between calls.
+    pub fn compute_vote_throughput(bank: &Bank, last_vote_ticks: &mut HashMap<Pubkey, u64>) -> u64 {
+        let new_votes = bank.with_vote_accounts_locked(|vote_accounts| {
+            let mut new_votes = 0;
+            for (pubkey, account) in vote_accounts {
+                if let Ok(vote_state) = VoteProgram::deserialize(&account.userdata) {
+                    let last_vote_tick = last_vote_ticks.get(pubkey).cloned();
+                    new_votes += vote_state
+                        .votes
+                        .iter()
+                        .filter(|vote| Some(vote.tick_height) > last_vote_tick)
+                        .count() as u64;
+                    if let Some(vote) = vote_state.votes.back() {
+                        last_vote_ticks.insert(*pubkey, vote.tick_height);
+                    }
+                }
+            }
+            new_votes
+        });
+
+        submit(
+            influxdb::Point::new(&"cluster-vote-throughput")
+                .add_field("count", influxdb::Value::Integer(new_votes as i64))
+                .to_owned(),
+        );
+        new_votes
+    }
+
+    /// Estimates how long until the bank's latest tick is finalized, assuming stake keeps
+    /// voting for recent ticks as fast as it did over the last VOTE_VELOCITY_WINDOW_TICKS
+    /// ticks. The stake still missing from a supermajority of the voted stake is divided by
+    /// the stake whose latest vote landed in that window per millisecond it spans. Unlike
+    /// `get_last_supermajority_timestamp`, no leader is excluded. None if the latest tick is
+    /// already finalized, or if no votes landed in the window to extrapolate from.
+    pub fn estimated_time_to_finality(bank: &Bank) -> Option<Duration> {
+        let tick_height = bank.tick_height();
+        let window_start = tick_height.checked_sub(VOTE_VELOCITY_WINDOW_TICKS)?;
+        let window_ms = bank
+            .timestamp_for_tick(tick_height)?
+            .checked_sub(bank.timestamp_for_tick(window_start)?)?;
+
+        let ticks_and_stakes = bank.vote_tick_stakes(None);
+        let super_majority_stake = supermajority_threshold(&ticks_and_stakes);
+        let voted_stake: Stake = ticks_and_stakes
+            .iter()
+            .filter(|(tick, _)| *tick >= tick_height)
+            .map(|(_, stake)| stake)
+            .sum();
+        if voted_stake > super_majority_stake {
+            return None;
+        }
+        let Stake(remaining_stake) = super_majority_stake + Stake(1) - voted_stake;
+
+        let Stake(window_stake) = ticks_and_stakes
+            .iter()
+            .filter(|(tick, _)| *tick >= window_start)
+            .map(|(_, stake)| stake)
+            .sum();
+        if window_stake == 0 || window_ms == 0 {
+            return None;
+        }
+        // Stake and milliseconds can each fill most of a u64, so their product needs a u128
+        let window_stake = u128::from(window_stake);
+        let estimate_ms =
+            (u128::from(remaining_stake) * u128::from(window_ms) + window_stake - 1) / window_stake;
+        Some(Duration::from_millis(
+            estimate_ms.min(u128::from(std::u64::MAX)) as u64,
+        ))
+    }
+
+    /// Returns the number of iterations of the finality thread so far, and how many of those
+    /// skipped recomputing finality and the vote throughput because the bank hadn't changed
+    pub fn loop_counters(&self) -> (usize, usize) {
+        (
+            self.iterations.load(Ordering::Relaxed),
+            self.skipped.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Returns whether the finality thread is backing off because its last computation took
+    /// longer than COMPUTE_FINALITY_MS
+    pub fn is_overloaded(&self) -> bool {
+        self.overloaded.load(Ordering::Relaxed)
+    }
+
+    /// Returns the highest tick confirmed by a supermajority of the stake so far, whether or
+    /// not its timestamp is still recorded. It never moves backwards, even if a later
+    /// computation confirms a lower tick or none at all.
+    pub fn last_finalized_tick(&self) -> Option<u64> {
+        *self.last_finalized_tick.lock().unwrap()
+    }
+
+    /// Returns how many ticks the latest vote of `node_id` is behind `last_finalized_tick`,
+    /// see `Bank::validator_lag`. None if no tick is finalized yet or `node_id` hasn't voted.
+    pub fn validator_lag(&self, bank: &Bank, node_id: &Pubkey) -> Option<u64> {
+        bank.validator_lag(node_id, self.last_finalized_tick()?)
+    }
+
+    /// Subscribes to the finality the thread computes from now on, instead of polling
+    /// `Bank::finality`
+    pub fn subscribe(&self) -> FinalityReceiver {
+        let subscription = FinalitySubscription::default();
+        match &mut *self.subscriptions.lock().unwrap() {
+            Some(subscriptions) => subscriptions.push(subscription.clone()),
+            // The finality thread has already exited, there will be no updates
+            None => subscription.0.lock().unwrap().closed = true,
         }
+        FinalityReceiver { subscription }
     }
 
     /// Create a new ComputeLeaderFinalityService for computing finality.
-    pub fn new(bank: Arc<Bank>, leader_id: Pubkey, exit: Arc<AtomicBool>) -> Self {
-        let compute_finality_thread = Builder::new()
-            .name("solana-leader-finality-stage".to_string())
+    /// * `include_leader` - Whether the leader's own votes and stake count towards finality.
+    /// The leader votes for every tick it produces, so when included a leader holding close
+    /// to 2/3 of the voting stake can reach the supermajority with little agreement from the
+    /// rest of the cluster. When excluded, the supermajority is 2/3 of the stake of the other
+    /// validators only.
+    /// * `mode` - Whether finality is computed from the validators' latest votes or their
+    /// roots, see `FinalityMode`
+    /// * `finality_alert` - Optional callback invoked when finality exceeds a threshold
+    /// * `stack_size` - Stack size of the finality thread, which defaults to that of any
+    /// spawned thread. Each computation collects every vote account, so large clusters may
+    /// need more.
+    pub fn new(
+        bank: Arc<Bank>,
+        leader_id: Pubkey,
+        include_leader: bool,
+        mode: FinalityMode,
+        finality_alert: Option<FinalityAlert>,
+        stack_size: Option<usize>,
+        exit: Arc<AtomicBool>,
+    ) -> Self {
+        let mut builder = Builder::new().name("solana-leader-finality-stage".to_string());
+        if let Some(stack_size) = stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        let iterations = Arc::new(AtomicUsize::new(0));
+        let iterations_ = iterations.clone();
+        let skipped = Arc::new(AtomicUsize::new(0));
+        let skipped_ = skipped.clone();
+        let subscriptions = Arc::new(Mutex::new(Some(vec![])));
+        let subscriptions_ = subscriptions.clone();
+        let overloaded = Arc::new(AtomicBool::new(false));
+        let overloaded_ = overloaded.clone();
+        let last_finalized_tick = Arc::new(Mutex::new(None));
+        let last_finalized_tick_ = last_finalized_tick.clone();
+        let compute_finality_thread = builder
             .spawn(move || {
                 let mut last_valid_validator_timestamp = 0;
+                let mut last_vote_ticks = HashMap::new();
+                let mut cadence = FinalityCadence::default();
+                let mut finality_cache = FinalityCache::default();
                 loop {
                     if exit.load(Ordering::Relaxed) {
                         break;
                     }
-                    Self::compute_finality(&bank, leader_id, &mut last_valid_validator_timestamp);
-                    sleep(Duration::from_millis(COMPUTE_FINALITY_MS));
+                    let iteration = iterations_.fetch_add(1, Ordering::Relaxed) + 1;
+                    let finality_ms = cadence.time_scan(|| {
+                        Self::compute_finality(
+                            &bank,
+                            leader_id,
+                            include_leader,
+                            mode,
+                            &mut last_valid_validator_timestamp,
+                            &mut finality_cache,
+                        )
+                    });
+                    overloaded_.store(cadence.overloaded, Ordering::Relaxed);
+                    {
+                        let mut last_finalized_tick = last_finalized_tick_.lock().unwrap();
+                        *last_finalized_tick =
+                            std::cmp::max(*last_finalized_tick, finality_cache.tick_height);
+                    }
+                    if let Some(finality_ms) = finality_ms {
+                        publish_finality(&subscriptions_, finality_ms);
+                    }
+                    if let (Some(finality_ms), Some(alert)) = (finality_ms, &finality_alert) {
+                        if finality_ms > alert.threshold_ms {
+                            (alert.on_finality)(finality_ms);
+                        }
+                    }
+
+                    // Finality latency grows with time, but the throughput, like the
+                    // cached outcome, can't change while the bank doesn't
+                    if finality_cache.rescanned {
+                        Self::compute_vote_throughput(&bank, &mut last_vote_ticks);
+                    } else {
+                        skipped_.fetch_add(1, Ordering::Relaxed);
+                    }
+
+                    if iteration % LOOP_COUNTERS_SUBMIT_ITERATIONS == 0 {
+                        submit(
+                            influxdb::Point::new(&"leader-finality-loop")
+                                .add_field("iterations", influxdb::Value::Integer(iteration as i64))
+                                .add_field(
+                                    "skipped",
+                                    influxdb::Value::Integer(
+                                        skipped_.load(Ordering::Relaxed) as i64
+                                    ),
+                                )
+                                .to_owned(),
+                        );
+                    }
+                    sleep(Duration::from_millis(cadence.interval_ms));
                 }
+                close_subscriptions(&subscriptions_);
             })
             .unwrap();
 
         (ComputeLeaderFinalityService {
             compute_finality_thread,
+            iterations,
+            skipped,
+            subscriptions,
+            overloaded,
+            last_finalized_tick,
         })
     }
 }
@@ -148,85 +742,1142 @@ impl Service for ComputeLeaderFinalityService {
 
 #[cfg(test)]
 pub mod tests {
-    use crate::bank::Bank;
-    use crate::compute_leader_finality_service::ComputeLeaderFinalityService;
+    use crate::bank::{Bank, FinalityError};
+    use crate::compute_leader_finality_service::{
+        publish_finality, supermajority_threshold, vote_account_count_point,
+        ComputeLeaderFinalityService, FinalityAlert, FinalityCache, FinalityCadence, FinalityMode,
+        FinalityOutcome, FinalityReceiver, FinalitySubscription, StakeSource, COMPUTE_FINALITY_MS,
+        FINALITY_SUBSCRIPTION_CAPACITY, MAX_COMPUTE_FINALITY_MS, VOTE_VELOCITY_WINDOW_TICKS,
+    };
     use crate::create_vote_account::*;
 
     use crate::mint::Mint;
+    use crate::service::Service;
+    use crate::stake::Stake;
     use bincode::serialize;
     use solana_sdk::hash::hash;
+    use solana_sdk::pubkey::Pubkey;
     use solana_sdk::signature::{Keypair, KeypairUtil};
+    use solana_sdk::timing;
     use solana_sdk::transaction::Transaction;
-    use solana_sdk::vote_program::Vote;
+    use solana_sdk::vote_program::{SignedVote, Vote, VoteProgram};
     use solana_sdk::vote_transaction::VoteTransaction;
-    use std::sync::Arc;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{channel, RecvTimeoutError};
+    use std::sync::{Arc, Mutex};
     use std::thread::sleep;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
+
+    /// Builds a bank with `num_validators` validators, each with a stake of 1 (unless
+    /// overridden by `with_stakes`) and a vote account, the first of which vote at the tick
+    /// heights given by `with_votes`
+    struct FinalityFixture {
+        num_validators: usize,
+        stakes: Vec<u64>,
+        votes: Vec<u64>,
+    }
+
+    impl FinalityFixture {
+        fn new(num_validators: usize) -> Self {
+            FinalityFixture {
+                num_validators,
+                stakes: vec![1; num_validators],
+                votes: vec![],
+            }
+        }
+
+        fn with_stakes(mut self, stakes: &[u64]) -> Self {
+            assert_eq!(stakes.len(), self.num_validators);
+            self.stakes = stakes.to_vec();
+            self
+        }
+
+        fn with_votes(mut self, votes: &[u64]) -> Self {
+            assert!(votes.len() <= self.num_validators);
+            self.votes = votes.to_vec();
+            self
+        }
+
+        fn build(self) -> (Bank, Vec<Keypair>) {
+            solana_logger::setup();
+
+            let mint = Mint::new(1234);
+            let bank = Bank::new(&mint);
+            // Give each tick a different timestamp in the bank
+            bank.set_test_clock(0);
+            let ids: Vec<_> = (0..self.num_validators)
+                .map(|i| {
+                    let last_id = hash(&serialize(&i).unwrap()); // Unique hash
+                    bank.register_tick(&last_id);
+                    last_id
+                })
+                .collect();
+
+            let vote_accounts = ids
+                .iter()
+                .enumerate()
+                .map(|(i, last_id)| {
+                    // Create new validator to vote
+                    let validator_keypair = Keypair::new();
+
+                    // Give the validator some tokens, 1 of which goes to its vote account
+                    let tokens = self.stakes[i] + 1;
+                    bank.transfer(
+                        tokens,
+                        &mint.keypair(),
+                        validator_keypair.pubkey(),
+                        *last_id,
+                    )
+                    .unwrap();
+                    let vote_account = create_vote_account(&validator_keypair, &bank, 1, *last_id)
+                        .expect("Expected successful creation of account");
+
+                    if let Some(tick_height) = self.votes.get(i) {
+                        let vote = Vote {
+                            tick_height: *tick_height,
+                            bank_hash: *last_id,
+                        };
+                        let vote_tx = Transaction::vote_new(&vote_account, vote, *last_id, 0);
+                        bank.process_transaction(&vote_tx).unwrap();
+                    }
+                    vote_account
+                })
+                .collect();
+
+            (bank, vote_accounts)
+        }
+    }
+
+    #[test]
+    fn test_supermajority_threshold() {
+        assert_eq!(supermajority_threshold(&[]), Stake(0));
+        // Finality needs more than 6 of the 10 voted stake, i.e. 7 of it
+        assert_eq!(
+            supermajority_threshold(&[(1, Stake(4)), (2, Stake(6))]),
+            Stake(6)
+        );
+    }
 
     #[test]
     fn test_compute_finality() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        // Create a total of 10 vote accounts, each will have a balance of 1 (after giving 1 to
+        // their vote account), for a total staking pool of 10 tokens, but only vote for the
+        // first 6 validators
+        let (bank, vote_accounts) = FinalityFixture::new(10)
+            .with_votes(&[1, 2, 3, 4, 5, 6])
+            .build();
+        let bank = Arc::new(bank);
+
+        // There isn't 2/3 consensus, so the bank's finality value should be the default
+        let mut last_finality_time = 0;
+        let mut cache = FinalityCache::default();
+        ComputeLeaderFinalityService::compute_finality(
+            &bank,
+            dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
+            &mut last_finality_time,
+            &mut cache,
+        );
+        assert_eq!(bank.finality_ms(), None);
+
+        // Get another validator to vote, so we now have 2/3 consensus
+        let vote_account = &vote_accounts[7];
+        let last_id = bank.last_id();
+        let vote = Vote {
+            tick_height: 7,
+            bank_hash: last_id,
+        };
+        let vote_tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+        bank.process_transaction(&vote_tx).unwrap();
+
+        // The vote invalidates the cached outcome
+        ComputeLeaderFinalityService::compute_finality(
+            &bank,
+            dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
+            &mut last_finality_time,
+            &mut cache,
+        );
+        assert!(bank.finality_ms().is_some());
+        assert!(last_finality_time > 0);
+        assert_eq!(cache.scans, 2);
+    }
+
+    #[test]
+    fn test_compute_finality_cached() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        let (bank, _) = FinalityFixture::new(3).with_votes(&[2, 2, 2]).build();
+        let bank = Arc::new(bank);
+
+        let mut last_finality_time = 0;
+        let mut cache = FinalityCache::default();
+        let mut compute_finality = |cache: &mut FinalityCache| {
+            ComputeLeaderFinalityService::compute_finality(
+                &bank,
+                dummy_leader_id,
+                false,
+                FinalityMode::Optimistic,
+                &mut last_finality_time,
+                cache,
+            )
+        };
+        assert!(compute_finality(&mut cache).is_some());
+        assert_eq!(cache.scans, 1);
+        assert!(cache.rescanned);
+
+        // Nothing changed at the same tick height, so the cached outcome is reused
+        assert!(compute_finality(&mut cache).is_some());
+        assert_eq!(cache.scans, 1);
+        assert!(!cache.rescanned);
+
+        // A new tick is scanned again
+        bank.register_tick(&hash(bank.last_id().as_ref()));
+        assert!(compute_finality(&mut cache).is_some());
+        assert_eq!(cache.scans, 2);
+        assert!(cache.rescanned);
+    }
+
+    #[test]
+    fn test_get_last_supermajority_timestamp_self_leader() {
+        // The leader holds most of the stake, and its latest vote is for a tick the bank
+        // hasn't registered yet, so it can't help confirm a tick
+        let (bank, vote_accounts) = FinalityFixture::new(4)
+            .with_stakes(&[1, 1, 1, 10])
+            .with_votes(&[2, 2, 2, 100])
+            .build();
+        let bank = Arc::new(bank);
+        let leader_id = node_id(&bank, &vote_accounts[3]);
+        let now = timing::timestamp();
+        let confirmed = |include_leader| {
+            ComputeLeaderFinalityService::get_last_supermajority_timestamp(
+                &bank,
+                &*bank,
+                leader_id,
+                include_leader,
+                FinalityMode::Optimistic,
+                now,
+                0,
+            )
+            .confirmed
+        };
+
+        // Excluded, the threshold is over the other validators' 3 stake alone, all of which
+        // voted at tick 2
+        assert!(confirmed(false));
+        // Included, the leader's stake raises the threshold to more than 8 of 13
+        assert!(!confirmed(true));
+
+        let dump = ComputeLeaderFinalityService::debug_dump(&bank, leader_id);
+        assert!(dump.contains("total voted stake=3 threshold=>2 finality_tick=2"));
+    }
+
+    #[test]
+    fn test_get_last_supermajority_timestamp_duplicate_vote_accounts() {
+        solana_logger::setup();
+        let dummy_leader_id = Keypair::new().pubkey();
+        let mint = Mint::new(1234);
+        let mut bank = Bank::new(&mint);
+        bank.set_max_vote_accounts(5);
+        bank.set_test_clock(0);
+        let last_id = bank.last_id();
+        for _ in 0..3 {
+            bank.register_tick(&hash(bank.last_id().as_ref()));
+        }
+        let future_tick = bank.tick_height() + 5;
+
+        // One node votes at tick 2 from each of its 5 vote accounts (see github issue 1654),
+        // while 2 other validators vote for a tick the bank hasn't registered yet
+        let node = Keypair::new();
+        bank.transfer(7, &mint.keypair(), node.pubkey(), last_id)
+            .unwrap();
+        let mut votes: Vec<_> = (0..5)
+            .map(|_| (create_vote_account(&node, &bank, 1, last_id).unwrap(), 2))
+            .collect();
+        let validators = [Keypair::new(), Keypair::new()];
+        for validator in &validators {
+            bank.transfer(3, &mint.keypair(), validator.pubkey(), last_id)
+                .unwrap();
+            let vote_account = create_vote_account(validator, &bank, 1, last_id).unwrap();
+            votes.push((vote_account, future_tick));
+        }
+        for (vote_account, tick_height) in &votes {
+            let vote = Vote {
+                tick_height: *tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+        let bank = Arc::new(bank);
+        assert_eq!(bank.get_stake(&node.pubkey()), Stake(2));
+        let confirmed = || {
+            ComputeLeaderFinalityService::get_last_supermajority_timestamp(
+                &bank,
+                &*bank,
+                dummy_leader_id,
+                false,
+                FinalityMode::Optimistic,
+                timing::timestamp(),
+                0,
+            )
+            .confirmed
+        };
+
+        // Counted once, the node's 2 stake doesn't exceed 2/3 of the 6 voted stake. Counted
+        // once per vote account, its 10 of 14 would have been a supermajority.
+        assert!(!confirmed());
+        let dump = ComputeLeaderFinalityService::debug_dump(&bank, dummy_leader_id);
+        assert!(dump.contains("total voted stake=6 threshold=>4 finality_tick=none"));
+
+        // The other validators' votes land once their tick is registered
+        for _ in 0..5 {
+            bank.register_tick(&hash(bank.last_id().as_ref()));
+        }
+        assert!(confirmed());
+        let dump = ComputeLeaderFinalityService::debug_dump(&bank, dummy_leader_id);
+        assert!(dump.contains(&format!(
+            "total voted stake=6 threshold=>4 finality_tick={}",
+            future_tick
+        )));
+    }
+
+    #[test]
+    fn test_get_last_supermajority_timestamp_slashed() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        // The third validator votes for a tick the bank hasn't registered yet, so the first two
+        // make up the supermajority on their own: 5 of 6 tokens
+        let (bank, vote_accounts) = FinalityFixture::new(3)
+            .with_stakes(&[4, 1, 1])
+            .with_votes(&[1, 1, 100])
+            .build();
+        let bank = Arc::new(bank);
+        let now = timing::timestamp();
+        assert!(
+            ComputeLeaderFinalityService::get_last_supermajority_timestamp(
+                &bank,
+                &*bank,
+                dummy_leader_id,
+                false,
+                FinalityMode::Optimistic,
+                now,
+                0
+            )
+            .confirmed
+        );
+
+        // Slashing the first validator's vote account, given a vote for tick 1 that conflicts
+        // with the one in its history, stops its vote from counting right away, leaving 1 of
+        // the remaining 2 tokens
+        let last_id = bank.last_id();
+        let conflict = Vote {
+            tick_height: 1,
+            bank_hash: hash(b"conflict"),
+        };
+        let vote_tx = Transaction::vote_new(&vote_accounts[0], conflict, last_id, 0);
+        let slash_tx = Transaction::vote_slash(
+            &vote_accounts[1],
+            vote_accounts[0].pubkey(),
+            SignedVote::from_transaction(&vote_tx).unwrap(),
+            last_id,
+            0,
+        );
+        bank.process_transaction(&slash_tx).unwrap();
+        assert!(
+            !ComputeLeaderFinalityService::get_last_supermajority_timestamp(
+                &bank,
+                &*bank,
+                dummy_leader_id,
+                false,
+                FinalityMode::Optimistic,
+                now,
+                0
+            )
+            .confirmed
+        );
+
+        // The diagnostics leave the slashed validator out the same way
+        let slashed_node = node_id(&bank, &vote_accounts[0]);
+        let summary = bank.stake_summary();
+        assert_eq!(summary.total, Stake(2));
+        assert_eq!(summary.active, Stake(1));
+        assert_eq!(
+            bank.voters_for_tick(1),
+            vec![(node_id(&bank, &vote_accounts[1]), Stake(1))]
+        );
+        assert_eq!(
+            ComputeLeaderFinalityService::get_pivotal_validator(&bank, dummy_leader_id, false),
+            Some(node_id(&bank, &vote_accounts[2]))
+        );
+        let dump = ComputeLeaderFinalityService::debug_dump(&bank, dummy_leader_id);
+        assert!(dump.contains(&format!(
+            "{} stake=0 latest_tick=1 (not counted)",
+            slashed_node
+        )));
+        assert!(dump.contains("total voted stake=2 threshold=>1 finality_tick=none"));
+    }
+
+    /// Stake source in which one validator's stake is still cooling down
+    struct CoolingStake<'a> {
+        bank: &'a Bank,
+        node_id: Pubkey,
+        effective_stake: Stake,
+    }
+
+    impl<'a> StakeSource for CoolingStake<'a> {
+        fn stake(&self, node_id: &Pubkey) -> Stake {
+            self.bank.get_stake(node_id)
+        }
+
+        fn effective_stake(&self, node_id: &Pubkey, epoch: u64) -> Stake {
+            assert_eq!(epoch, self.bank.epoch());
+            if *node_id == self.node_id {
+                self.effective_stake
+            } else {
+                self.stake(node_id)
+            }
+        }
+    }
+
+    #[test]
+    fn test_get_last_supermajority_timestamp_effective_stake() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        // The third validator votes for a tick the bank hasn't registered yet, so the first
+        // two hold exactly, and not more than, 2/3 of the 30 tokens
+        let (bank, vote_accounts) = FinalityFixture::new(3)
+            .with_stakes(&[10, 10, 10])
+            .with_votes(&[1, 1, 100])
+            .build();
+        let bank = Arc::new(bank);
+        let now = timing::timestamp();
+        let confirmed = |stake_source: &dyn StakeSource| {
+            ComputeLeaderFinalityService::get_last_supermajority_timestamp(
+                &bank,
+                stake_source,
+                dummy_leader_id,
+                false,
+                FinalityMode::Optimistic,
+                now,
+                0,
+            )
+            .confirmed
+        };
+        assert!(!confirmed(&*bank));
+
+        // Only 4 of the third validator's tokens are still effective, so the first two hold
+        // 20 of 24 tokens
+        let vote_account = bank.get_account(&vote_accounts[2].pubkey()).unwrap();
+        let node_id = VoteProgram::deserialize(&vote_account.userdata)
+            .unwrap()
+            .node_id;
+        let cooling_stake = CoolingStake {
+            bank: &bank,
+            node_id,
+            effective_stake: Stake(4),
+        };
+        assert!(confirmed(&cooling_stake));
+    }
+
+    #[test]
+    fn test_get_last_supermajority_timestamp_confirmed() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        let (bank, _) = FinalityFixture::new(3).with_votes(&[1, 1, 1]).build();
+        let bank = Arc::new(bank);
+        let now = timing::timestamp();
+        let outcome = |mode| {
+            ComputeLeaderFinalityService::get_last_supermajority_timestamp(
+                &bank,
+                &*bank,
+                dummy_leader_id,
+                false,
+                mode,
+                now,
+                0,
+            )
+        };
+
+        // Every validator voted for tick 1, but none of those votes has been rooted yet
+        assert!(outcome(FinalityMode::Optimistic).confirmed);
+        assert!(!outcome(FinalityMode::Confirmed).confirmed);
+    }
+
+    #[test]
+    fn test_compute_finality_clustered_votes() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        // 3 validators stuck at tick 2 and 4 validators caught up to tick 6
+        let (bank, _) = FinalityFixture::new(7)
+            .with_votes(&[2, 2, 2, 6, 6, 6, 6])
+            .build();
+        let bank = Arc::new(bank);
+
+        // The 4 leading validators alone don't exceed 2/3 of the 7 total stake
+        let now = timing::timestamp();
+        let (confirmed_tick, _) =
+            ComputeLeaderFinalityService::get_confirmed_tick(&bank, dummy_leader_id, false, now)
+                .unwrap();
+        assert_eq!(confirmed_tick, 2);
+
+        let mut last_finality_time = 0;
+        ComputeLeaderFinalityService::compute_finality(
+            &bank,
+            dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
+            &mut last_finality_time,
+            &mut FinalityCache::default(),
+        );
+        // Whereas the finality timestamp is that of the first tick at which the votes up to
+        // and including it add up to a supermajority
+        assert!(bank.finality() != std::usize::MAX);
+        assert_eq!(bank.timestamp_for_tick(6), Some(last_finality_time));
+    }
+
+    #[test]
+    fn test_get_confirmed_tick() {
         solana_logger::setup();
 
         let mint = Mint::new(1234);
         let dummy_leader_id = Keypair::new().pubkey();
         let bank = Arc::new(Bank::new(&mint));
-        // generate 10 validators, but only vote for the first 6 validators
+        // Give each tick a different timestamp in the bank
+        bank.set_test_clock(0);
         let ids: Vec<_> = (0..10)
             .map(|i| {
                 let last_id = hash(&serialize(&i).unwrap()); // Unique hash
                 bank.register_tick(&last_id);
-                // sleep to get a different timestamp in the bank
-                sleep(Duration::from_millis(1));
                 last_id
             })
             .collect();
 
-        // Create a total of 10 vote accounts, each will have a balance of 1 (after giving 1 to
-        // their vote account), for a total staking pool of 10 tokens.
-        let vote_accounts: Vec<_> = (0..10)
+        // 6 validators with a stake of 1 each, voting at staggered tick heights 2..=7
+        let vote_accounts: Vec<_> = (0..6)
             .map(|i| {
-                // Create new validator to vote
                 let validator_keypair = Keypair::new();
                 let last_id = ids[i];
-
-                // Give the validator some tokens
                 bank.transfer(2, &mint.keypair(), validator_keypair.pubkey(), last_id)
                     .unwrap();
-                let vote_account = create_vote_account(&validator_keypair, &bank, 1, last_id)
-                    .expect("Expected successful creation of account");
-
-                if i < 6 {
-                    let vote = Vote {
-                        tick_height: (i + 1) as u64,
-                    };
-                    let vote_tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
-                    bank.process_transaction(&vote_tx).unwrap();
-                }
-                vote_account
+                create_vote_account(&validator_keypair, &bank, 1, last_id)
+                    .expect("Expected successful creation of account")
             })
             .collect();
 
-        // There isn't 2/3 consensus, so the bank's finality value should be the default
+        let now = timing::timestamp();
+        assert_eq!(
+            ComputeLeaderFinalityService::get_confirmed_tick(&bank, dummy_leader_id, false, now),
+            Err(FinalityError::NoValidSupermajority)
+        );
+
+        for (i, vote_account) in vote_accounts.iter().enumerate() {
+            let vote = Vote {
+                tick_height: (i + 2) as u64,
+                bank_hash: ids[i],
+            };
+            let vote_tx = Transaction::vote_new(&vote_account, vote, ids[i], 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+
+        // The supermajority is more than 4 of the 6 total stake, so the 5 validators that
+        // voted at or above tick 3 confirm it
+        let now = timing::timestamp();
+        let (confirmed_tick, finality_ms) =
+            ComputeLeaderFinalityService::get_confirmed_tick(&bank, dummy_leader_id, false, now)
+                .unwrap();
+        assert_eq!(confirmed_tick, 3);
+        assert!(finality_ms > 0);
+    }
+
+    #[test]
+    fn test_compute_finality_ignores_validators_without_votes() {
+        solana_logger::setup();
+
+        let mint = Mint::new(1234);
+        let dummy_leader_id = Keypair::new().pubkey();
+        let bank = Arc::new(Bank::new(&mint));
+        let last_id = hash(&serialize(&0).unwrap());
+        // Give the tick a different timestamp in the bank
+        bank.set_test_clock(0);
+        bank.register_tick(&last_id);
+
+        // 3 validators with a stake of 1 each vote for the latest tick
+        for _ in 0..3 {
+            let validator_keypair = Keypair::new();
+            bank.transfer(2, &mint.keypair(), validator_keypair.pubkey(), last_id)
+                .unwrap();
+            let vote_account = create_vote_account(&validator_keypair, &bank, 1, last_id)
+                .expect("Expected successful creation of account");
+            let vote = Vote {
+                tick_height: bank.tick_height(),
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+
+        // A just-registered validator holding most of the stake, that hasn't voted yet
+        let validator_keypair = Keypair::new();
+        bank.transfer(101, &mint.keypair(), validator_keypair.pubkey(), last_id)
+            .unwrap();
+        create_vote_account(&validator_keypair, &bank, 1, last_id)
+            .expect("Expected successful creation of account");
+
         let mut last_finality_time = 0;
         ComputeLeaderFinalityService::compute_finality(
             &bank,
             dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
             &mut last_finality_time,
+            &mut FinalityCache::default(),
         );
-        assert_eq!(bank.finality(), std::usize::MAX);
+        assert!(bank.finality() != std::usize::MAX);
+        assert!(last_finality_time > 0);
+    }
 
-        // Get another validator to vote, so we now have 2/3 consensus
-        let vote_account = &vote_accounts[7];
-        let vote = Vote { tick_height: 7 };
-        let vote_tx = Transaction::vote_new(&vote_account, vote, ids[6], 0);
-        bank.process_transaction(&vote_tx).unwrap();
+    #[test]
+    fn test_finality_outcome_without_timestamp() {
+        solana_logger::setup();
+
+        let mint = Mint::new(1234);
+        let dummy_leader_id = Keypair::new().pubkey();
+        let bank = Arc::new(Bank::new(&mint));
+        let last_id = bank.last_id();
+        let vote_tick_height = bank.tick_height();
+
+        // 3 validators with a stake of 1 each vote for the bank's first tick
+        for _ in 0..3 {
+            let validator_keypair = Keypair::new();
+            bank.transfer(2, &mint.keypair(), validator_keypair.pubkey(), last_id)
+                .unwrap();
+            let vote_account = create_vote_account(&validator_keypair, &bank, 1, last_id)
+                .expect("Expected successful creation of account");
+            let vote = Vote {
+                tick_height: vote_tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(&vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+        // Registering the same id again moves its entry, and timestamp, to the next tick
+        bank.register_tick(&last_id);
+
+        let now = timing::timestamp();
+        assert_eq!(
+            ComputeLeaderFinalityService::get_last_supermajority_timestamp(
+                &bank,
+                &*bank,
+                dummy_leader_id,
+                false,
+                FinalityMode::Optimistic,
+                now,
+                0
+            ),
+            FinalityOutcome {
+                confirmed: true,
+                timestamp: None,
+                tick_height: Some(vote_tick_height),
+            }
+        );
 
+        // Consensus without a timestamp leaves the finality value untouched
+        let mut last_finality_time = 0;
         ComputeLeaderFinalityService::compute_finality(
             &bank,
             dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
             &mut last_finality_time,
+            &mut FinalityCache::default(),
         );
-        assert!(bank.finality() != std::usize::MAX);
-        assert!(last_finality_time > 0);
+        assert_eq!(bank.finality(), std::usize::MAX);
+        assert_eq!(last_finality_time, 0);
+    }
+
+    /// Builds a bank where the leader holds a stake of 4 and voted at tick 5, and 3 other
+    /// validators with a stake of 1 each voted at ticks 2, 2 and 5
+    fn build_with_staked_leader() -> (Arc<Bank>, Pubkey) {
+        let (bank, vote_accounts) = FinalityFixture::new(4)
+            .with_stakes(&[1, 1, 1, 4])
+            .with_votes(&[2, 2, 5, 5])
+            .build();
+        let leader_vote_account = bank.get_account(&vote_accounts[3].pubkey()).unwrap();
+        let leader_id = VoteProgram::deserialize(&leader_vote_account.userdata)
+            .unwrap()
+            .node_id;
+        (Arc::new(bank), leader_id)
+    }
+
+    #[test]
+    fn test_compute_vote_throughput() {
+        let (bank, vote_accounts) = FinalityFixture::new(3).with_votes(&[1, 2, 3]).build();
+        let mut last_vote_ticks = HashMap::new();
+        assert_eq!(
+            ComputeLeaderFinalityService::compute_vote_throughput(&bank, &mut last_vote_ticks),
+            3
+        );
+        assert_eq!(
+            ComputeLeaderFinalityService::compute_vote_throughput(&bank, &mut last_vote_ticks),
+            0
+        );
+
+        // Two more votes by the first validator and one by the second
+        let last_id = bank.last_id();
+        for (vote_account, tick_height) in &[
+            (&vote_accounts[0], 2),
+            (&vote_accounts[0], 3),
+            (&vote_accounts[1], 3),
+        ] {
+            let vote = Vote {
+                tick_height: *tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+        assert_eq!(
+            ComputeLeaderFinalityService::compute_vote_throughput(&bank, &mut last_vote_ticks),
+            3
+        );
+    }
+
+    #[test]
+    fn test_estimated_time_to_finality() {
+        let (bank, vote_accounts) = FinalityFixture::new(6).build();
+        bank.advance_ticks(2 * VOTE_VELOCITY_WINDOW_TICKS);
+        assert_eq!(
+            ComputeLeaderFinalityService::estimated_time_to_finality(&bank),
+            None
+        );
+
+        // Each tick is 1ms apart on the test clock, and of the 6 voted stake, 5 landed within
+        // the window. 3 already voted for the latest tick, 2 more are needed to exceed 4.
+        let tick_height = bank.tick_height();
+        let last_id = bank.last_id();
+        let vote = |vote_account: &Keypair, tick_height: u64| {
+            let vote = Vote {
+                tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        };
+        let window_start = tick_height - VOTE_VELOCITY_WINDOW_TICKS;
+        for (vote_account, tick) in vote_accounts.iter().zip(&[
+            tick_height,
+            tick_height,
+            tick_height,
+            window_start + 1,
+            window_start,
+            window_start - 1,
+        ]) {
+            vote(vote_account, *tick);
+        }
+        let window_ms = VOTE_VELOCITY_WINDOW_TICKS;
+        assert_eq!(
+            ComputeLeaderFinalityService::estimated_time_to_finality(&bank),
+            Some(Duration::from_millis((2 * window_ms + 4) / 5))
+        );
+
+        // One more vote for the latest tick leaves 1 stake to go
+        vote(&vote_accounts[3], tick_height);
+        assert_eq!(
+            ComputeLeaderFinalityService::estimated_time_to_finality(&bank),
+            Some(Duration::from_millis((window_ms + 4) / 5))
+        );
+
+        // Already finalized
+        vote(&vote_accounts[4], tick_height);
+        assert_eq!(
+            ComputeLeaderFinalityService::estimated_time_to_finality(&bank),
+            None
+        );
+    }
+
+    #[test]
+    fn test_debug_dump() {
+        let (bank, leader_id) = build_with_staked_leader();
+        let dump = ComputeLeaderFinalityService::debug_dump(&bank, leader_id);
+
+        let latest_vote_ticks = bank.latest_vote_ticks(None);
+        assert_eq!(latest_vote_ticks.len(), 4);
+        for (node_id, tick_height) in latest_vote_ticks {
+            let line = format!(
+                "{} stake={} latest_tick={}",
+                node_id,
+                bank.get_stake(&node_id),
+                tick_height.unwrap()
+            );
+            assert!(dump.contains(&line), "{} missing from {}", line, dump);
+        }
+        assert!(dump.contains(&format!(
+            "{} stake=4 latest_tick=5 (leader, excluded)",
+            leader_id
+        )));
+
+        // The other validators' 3 stake has to exceed 2 to confirm a tick
+        assert!(dump.contains("total voted stake=3 threshold=>2 finality_tick="));
+    }
+
+    fn node_id(bank: &Bank, vote_account: &Keypair) -> Pubkey {
+        let account = bank.get_account(&vote_account.pubkey()).unwrap();
+        VoteProgram::deserialize(&account.userdata).unwrap().node_id
+    }
+
+    #[test]
+    fn test_get_pivotal_validator() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        // A total stake of 10, so finality takes more than 6
+        let (bank, vote_accounts) = FinalityFixture::new(4)
+            .with_stakes(&[1, 2, 3, 4])
+            .with_votes(&[2, 3, 4, 5])
+            .build();
+
+        // The cumulative stake is 1, 3, 6 and then 10 with the last validator's vote
+        assert_eq!(
+            ComputeLeaderFinalityService::get_pivotal_validator(&bank, dummy_leader_id, false),
+            Some(node_id(&bank, &vote_accounts[3]))
+        );
+
+        // Excluding the last validator as the leader leaves 6 stake, which takes more than 4
+        let leader_id = node_id(&bank, &vote_accounts[3]);
+        assert_eq!(
+            ComputeLeaderFinalityService::get_pivotal_validator(&bank, leader_id, false),
+            Some(node_id(&bank, &vote_accounts[2]))
+        );
+
+        // A vote for a future tick doesn't count, finality is missed for want of it
+        let (bank, vote_accounts) = FinalityFixture::new(4)
+            .with_stakes(&[1, 2, 3, 4])
+            .with_votes(&[2, 3, 4, 100])
+            .build();
+        assert_eq!(
+            bank.get_finality_tick(&mut bank.vote_tick_stakes(None), Stake(6)),
+            None
+        );
+        assert_eq!(
+            ComputeLeaderFinalityService::get_pivotal_validator(&bank, dummy_leader_id, false),
+            Some(node_id(&bank, &vote_accounts[3]))
+        );
+    }
+
+    #[test]
+    fn test_get_confirmed_tick_excluding_leader() {
+        let (bank, leader_id) = build_with_staked_leader();
+
+        // Only 1 of the other validators' 3 stake voted at tick 5, so tick 2 is confirmed
+        let now = timing::timestamp();
+        let (confirmed_tick, _) =
+            ComputeLeaderFinalityService::get_confirmed_tick(&bank, leader_id, false, now).unwrap();
+        assert_eq!(confirmed_tick, 2);
+    }
+
+    #[test]
+    fn test_get_confirmed_tick_including_leader() {
+        let (bank, leader_id) = build_with_staked_leader();
+
+        // With the leader's stake, 5 of the total 7 stake voted at tick 5
+        let now = timing::timestamp();
+        let (confirmed_tick, _) =
+            ComputeLeaderFinalityService::get_confirmed_tick(&bank, leader_id, true, now).unwrap();
+        assert_eq!(confirmed_tick, 5);
+    }
+
+    #[test]
+    fn test_finality_alert() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        let (bank, _) = FinalityFixture::new(3).with_votes(&[2, 2, 2]).build();
+        let bank = Arc::new(bank);
+
+        // The fixture's ticks are timestamped far in the past, so finality is well over 1ms
+        let (sender, receiver) = channel();
+        let finality_alert = FinalityAlert {
+            threshold_ms: 1,
+            on_finality: Box::new(move |finality_ms| {
+                sender.send(finality_ms).unwrap();
+            }),
+        };
+        let exit = Arc::new(AtomicBool::new(false));
+        let service = ComputeLeaderFinalityService::new(
+            bank,
+            dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
+            Some(finality_alert),
+            None,
+            exit.clone(),
+        );
+
+        let finality_ms = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(finality_ms > 1);
+
+        exit.store(true, Ordering::Relaxed);
+        service.join().unwrap();
+    }
+
+    #[test]
+    fn test_finality_subscribe() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        let (bank, _) = FinalityFixture::new(3).with_votes(&[2, 2, 2]).build();
+        let bank = Arc::new(bank);
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let service = ComputeLeaderFinalityService::new(
+            bank.clone(),
+            dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
+            None,
+            None,
+            exit.clone(),
+        );
+        let receiver = service.subscribe();
+
+        // The fixture's ticks are timestamped far in the past, so finality is well over 1ms
+        let finality_ms = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(finality_ms > 1);
+        assert_ne!(bank.finality(), std::usize::MAX);
+
+        // Receivers are disconnected once the thread exits
+        exit.store(true, Ordering::Relaxed);
+        let mut last = Ok(finality_ms);
+        while last.is_ok() {
+            last = receiver.recv_timeout(Duration::from_secs(5));
+        }
+        assert_eq!(last, Err(RecvTimeoutError::Disconnected));
+        assert_eq!(
+            service.subscribe().recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+        service.join().unwrap();
+    }
+
+    #[test]
+    fn test_finality_subscription_drops_oldest() {
+        // A subscriber that doesn't keep up only receives the latest updates
+        let subscription = FinalitySubscription::default();
+        let subscriptions = Mutex::new(Some(vec![subscription.clone()]));
+        let receiver = FinalityReceiver { subscription };
+        let capacity = FINALITY_SUBSCRIPTION_CAPACITY as u64;
+        for finality_ms in 0..2 * capacity {
+            publish_finality(&subscriptions, finality_ms);
+        }
+        let received: Vec<_> = (0..)
+            .map(|_| receiver.try_recv())
+            .take_while(Option::is_some)
+            .map(Option::unwrap)
+            .collect();
+        assert_eq!(received, (capacity..2 * capacity).collect::<Vec<_>>());
+
+        // Subscribers that went away are forgotten
+        drop(receiver);
+        publish_finality(&subscriptions, 0);
+        assert!(subscriptions.lock().unwrap().as_ref().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_finality_thread_stack_size() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        let (bank, _) = FinalityFixture::new(3).with_votes(&[2, 2, 2]).build();
+        let bank = Arc::new(bank);
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let service = ComputeLeaderFinalityService::new(
+            bank.clone(),
+            dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
+            None,
+            Some(256 * 1024),
+            exit.clone(),
+        );
+
+        let start = Instant::now();
+        while bank.finality() == std::usize::MAX {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            sleep(Duration::from_millis(COMPUTE_FINALITY_MS));
+        }
+
+        exit.store(true, Ordering::Relaxed);
+        service.join().unwrap();
+    }
+
+    #[test]
+    fn test_finality_loop_counters() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        let (bank, _) = FinalityFixture::new(3).with_votes(&[2, 2, 2]).build();
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let service = ComputeLeaderFinalityService::new(
+            Arc::new(bank),
+            dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
+            None,
+            None,
+            exit.clone(),
+        );
+
+        // Nothing lands in the bank, so every iteration after the first skips the throughput
+        let start = Instant::now();
+        while service.loop_counters().0 < 3 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            sleep(Duration::from_millis(COMPUTE_FINALITY_MS));
+        }
+        let (iterations, skipped) = service.loop_counters();
+        assert!(skipped > 0);
+        assert!(skipped < iterations);
+
+        exit.store(true, Ordering::Relaxed);
+        service.join().unwrap();
+    }
+
+    #[test]
+    fn test_finality_cadence_backs_off_slow_scans() {
+        let mut cadence = FinalityCadence::default();
+        assert_eq!(cadence.time_scan(|| 1), 1);
+        assert_eq!(cadence.interval_ms, COMPUTE_FINALITY_MS);
+        assert!(!cadence.overloaded);
+
+        // Every slow scan doubles the wait, up to the maximum
+        let slow_scan = || sleep(Duration::from_millis(COMPUTE_FINALITY_MS + 10));
+        cadence.time_scan(slow_scan);
+        assert_eq!(cadence.interval_ms, 2 * COMPUTE_FINALITY_MS);
+        assert!(cadence.overloaded);
+        cadence.time_scan(slow_scan);
+        assert_eq!(cadence.interval_ms, 4 * COMPUTE_FINALITY_MS);
+        cadence.interval_ms = MAX_COMPUTE_FINALITY_MS;
+        cadence.time_scan(slow_scan);
+        assert_eq!(cadence.interval_ms, MAX_COMPUTE_FINALITY_MS);
+        assert!(cadence.overloaded);
+
+        // A fast scan restores the normal cadence
+        cadence.time_scan(|| ());
+        assert_eq!(cadence.interval_ms, COMPUTE_FINALITY_MS);
+        assert!(!cadence.overloaded);
+    }
+
+    #[test]
+    fn test_finality_service_not_overloaded() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        let (bank, _) = FinalityFixture::new(3).with_votes(&[2, 2, 2]).build();
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let service = ComputeLeaderFinalityService::new(
+            Arc::new(bank),
+            dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
+            None,
+            None,
+            exit.clone(),
+        );
+
+        let start = Instant::now();
+        while service.loop_counters().0 < 2 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            sleep(Duration::from_millis(COMPUTE_FINALITY_MS));
+        }
+        assert!(!service.is_overloaded());
+
+        exit.store(true, Ordering::Relaxed);
+        service.join().unwrap();
+    }
+
+    #[test]
+    fn test_last_finalized_tick() {
+        let dummy_leader_id = Keypair::new().pubkey();
+        let (bank, vote_accounts) = FinalityFixture::new(3).with_votes(&[2, 2, 2]).build();
+        let bank = Arc::new(bank);
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let service = ComputeLeaderFinalityService::new(
+            bank.clone(),
+            dummy_leader_id,
+            false,
+            FinalityMode::Optimistic,
+            None,
+            None,
+            exit.clone(),
+        );
+        let wait_for = |condition: &dyn Fn() -> bool| {
+            let start = Instant::now();
+            while !condition() {
+                assert!(start.elapsed() < Duration::from_secs(5));
+                sleep(Duration::from_millis(COMPUTE_FINALITY_MS));
+            }
+        };
+        wait_for(&|| service.last_finalized_tick() == Some(2));
+
+        // Votes for a later tick advance it
+        bank.advance_ticks(1);
+        let tick_height = bank.tick_height();
+        let last_id = bank.last_id();
+        for vote_account in &vote_accounts {
+            let vote = Vote {
+                tick_height,
+                bank_hash: last_id,
+            };
+            let vote_tx = Transaction::vote_new(vote_account, vote, last_id, 0);
+            bank.process_transaction(&vote_tx).unwrap();
+        }
+        wait_for(&|| service.last_finalized_tick() == Some(tick_height));
+        let account = bank.get_account(&vote_accounts[0].pubkey()).unwrap();
+        let node_id = VoteProgram::deserialize(&account.userdata).unwrap().node_id;
+        assert_eq!(service.validator_lag(&bank, &node_id), Some(0));
+        assert_eq!(service.validator_lag(&bank, &Keypair::new().pubkey()), None);
+
+        // Once the votes are gone nothing is confirmed, but the finalized tick stays put
+        bank.clear_vote_accounts();
+        bank.advance_ticks(1);
+        let iterations = service.loop_counters().0;
+        wait_for(&|| service.loop_counters().0 > iterations + 1);
+        assert_eq!(service.last_finalized_tick(), Some(tick_height));
+
+        exit.store(true, Ordering::Relaxed);
+        service.join().unwrap();
+    }
+
+    #[test]
+    fn test_submit_vote_account_count() {
+        let (bank, _) = FinalityFixture::new(3).build();
+        assert_eq!(
+            ComputeLeaderFinalityService::submit_vote_account_count(&bank),
+            (3, 3)
+        );
+
+        // A second and third vote account for one validator only add to the account count
+        let mint = Mint::new(100);
+        let mut bank = Bank::new(&mint);
+        bank.set_max_vote_accounts(3);
+        let last_id = bank.last_id();
+        let validators = [Keypair::new(), Keypair::new()];
+        for validator in &validators {
+            bank.transfer(10, &mint.keypair(), validator.pubkey(), last_id)
+                .unwrap();
+        }
+        for validator in &[
+            &validators[0],
+            &validators[0],
+            &validators[0],
+            &validators[1],
+        ] {
+            create_vote_account(validator, &bank, 1, last_id).unwrap();
+        }
+        assert_eq!(
+            ComputeLeaderFinalityService::submit_vote_account_count(&bank),
+            (4, 2)
+        );
+
+        let point = vote_account_count_point(4, 2);
+        assert_eq!(point.measurement, "vote-account-count");
+        match (
+            point.fields.get("vote_accounts"),
+            point.fields.get("node_ids"),
+        ) {
+            (Some(influxdb::Value::Integer(4)), Some(influxdb::Value::Integer(2))) => (),
+            fields => panic!("unexpected fields {:?}", fields),
+        }
     }
 }
@@ -5,6 +5,7 @@ use crate::bank::Bank;
 
 use crate::entry::Entry;
 use crate::ledger::create_ticks;
+use crate::stake::Stake;
 use bincode::serialize;
 use byteorder::{LittleEndian, ReadBytesExt};
 use hashbrown::HashSet;
@@ -13,7 +14,7 @@ use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::{Keypair, KeypairUtil};
 use solana_sdk::system_transaction::SystemTransaction;
 use solana_sdk::transaction::Transaction;
-use solana_sdk::vote_program::{self, Vote, VoteProgram};
+use solana_sdk::vote_program::{Vote, VoteProgram};
 use solana_sdk::vote_transaction::VoteTransaction;
 use std::io::Cursor;
 
@@ -325,7 +326,7 @@ impl LeaderScheduler {
                 .accounts
                 .values()
                 .filter_map(|account| {
-                    if vote_program::check_id(&account.owner) {
+                    if account.owner == bank.vote_program_id() {
                         if let Ok(vote_state) = VoteProgram::deserialize(&account.userdata) {
                             return vote_state
                                 .votes
@@ -416,9 +417,9 @@ impl LeaderScheduler {
     {
         let mut active_accounts: Vec<(&'a Pubkey, u64)> = active
             .filter_map(|pk| {
-                let stake = bank.get_stake(pk);
+                let Stake(stake) = bank.get_stake(pk);
                 if stake > 0 {
-                    Some((pk, stake as u64))
+                    Some((pk, stake))
                 } else {
                     None
                 }
@@ -499,7 +500,10 @@ pub fn make_active_set_entries(
     last_entry_id = new_vote_account_entry.id;
 
     // 3) Create vote entry
-    let vote = Vote { tick_height: 1 };
+    let vote = Vote {
+        tick_height: 1,
+        bank_hash: *last_tick_id,
+    };
     let vote_tx = Transaction::vote_new(&vote_account, vote, *last_tick_id, 0);
     let vote_entry = Entry::new(&last_entry_id, 0, 1, vec![vote_tx]);
     last_entry_id = vote_entry.id;
@@ -540,6 +544,7 @@ mod tests {
     fn push_vote(vote_account: &Keypair, bank: &Bank, height: u64, last_id: Hash) {
         let vote = Vote {
             tick_height: height,
+            bank_hash: last_id,
         };
 
         let new_vote_tx = Transaction::vote_new(vote_account, vote, last_id, 0);
@@ -62,6 +62,7 @@ pub mod service;
 pub mod signature;
 pub mod sigverify;
 pub mod sigverify_stage;
+pub mod stake;
 pub mod status_deque;
 pub mod storage_stage;
 pub mod store_ledger_stage;
@@ -6,6 +6,7 @@ use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signature::{gen_pkcs8, Keypair, KeypairUtil};
 use solana_sdk::system_transaction::SystemTransaction;
 use solana_sdk::transaction::Transaction;
+use solana_sdk::vote_program;
 use untrusted::Input;
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -15,6 +16,10 @@ pub struct Mint {
     pub tokens: u64,
     pub bootstrap_leader_id: Pubkey,
     pub bootstrap_leader_tokens: u64,
+    /// The id the vote program is loaded under, so local test clusters sharing a process
+    /// can each use their own vote program instance
+    #[serde(default = "vote_program::id")]
+    pub vote_program_id: Pubkey,
 }
 
 impl Mint {
@@ -33,6 +38,7 @@ impl Mint {
             tokens,
             bootstrap_leader_id,
             bootstrap_leader_tokens,
+            vote_program_id: vote_program::id(),
         }
     }
 
@@ -25,6 +25,12 @@ impl Poh {
         }
     }
 
+    /// Restarts the hash chain from `id`, keeping the tick height
+    pub fn reset(&mut self, id: Hash) {
+        self.id = id;
+        self.num_hashes = 0;
+    }
+
     pub fn hash(&mut self) {
         self.id = hash(&self.id.as_ref());
         self.num_hashes += 1;
@@ -7,6 +7,10 @@ use crate::poh::Poh;
 use crate::result::{Error, Result};
 use solana_sdk::hash::Hash;
 use solana_sdk::transaction::Transaction;
+use std::borrow::Cow;
+use std::mem;
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 
@@ -14,20 +18,38 @@ use std::sync::{Arc, Mutex};
 pub enum PohRecorderError {
     InvalidCallingObject,
     MaxHeightReached,
+    /// A failure injected by `PohRecorder::fail_next_calls`
+    #[cfg(test)]
+    InjectedFailure,
 }
 
+/// Predicate deciding which transactions a `PohRecorder` accepts into its entries
+pub type TransactionFilter = Arc<dyn Fn(&Transaction) -> bool + Send + Sync>;
+
 #[derive(Clone)]
 pub struct PohRecorder {
     poh: Arc<Mutex<Poh>>,
     bank: Arc<Bank>,
     sender: Sender<Vec<Entry>>,
     max_tick_height: Option<u64>,
+    max_coalesced_transactions: Option<usize>,
+    pending_transactions: Arc<Mutex<Vec<Transaction>>>,
+    transaction_filter: Option<TransactionFilter>,
+    // Whether entries are held back and sent along with the next tick, see
+    // `set_entry_batching`
+    batch_entries: bool,
+    pending_entries: Arc<Mutex<Vec<Entry>>>,
+    // Number of upcoming `hash` and `tick` calls to fail, see `fail_next_calls`
+    #[cfg(test)]
+    injected_failures: Arc<AtomicUsize>,
 }
 
 impl PohRecorder {
     pub fn hash(&self) -> Result<()> {
         // TODO: amortize the cost of this lock by doing the loop in here for
         // some min amount of hashes
+        #[cfg(test)]
+        self.check_injected_failure()?;
         let mut poh = self.poh.lock().unwrap();
 
         self.check_tick_height(&poh)?;
@@ -37,14 +59,18 @@ impl PohRecorder {
         Ok(())
     }
 
-    pub fn tick(&mut self) -> Result<()> {
+    /// Produces a tick, returning its entry
+    pub fn tick(&mut self) -> Result<Entry> {
         // Register and send the entry out while holding the lock if the max PoH height
         // hasn't been reached.
         // This guarantees PoH order and Entry production and banks LastId queue is the same
+        #[cfg(test)]
+        self.check_injected_failure()?;
         let mut poh = self.poh.lock().unwrap();
 
         self.check_tick_height(&poh)?;
 
+        self.flush_pending_txs(&mut *poh)?;
         self.register_and_send_tick(&mut *poh)
     }
 
@@ -55,9 +81,62 @@ impl PohRecorder {
 
         self.check_tick_height(&poh)?;
 
+        if let Some(max_coalesced_transactions) = self.max_coalesced_transactions {
+            // Record an entry for every full batch of queued transactions, so none holds more
+            // than the max, and leave the rest queued
+            let full_batches = {
+                let mut pending_transactions = self.pending_transactions.lock().unwrap();
+                pending_transactions.extend(txs);
+                let full_len = pending_transactions.len() / max_coalesced_transactions
+                    * max_coalesced_transactions;
+                let rest = pending_transactions.split_off(full_len);
+                mem::replace(&mut *pending_transactions, rest)
+            };
+            let entries = full_batches
+                .chunks(max_coalesced_transactions)
+                .map(|txs| Self::record_txs(&mut *poh, Transaction::hash(txs), txs.to_vec()))
+                .collect();
+            return self.send_entries(entries);
+        }
+
         self.record_and_send_txs(&mut *poh, mixin, txs)
     }
 
+    /// Records an entry for each of `hashes_and_txs`, in order, and sends them out together
+    /// rather than one at a time. Transactions aren't coalesced.
+    pub fn record_batch(&self, hashes_and_txs: Vec<(Hash, Vec<Transaction>)>) -> Result<()> {
+        let mut poh = self.poh.lock().unwrap();
+
+        self.check_tick_height(&poh)?;
+
+        let entries = hashes_and_txs
+            .into_iter()
+            .map(|(mixin, txs)| Self::record_txs(&mut *poh, mixin, txs))
+            .collect();
+        self.send_entries(entries)
+    }
+
+    /// Sends out the entries held back since the last tick, see `set_entry_batching`
+    pub fn flush_entries(&self) -> Result<()> {
+        let _poh = self.poh.lock().unwrap();
+        let entries = mem::replace(&mut *self.pending_entries.lock().unwrap(), vec![]);
+        if entries.is_empty() {
+            return Ok(());
+        }
+        self.sender.send(entries)?;
+        Ok(())
+    }
+
+    /// Restarts PoH from `new_prev_id`, e.g. when the leader switches forks, so the next
+    /// entry chains from it. Transactions queued for coalescing belong to the abandoned
+    /// fork and are dropped.
+    pub fn reset(&self, new_prev_id: Hash) {
+        let mut poh = self.poh.lock().unwrap();
+        self.pending_transactions.lock().unwrap().clear();
+        self.pending_entries.lock().unwrap().clear();
+        poh.reset(new_prev_id);
+    }
+
     /// A recorder to synchronize PoH with the following data structures
     /// * bank - the LastId's queue is updated on `tick` and `record` events
     /// * sender - the Entry channel that outputs to the ledger
@@ -73,7 +152,89 @@ impl PohRecorder {
             bank,
             sender,
             max_tick_height,
+            max_coalesced_transactions: None,
+            pending_transactions: Arc::new(Mutex::new(vec![])),
+            transaction_filter: None,
+            batch_entries: false,
+            pending_entries: Arc::new(Mutex::new(vec![])),
+            #[cfg(test)]
+            injected_failures: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Only accept the transactions passing `filter` into entries, e.g. to have a node
+    /// specialize in recording votes
+    pub fn set_transaction_filter(&mut self, filter: TransactionFilter) {
+        self.transaction_filter = Some(filter);
+    }
+
+    /// Holds back the entries recorded between ticks and sends them along with the next tick
+    /// in a single batch, so consumers receive everything produced within a tick together
+    pub fn set_entry_batching(&mut self, batch_entries: bool) {
+        self.batch_entries = batch_entries;
+    }
+
+    /// Returns the transactions of `txs` accepted by the transaction filter. The bank
+    /// applies this before executing transactions, so that state is never committed for a
+    /// transaction that can't be recorded.
+    pub fn filter_transactions<'a>(&self, txs: &'a [Transaction]) -> Cow<'a, [Transaction]> {
+        match self.transaction_filter {
+            Some(ref filter) => Cow::Owned(txs.iter().filter(|tx| filter(tx)).cloned().collect()),
+            None => Cow::Borrowed(txs),
+        }
+    }
+
+    /// A recorder that coalesces the transactions passed to `record` into entries of at most
+    /// `max_coalesced_transactions`, each sent out once full or at the next `tick`,
+    /// whichever comes first
+    pub fn new_with_coalescing(
+        bank: Arc<Bank>,
+        sender: Sender<Vec<Entry>>,
+        last_entry_id: Hash,
+        max_tick_height: Option<u64>,
+        max_coalesced_transactions: usize,
+    ) -> Self {
+        assert!(max_coalesced_transactions > 0);
+        PohRecorder {
+            max_coalesced_transactions: Some(max_coalesced_transactions),
+            ..Self::new(bank, sender, last_entry_id, max_tick_height)
+        }
+    }
+
+    /// Number of transactions queued for coalescing into the next entry, see
+    /// `new_with_coalescing`
+    pub fn pending_len(&self) -> usize {
+        self.pending_transactions.lock().unwrap().len()
+    }
+
+    /// Removes and returns the transactions queued for coalescing, which won't be recorded
+    pub fn take_pending(&self) -> Vec<Transaction> {
+        mem::replace(&mut *self.pending_transactions.lock().unwrap(), vec![])
+    }
+
+    /// Queues `txs` for coalescing without checking the max tick height, so tests can leave
+    /// transactions behind once it is reached
+    #[cfg(test)]
+    pub fn queue_pending(&self, txs: Vec<Transaction>) {
+        self.pending_transactions.lock().unwrap().extend(txs);
+    }
+
+    /// Makes the next `count` calls to `hash` or `tick`, by this recorder or any of its
+    /// clones, fail with `PohRecorderError::InjectedFailure` without side effects
+    #[cfg(test)]
+    pub fn fail_next_calls(&self, count: usize) {
+        self.injected_failures.store(count, Ordering::Relaxed);
+    }
+
+    #[cfg(test)]
+    fn check_injected_failure(&self) -> Result<()> {
+        let failures = self.injected_failures.load(Ordering::Relaxed);
+        if failures > 0 {
+            self.injected_failures
+                .store(failures - 1, Ordering::Relaxed);
+            return Err(Error::PohRecorderError(PohRecorderError::InjectedFailure));
         }
+        Ok(())
     }
 
     fn check_tick_height(&self, poh: &Poh) -> Result<()> {
@@ -85,20 +246,45 @@ impl PohRecorder {
         }
     }
 
-    fn record_and_send_txs(&self, poh: &mut Poh, mixin: Hash, txs: Vec<Transaction>) -> Result<()> {
+    fn record_txs(poh: &mut Poh, mixin: Hash, txs: Vec<Transaction>) -> Entry {
         let entry = poh.record(mixin);
         assert!(!txs.is_empty(), "Entries without transactions are used to track real-time passing in the ledger and can only be generated with PohRecorder::tick function");
-        let entry = Entry {
+        Entry {
             tick_height: entry.tick_height,
             num_hashes: entry.num_hashes,
             id: entry.id,
             transactions: txs,
-        };
-        self.sender.send(vec![entry])?;
+        }
+    }
+
+    /// Sends `entries` out, or holds them back until the next tick when batching entries
+    fn send_entries(&self, entries: Vec<Entry>) -> Result<()> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        if self.batch_entries {
+            self.pending_entries.lock().unwrap().extend(entries);
+            return Ok(());
+        }
+        self.sender.send(entries)?;
         Ok(())
     }
 
-    fn register_and_send_tick(&self, poh: &mut Poh) -> Result<()> {
+    fn record_and_send_txs(&self, poh: &mut Poh, mixin: Hash, txs: Vec<Transaction>) -> Result<()> {
+        let entry = Self::record_txs(poh, mixin, txs);
+        self.send_entries(vec![entry])
+    }
+
+    fn flush_pending_txs(&self, poh: &mut Poh) -> Result<()> {
+        let txs = self.take_pending();
+        if txs.is_empty() {
+            return Ok(());
+        }
+        let mixin = Transaction::hash(&txs);
+        self.record_and_send_txs(poh, mixin, txs)
+    }
+
+    fn register_and_send_tick(&self, poh: &mut Poh) -> Result<Entry> {
         let tick = poh.tick();
         let tick = Entry {
             tick_height: tick.tick_height,
@@ -107,14 +293,17 @@ impl PohRecorder {
             transactions: vec![],
         };
         self.bank.register_tick(&tick.id);
-        self.sender.send(vec![tick])?;
-        Ok(())
+        let mut entries = mem::replace(&mut *self.pending_entries.lock().unwrap(), vec![]);
+        entries.push(tick.clone());
+        self.sender.send(entries)?;
+        Ok(tick)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ledger::Block;
     use crate::mint::Mint;
     use crate::test_tx::test_tx;
     use solana_sdk::hash::hash;
@@ -153,4 +342,115 @@ mod tests {
         drop(entry_receiver);
         assert!(poh_recorder.tick().is_err());
     }
+
+    #[test]
+    fn test_poh_coalescing() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let mut poh_recorder =
+            PohRecorder::new_with_coalescing(bank, entry_sender, prev_id, None, 4);
+
+        // Transactions are queued until the max is reached...
+        let h1 = hash(b"hello world!");
+        for _ in 0..5 {
+            assert!(poh_recorder.record(h1, vec![test_tx()]).is_ok());
+        }
+        let mut entries = entry_receiver.recv().unwrap();
+        assert_eq!(entries[0].transactions.len(), 4);
+        assert!(entry_receiver.try_recv().is_err());
+
+        assert_eq!(poh_recorder.pending_len(), 1);
+
+        // ...or flushed ahead of the next tick
+        assert!(poh_recorder.tick().is_ok());
+        assert_eq!(poh_recorder.pending_len(), 0);
+        entries.extend(entry_receiver.recv().unwrap());
+        entries.extend(entry_receiver.recv().unwrap());
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].transactions.len(), 1);
+        assert!(entries[2].is_tick());
+        assert!(entries.verify(&prev_id));
+    }
+
+    #[test]
+    fn test_poh_coalescing_oversized_record() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new_with_coalescing(bank, entry_sender, prev_id, None, 4);
+
+        // A record of more than the max is split into full entries, and the rest is queued
+        let h1 = hash(b"hello world!");
+        assert!(poh_recorder.record(h1, vec![test_tx()]).is_ok());
+        assert!(poh_recorder.record(h1, vec![test_tx(); 10]).is_ok());
+        let entries = entry_receiver.recv().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|entry| entry.transactions.len() == 4));
+        assert!(entries.verify(&prev_id));
+        assert!(entry_receiver.try_recv().is_err());
+        assert_eq!(poh_recorder.pending_len(), 3);
+    }
+
+    #[test]
+    fn test_poh_record_batch() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank, entry_sender, prev_id, None);
+
+        let hashes_and_txs = vec![
+            (hash(b"hello"), vec![test_tx()]),
+            (hash(b"world"), vec![test_tx(), test_tx()]),
+        ];
+        assert!(poh_recorder.record_batch(hashes_and_txs).is_ok());
+        let entries = entry_receiver.recv().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].transactions.len(), 2);
+        assert!(entries.verify(&prev_id));
+        assert!(entry_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_poh_entry_batching() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let mut poh_recorder = PohRecorder::new(bank, entry_sender, prev_id, None);
+        poh_recorder.set_entry_batching(true);
+
+        // Entries recorded within a tick are held back...
+        let h1 = hash(b"hello world!");
+        for _ in 0..3 {
+            assert!(poh_recorder.record(h1, vec![test_tx()]).is_ok());
+        }
+        assert!(poh_recorder
+            .record_batch(vec![(h1, vec![test_tx()])])
+            .is_ok());
+        assert!(entry_receiver.try_recv().is_err());
+
+        // ...and arrive together with the tick, in PoH order
+        assert!(poh_recorder.tick().is_ok());
+        let entries = entry_receiver.recv().unwrap();
+        assert_eq!(entries.len(), 5);
+        assert!(entries[..4].iter().all(|entry| !entry.is_tick()));
+        assert!(entries[4].is_tick());
+        assert!(entries.verify(&prev_id));
+        assert!(entry_receiver.try_recv().is_err());
+
+        // A tick without entries is sent on its own
+        assert!(poh_recorder.tick().is_ok());
+        assert_eq!(entry_receiver.recv().unwrap().len(), 1);
+
+        // Held back entries can also be flushed without a tick
+        assert!(poh_recorder.record(h1, vec![test_tx()]).is_ok());
+        assert!(poh_recorder.flush_entries().is_ok());
+        assert_eq!(entry_receiver.recv().unwrap().len(), 1);
+        assert!(poh_recorder.flush_entries().is_ok());
+        assert!(entry_receiver.try_recv().is_err());
+    }
 }
@@ -1,16 +1,160 @@
 //! The `poh_service` module implements a service that records the passing of
 //! "ticks", a measure of time in the PoH stream
 
-use crate::poh_recorder::PohRecorder;
-use crate::result::Result;
+use crate::bank::Bank;
+use crate::poh_recorder::{PohRecorder, PohRecorderError};
+use crate::result::{Error, Result};
 use crate::service::Service;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use solana_metrics::{influxdb, submit};
+use solana_sdk::hash::Hash;
+use solana_sdk::timing;
+use solana_sdk::transaction::Transaction;
+use std::cmp;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
 use std::thread::{self, Builder, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 pub const NUM_TICKS_PER_SECOND: usize = 10;
 
+/// Upper bounds, in milliseconds, of the `poh-tick-interval` histogram buckets. Longer
+/// intervals land in a final overflow bucket.
+const TICK_INTERVAL_BUCKETS_MS: [u64; 8] = [1, 2, 5, 10, 20, 50, 100, 500];
+
+/// How often the tick producer submits its `poh-tick-interval` histogram
+const TICK_INTERVAL_SUBMIT_MS: u64 = 10_000;
+
+/// The clock the tick producer sleeps on and reads tick intervals from, so tests can stand
+/// in for the scheduler's jitter and assert exact intervals
+trait TickClock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+struct SystemTickClock;
+
+impl TickClock for SystemTickClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        sleep(duration)
+    }
+}
+
+/// Distribution of the time between consecutive ticks, accumulated by the tick producer
+/// so that stalls show up even when the average tick rate looks fine
+struct TickIntervalHistogram {
+    // One count per bucket in TICK_INTERVAL_BUCKETS_MS, plus the overflow bucket
+    counts: [u64; 9],
+    last_tick: Option<Instant>,
+    last_submit_ms: u64,
+    // Tags the submitted histogram, see `PohService::new`
+    name_suffix: String,
+}
+
+impl TickIntervalHistogram {
+    fn new(name_suffix: String) -> Self {
+        TickIntervalHistogram {
+            counts: [0; 9],
+            last_tick: None,
+            last_submit_ms: timing::timestamp(),
+            name_suffix,
+        }
+    }
+
+    /// Records a tick at `now`, sampling the interval since the previous tick
+    fn record(&mut self, now: Instant) {
+        if let Some(last_tick) = self.last_tick {
+            let interval_ms = timing::duration_as_ms(&now.duration_since(last_tick));
+            let bucket = TICK_INTERVAL_BUCKETS_MS
+                .iter()
+                .position(|bound| interval_ms <= *bound)
+                .unwrap_or(TICK_INTERVAL_BUCKETS_MS.len());
+            self.counts[bucket] += 1;
+        }
+        self.last_tick = Some(now);
+    }
+
+    fn samples(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Submits and resets the histogram once every TICK_INTERVAL_SUBMIT_MS
+    fn maybe_submit(&mut self) {
+        let now_ms = timing::timestamp();
+        if now_ms.saturating_sub(self.last_submit_ms) < TICK_INTERVAL_SUBMIT_MS {
+            return;
+        }
+        let mut point = influxdb::Point::new("poh-tick-interval");
+        if !self.name_suffix.is_empty() {
+            point.add_tag(
+                "name_suffix",
+                influxdb::Value::String(self.name_suffix.clone()),
+            );
+        }
+        for (bound, count) in TICK_INTERVAL_BUCKETS_MS.iter().zip(self.counts.iter()) {
+            point.add_field(
+                &format!("le_{}ms", bound),
+                influxdb::Value::Integer(*count as i64),
+            );
+        }
+        point.add_field(
+            "overflow",
+            influxdb::Value::Integer(self.counts[TICK_INTERVAL_BUCKETS_MS.len()] as i64),
+        );
+        submit(point);
+        self.counts = [0; 9];
+        self.last_submit_ms = now_ms;
+    }
+}
+
+/// The tick producer retries a failed recorder call up to this many times in a row before
+/// giving up
+const MAX_RECORDER_RETRIES: usize = 3;
+
+/// The tick producer waits this long times the number of the retry before retrying a failed
+/// recorder call
+const RECORDER_RETRY_BACKOFF_MS: u64 = 10;
+
+/// Counters shared between the tick producer and its `PohService`
+struct TickStats {
+    // Timestamp in milliseconds of the most recently produced tick
+    last_tick_timestamp: AtomicUsize,
+    // Ticks that rolled all the hashes the config asked for
+    full_ticks: AtomicUsize,
+    // Ticks cut short by entries recorded since the previous tick
+    partial_ticks: AtomicUsize,
+}
+
+impl TickStats {
+    fn new() -> Self {
+        TickStats {
+            last_tick_timestamp: AtomicUsize::new(timing::timestamp() as usize),
+            full_ticks: AtomicUsize::new(0),
+            partial_ticks: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Niceness to request for the tick producer via `PohService::new_with_tick_priority`, so
+/// that it isn't starved of CPU on a loaded machine
+pub const HIGH_TICK_PRIORITY: i32 = -10;
+
+/// Sets the niceness of the calling thread, returning false if the platform or the
+/// process' privileges don't allow it
+#[cfg(target_os = "linux")]
+fn set_thread_priority(nice: i32) -> bool {
+    // On Linux niceness is a per-thread attribute, and `who == 0` is the calling thread
+    unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) == 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_thread_priority(_nice: i32) -> bool {
+    false
+}
+
 #[derive(Copy, Clone)]
 pub enum Config {
     /// * `Tick` - Run full PoH thread.  Tick is a rough estimate of how many hashes to roll before transmitting a new entry.
@@ -18,6 +162,26 @@ pub enum Config {
     /// * `Sleep`- Low power mode.  Sleep is a rough estimate of how long to sleep before rolling 1 poh once and producing 1
     /// tick.
     Sleep(Duration),
+    /// * `RampedTick` - Like `Tick`, but the number of hashes per tick ramps up linearly to
+    /// `num_hashes` over the first `warmup_ticks` ticks, so a freshly started node doesn't stall
+    /// for a full round of hashing before producing its first tick.
+    RampedTick {
+        num_hashes: usize,
+        warmup_ticks: usize,
+    },
+    /// * `TickCount` - Like `Tick`, but the tick producer exits cleanly once it has produced
+    /// `max_ticks` ticks, for deterministic tests and bounded simulations.
+    TickCount(usize, usize),
+    /// * `AdaptiveTick` - Like `Tick`, but a leader under load stretches its ticks to pack
+    /// more transactions into each entry. The number of hashes per tick grows linearly with
+    /// the transactions queued in the recorder (see `PohRecorder::new_with_coalescing`) at the
+    /// start of the tick, from `num_hashes` when the queue is empty up to `max_hashes` once
+    /// `queue_depth` or more are queued, and never leaves that range.
+    AdaptiveTick {
+        num_hashes: usize,
+        max_hashes: usize,
+        queue_depth: usize,
+    },
 }
 
 impl Default for Config {
@@ -27,9 +191,33 @@ impl Default for Config {
     }
 }
 
+impl Config {
+    /// The number of hashes per second PoH would roll to produce NUM_TICKS_PER_SECOND ticks,
+    /// for estimating the CPU load before starting a node. `RampedTick` is rated once warmed
+    /// up and `AdaptiveTick` at its most stretched ticks. None for `Sleep`, which rolls a
+    /// single hash per tick and spends the rest of the tick sleeping.
+    pub fn effective_hashes_per_second(&self) -> Option<u64> {
+        let num_hashes = match *self {
+            Config::Tick(num_hashes)
+            | Config::TickCount(num_hashes, _)
+            | Config::RampedTick { num_hashes, .. } => num_hashes,
+            Config::AdaptiveTick {
+                num_hashes,
+                max_hashes,
+                ..
+            } => cmp::max(num_hashes, max_hashes),
+            Config::Sleep(_) => return None,
+        };
+        Some((num_hashes as u64).saturating_mul(NUM_TICKS_PER_SECOND as u64))
+    }
+}
+
 pub struct PohService {
-    tick_producer: JoinHandle<Result<()>>,
+    tick_producer: JoinHandle<(Result<()>, Vec<Transaction>)>,
     pub poh_exit: Arc<AtomicBool>,
+    tick_stats: Arc<TickStats>,
+    // Id to restart PoH from before the next tick, see `reset`
+    pending_reset: Arc<Mutex<Option<Hash>>>,
 }
 
 impl PohService {
@@ -37,47 +225,231 @@ impl PohService {
         self.poh_exit.store(true, Ordering::Relaxed);
     }
 
-    pub fn close(self) -> thread::Result<Result<()>> {
+    pub fn close(self) -> thread::Result<(Result<()>, Vec<Transaction>)> {
         self.exit();
         self.join()
     }
 
-    pub fn new(poh_recorder: PohRecorder, config: Config) -> Self {
+    /// Restarts PoH from `new_prev_id`, e.g. when the leader switches forks. The tick
+    /// producer applies the reset before starting on its next tick rather than midway
+    /// through a batch of hashes.
+    pub fn reset(&self, new_prev_id: Hash) {
+        *self.pending_reset.lock().unwrap() = Some(new_prev_id);
+    }
+
+    /// Returns true if the tick producer is running and has produced a tick within
+    /// the last `max_idle`
+    pub fn is_healthy(&self, max_idle: Duration) -> bool {
+        if self.poh_exit.load(Ordering::Relaxed) {
+            return false;
+        }
+        let last_tick_timestamp = self.tick_stats.last_tick_timestamp.load(Ordering::Relaxed);
+        timing::timestamp().saturating_sub(last_tick_timestamp as u64)
+            <= timing::duration_as_ms(&max_idle)
+    }
+
+    /// Returns the number of full and partial ticks produced so far. A tick is partial when
+    /// entries recorded since the previous tick took some of its hashes, e.g. when a tick
+    /// flushes coalesced transactions. Many partial ticks suggest the number of hashes per
+    /// tick is too small for the transaction load.
+    pub fn tick_counts(&self) -> (usize, usize) {
+        (
+            self.tick_stats.full_ticks.load(Ordering::Relaxed),
+            self.tick_stats.partial_ticks.load(Ordering::Relaxed),
+        )
+    }
+
+    /// * `name_suffix` - Appended to the tick producer's thread name and tagged on its
+    /// metrics, to tell apart several services running in the same process
+    pub fn new(poh_recorder: PohRecorder, config: Config, name_suffix: String) -> Self {
+        Self::new_with_tick_priority(poh_recorder, config, None, name_suffix)
+    }
+
+    /// Like `new`, but runs the tick producer at the given niceness (e.g. `HIGH_TICK_PRIORITY`)
+    /// where the platform allows it, to reduce tick jitter under load
+    pub fn new_with_tick_priority(
+        poh_recorder: PohRecorder,
+        config: Config,
+        tick_priority: Option<i32>,
+        name_suffix: String,
+    ) -> Self {
+        Self::spawn(poh_recorder, config, tick_priority, name_suffix, None)
+    }
+
+    /// Like `new`, but the tick producer also registers every tick it produces with `bank`,
+    /// so a single node doesn't need to replay its own entries into it. Ticks `bank` already
+    /// knows of, e.g. because it's the bank of `poh_recorder`, aren't registered twice.
+    pub fn new_with_bank(poh_recorder: PohRecorder, config: Config, bank: Arc<Bank>) -> Self {
+        Self::spawn(poh_recorder, config, None, String::new(), Some(bank))
+    }
+
+    fn spawn(
+        poh_recorder: PohRecorder,
+        config: Config,
+        tick_priority: Option<i32>,
+        name_suffix: String,
+        bank: Option<Arc<Bank>>,
+    ) -> Self {
         // PohService is a headless producer, so when it exits it should notify the banking stage.
         // Since channel are not used to talk between these threads an AtomicBool is used as a
         // signal.
         let poh_exit = Arc::new(AtomicBool::new(false));
         let poh_exit_ = poh_exit.clone();
+        let tick_stats = Arc::new(TickStats::new());
+        let tick_stats_ = tick_stats.clone();
+        let pending_reset = Arc::new(Mutex::new(None));
+        let pending_reset_ = pending_reset.clone();
         // Single thread to generate ticks
         let tick_producer = Builder::new()
-            .name("solana-poh-service-tick_producer".to_string())
+            .name(format!("solana-poh-service-tick_producer{}", name_suffix))
             .spawn(move || {
+                if let Some(nice) = tick_priority {
+                    if !set_thread_priority(nice) {
+                        warn!("unable to set tick producer priority to {}", nice);
+                    }
+                }
                 let mut poh_recorder_ = poh_recorder;
-                let return_value = Self::tick_producer(&mut poh_recorder_, config, &poh_exit_);
+                let mut tick_intervals = TickIntervalHistogram::new(name_suffix);
+                let return_value = Self::tick_producer(
+                    &mut poh_recorder_,
+                    config,
+                    &poh_exit_,
+                    &tick_stats_,
+                    &pending_reset_,
+                    &mut tick_intervals,
+                    bank.as_ref().map(|bank| &**bank),
+                    &SystemTickClock,
+                );
                 poh_exit_.store(true, Ordering::Relaxed);
-                return_value
+                // Hand back the transactions queued since the last tick, so the caller can
+                // requeue them elsewhere. The producer usually ends by reaching the max tick
+                // height, which fails before the next tick would have recorded them.
+                (return_value, poh_recorder_.take_pending())
             })
             .unwrap();
 
         Self {
             tick_producer,
             poh_exit,
+            tick_stats,
+            pending_reset,
+        }
+    }
+
+    /// Number of hashes to roll for the `tick_count`th tick while warming up
+    fn ramped_hashes(num_hashes: usize, warmup_ticks: usize, tick_count: usize) -> usize {
+        if tick_count >= warmup_ticks {
+            num_hashes
+        } else {
+            cmp::max(1, num_hashes * (tick_count + 1) / (warmup_ticks + 1))
         }
     }
 
-    fn tick_producer(poh: &mut PohRecorder, config: Config, poh_exit: &AtomicBool) -> Result<()> {
+    /// Number of hashes to roll for a tick started with `queue_len` queued transactions,
+    /// see `Config::AdaptiveTick`
+    fn adaptive_hashes(
+        num_hashes: usize,
+        max_hashes: usize,
+        queue_depth: usize,
+        queue_len: usize,
+    ) -> usize {
+        let max_hashes = cmp::max(num_hashes, max_hashes);
+        let queue_depth = cmp::max(1, queue_depth);
+        let queue_len = cmp::min(queue_len, queue_depth);
+        num_hashes + (max_hashes - num_hashes) * queue_len / queue_depth
+    }
+
+    /// Calls `f` until it succeeds, backing off between attempts, unless it fails with an
+    /// error retrying can't fix or more than MAX_RECORDER_RETRIES times in a row
+    fn with_retries<T>(mut f: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut retries = 0;
         loop {
-            match config {
-                Config::Tick(num) => {
-                    for _ in 1..num {
-                        poh.hash()?;
-                    }
+            let result = f();
+            match result {
+                // Retrying won't lift the max tick height or bring back the entry receiver
+                Err(Error::PohRecorderError(PohRecorderError::MaxHeightReached))
+                | Err(Error::SendError) => return result,
+                Err(ref err) if retries < MAX_RECORDER_RETRIES => {
+                    retries += 1;
+                    warn!(
+                        "poh recorder error {:?}, retry {} of {}",
+                        err, retries, MAX_RECORDER_RETRIES
+                    );
+                    sleep(Duration::from_millis(
+                        RECORDER_RETRY_BACKOFF_MS * retries as u64,
+                    ));
                 }
+                _ => return result,
+            }
+        }
+    }
+
+    fn tick_producer(
+        poh: &mut PohRecorder,
+        config: Config,
+        poh_exit: &AtomicBool,
+        tick_stats: &TickStats,
+        pending_reset: &Mutex<Option<Hash>>,
+        tick_intervals: &mut TickIntervalHistogram,
+        bank: Option<&Bank>,
+        clock: &dyn TickClock,
+    ) -> Result<()> {
+        let mut tick_count = 0;
+        loop {
+            if let Some(new_prev_id) = pending_reset.lock().unwrap().take() {
+                poh.reset(new_prev_id);
+            }
+            let num_hashes = match config {
+                Config::Tick(num) | Config::TickCount(num, _) => num,
                 Config::Sleep(duration) => {
-                    sleep(duration);
+                    clock.sleep(duration);
+                    1
+                }
+                Config::RampedTick {
+                    num_hashes,
+                    warmup_ticks,
+                } => Self::ramped_hashes(num_hashes, warmup_ticks, tick_count),
+                Config::AdaptiveTick {
+                    num_hashes,
+                    max_hashes,
+                    queue_depth,
+                } => Self::adaptive_hashes(num_hashes, max_hashes, queue_depth, poh.pending_len()),
+            };
+            for _ in 1..num_hashes {
+                Self::with_retries(|| poh.hash())?;
+            }
+            // Stop short of the next tick, which would record the queued transactions, but
+            // send out the entries already recorded
+            if poh_exit.load(Ordering::Relaxed) {
+                debug!("tick service exited");
+                return poh.flush_entries();
+            }
+            let tick = Self::with_retries(|| poh.tick())?;
+            // Taken before `bank` is locked, so the tick is timestamped when PoH produced it
+            // rather than once the bank's other users let go of it
+            let tick_timestamp = timing::timestamp();
+            if let Some(bank) = bank {
+                if bank.last_id() != tick.id {
+                    bank.register_tick_at(&tick.id, tick_timestamp);
+                }
+            }
+            if tick.num_hashes < cmp::max(1, num_hashes) as u64 {
+                tick_stats.partial_ticks.fetch_add(1, Ordering::Relaxed);
+            } else {
+                tick_stats.full_ticks.fetch_add(1, Ordering::Relaxed);
+            }
+            tick_stats
+                .last_tick_timestamp
+                .store(tick_timestamp as usize, Ordering::Relaxed);
+            tick_intervals.record(clock.now());
+            tick_intervals.maybe_submit();
+            tick_count += 1;
+            if let Config::TickCount(_, max_ticks) = config {
+                if tick_count >= max_ticks {
+                    debug!("tick service produced {} ticks", tick_count);
+                    return Ok(());
                 }
             }
-            poh.tick()?;
             if poh_exit.load(Ordering::Relaxed) {
                 debug!("tick service exited");
                 return Ok(());
@@ -87,27 +459,36 @@ impl PohService {
 }
 
 impl Service for PohService {
-    type JoinReturnType = Result<()>;
+    /// How the tick producer exited, along with the transactions queued in the recorder but
+    /// not yet recorded when it did, whether or not it exited cleanly
+    type JoinReturnType = (Result<()>, Vec<Transaction>);
 
-    fn join(self) -> thread::Result<Result<()>> {
+    fn join(self) -> thread::Result<(Result<()>, Vec<Transaction>)> {
         self.tick_producer.join()
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Config, PohService};
+    use super::{
+        Config, PohService, TickClock, TickIntervalHistogram, TickStats, HIGH_TICK_PRIORITY,
+        MAX_RECORDER_RETRIES, NUM_TICKS_PER_SECOND, RECORDER_RETRY_BACKOFF_MS,
+    };
     use crate::bank::Bank;
     use crate::mint::Mint;
-    use crate::poh_recorder::PohRecorder;
-    use crate::result::Result;
+    use crate::poh_recorder::{PohRecorder, PohRecorderError};
+    use crate::result::{Error, Result};
     use crate::service::Service;
     use crate::test_tx::test_tx;
     use solana_sdk::hash::hash;
+    use solana_sdk::timing;
+    use std::cell::{Cell, RefCell};
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::mpsc::channel;
-    use std::sync::Arc;
-    use std::thread::{Builder, JoinHandle};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{sleep, Builder, JoinHandle};
+    use std::time::{Duration, Instant};
+    use std::vec;
 
     #[test]
     fn test_poh_service() {
@@ -140,7 +521,11 @@ mod tests {
         };
 
         const HASHES_PER_TICK: u64 = 2;
-        let poh_service = PohService::new(poh_recorder, Config::Tick(HASHES_PER_TICK as usize));
+        let poh_service = PohService::new(
+            poh_recorder,
+            Config::Tick(HASHES_PER_TICK as usize),
+            String::new(),
+        );
 
         // get some events
         let mut hashes = 0;
@@ -177,4 +562,565 @@ mod tests {
         assert!(entry_producer.join().is_ok());
     }
 
+    #[test]
+    fn test_poh_service_ramped_tick() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank, entry_sender, prev_id, None);
+
+        const HASHES_PER_TICK: u64 = 16;
+        let poh_service = PohService::new(
+            poh_recorder,
+            Config::RampedTick {
+                num_hashes: HASHES_PER_TICK as usize,
+                warmup_ticks: 4,
+            },
+            String::new(),
+        );
+
+        let tick_hashes: Vec<_> = entry_receiver
+            .iter()
+            .flatten()
+            .take(8)
+            .map(|entry| {
+                assert!(entry.is_tick());
+                entry.num_hashes
+            })
+            .collect();
+        // Early ticks roll fewer hashes, converging on HASHES_PER_TICK
+        assert_eq!(tick_hashes, vec![3, 6, 9, 12, 16, 16, 16, 16]);
+
+        assert!(poh_service.close().is_ok());
+    }
+
+    #[test]
+    fn test_adaptive_hashes() {
+        // Bounded by num_hashes and max_hashes whatever the queue depth
+        assert_eq!(PohService::adaptive_hashes(4, 12, 8, 0), 4);
+        assert_eq!(PohService::adaptive_hashes(4, 12, 8, 4), 8);
+        assert_eq!(PohService::adaptive_hashes(4, 12, 8, 8), 12);
+        assert_eq!(PohService::adaptive_hashes(4, 12, 8, 1000), 12);
+
+        // Misconfigured bounds don't stretch ticks in either direction
+        assert_eq!(PohService::adaptive_hashes(4, 2, 8, 1000), 4);
+        assert_eq!(PohService::adaptive_hashes(4, 12, 0, 0), 4);
+    }
+
+    #[test]
+    fn test_effective_hashes_per_second() {
+        assert_eq!(
+            Config::Tick(1000).effective_hashes_per_second(),
+            Some(1000 * NUM_TICKS_PER_SECOND as u64)
+        );
+        assert_eq!(
+            Config::TickCount(2, 5).effective_hashes_per_second(),
+            Some(2 * NUM_TICKS_PER_SECOND as u64)
+        );
+        let ramped_tick = Config::RampedTick {
+            num_hashes: 50,
+            warmup_ticks: 10,
+        };
+        assert_eq!(
+            ramped_tick.effective_hashes_per_second(),
+            Some(50 * NUM_TICKS_PER_SECOND as u64)
+        );
+        let adaptive_tick = Config::AdaptiveTick {
+            num_hashes: 4,
+            max_hashes: 12,
+            queue_depth: 8,
+        };
+        assert_eq!(
+            adaptive_tick.effective_hashes_per_second(),
+            Some(12 * NUM_TICKS_PER_SECOND as u64)
+        );
+        assert_eq!(Config::default().effective_hashes_per_second(), None);
+    }
+
+    #[test]
+    fn test_poh_service_adaptive_tick() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let max_tick_height = Some(bank.tick_height() + 2);
+        let mut poh_recorder =
+            PohRecorder::new_with_coalescing(bank, entry_sender, prev_id, max_tick_height, 100);
+
+        // A deep queue stretches the first tick, which flushes the queue
+        for _ in 0..10 {
+            poh_recorder.record(hash(b"load"), vec![test_tx()]).unwrap();
+        }
+        let config = Config::AdaptiveTick {
+            num_hashes: 2,
+            max_hashes: 8,
+            queue_depth: 10,
+        };
+        // The recorder stops ticking at its max tick height
+        assert!(PohService::tick_producer(
+            &mut poh_recorder,
+            config,
+            &AtomicBool::new(false),
+            &TickStats::new(),
+            &Mutex::new(None),
+            &mut TickIntervalHistogram::new(String::new()),
+            None,
+            &SystemTickClock,
+        )
+        .is_err());
+
+        // Count the hashes of each tick, including those rolled before recorded entries
+        let mut tick_hashes = vec![];
+        let mut hashes = 0;
+        for entry in entry_receiver.try_iter().flatten() {
+            if entry.is_tick() {
+                tick_hashes.push(hashes + entry.num_hashes);
+                hashes = 0;
+            } else {
+                hashes += entry.num_hashes - 1;
+            }
+        }
+        assert_eq!(tick_hashes, vec![8, 2]);
+    }
+
+    #[test]
+    fn test_poh_service_tick_count() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank, entry_sender, prev_id, None);
+        let poh_service = PohService::new(poh_recorder, Config::TickCount(2, 5), String::new());
+
+        // The recorder's sender is dropped once the tick producer exits, ending the iteration
+        let entries: Vec<_> = entry_receiver.iter().flatten().collect();
+        assert_eq!(entries.len(), 5);
+        assert!(entries.iter().all(|entry| entry.is_tick()));
+
+        assert!(poh_service.join().unwrap().0.is_ok());
+    }
+
+    #[test]
+    fn test_poh_service_tick_counts() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let max_tick_height = Some(bank.tick_height() + 3);
+        let mut poh_recorder =
+            PohRecorder::new_with_coalescing(bank, entry_sender, prev_id, max_tick_height, 100);
+
+        // The first tick flushes the queued transactions, which take most of its hashes
+        poh_recorder.record(hash(b"load"), vec![test_tx()]).unwrap();
+        let tick_stats = TickStats::new();
+        assert!(PohService::tick_producer(
+            &mut poh_recorder,
+            Config::Tick(4),
+            &AtomicBool::new(false),
+            &tick_stats,
+            &Mutex::new(None),
+            &mut TickIntervalHistogram::new(String::new()),
+            None,
+            &SystemTickClock,
+        )
+        .is_err());
+
+        let ticks: Vec<_> = entry_receiver
+            .try_iter()
+            .flatten()
+            .filter(|entry| entry.is_tick())
+            .collect();
+        let full_ticks = ticks.iter().filter(|tick| tick.num_hashes == 4).count();
+        assert_eq!(ticks.len(), 3);
+        assert_eq!(full_ticks, 2);
+        assert_eq!(tick_stats.full_ticks.load(Ordering::Relaxed), full_ticks);
+        assert_eq!(
+            tick_stats.partial_ticks.load(Ordering::Relaxed),
+            ticks.len() - full_ticks
+        );
+
+        // Without any entries recorded, every tick is full
+        let (entry_sender, entry_receiver) = channel();
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let poh_recorder = PohRecorder::new(bank, entry_sender, prev_id, None);
+        let poh_service = PohService::new(poh_recorder, Config::TickCount(2, 5), String::new());
+        let entries: Vec<_> = entry_receiver.iter().flatten().collect();
+        assert!(entries.iter().all(|entry| entry.num_hashes == 2));
+        assert_eq!(poh_service.tick_counts(), (entries.len(), 0));
+        assert!(poh_service.join().unwrap().0.is_ok());
+    }
+
+    #[test]
+    fn test_poh_service_recovers_from_recorder_errors() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank.clone(), entry_sender, prev_id, None);
+
+        // Fail the first hash and all but the last of its retries
+        poh_recorder.fail_next_calls(MAX_RECORDER_RETRIES);
+        let poh_service = PohService::new(poh_recorder, Config::TickCount(2, 5), String::new());
+        let entries: Vec<_> = entry_receiver.iter().flatten().collect();
+        assert!(poh_service.join().unwrap().0.is_ok());
+        assert_eq!(entries.len(), 5);
+        assert!(entries
+            .iter()
+            .all(|entry| entry.is_tick() && entry.num_hashes == 2));
+
+        // One more failure in a row and the tick producer gives up
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank.clone(), entry_sender, bank.last_id(), None);
+        poh_recorder.fail_next_calls(MAX_RECORDER_RETRIES + 1);
+        let poh_service = PohService::new(poh_recorder, Config::TickCount(2, 5), String::new());
+        assert_eq!(entry_receiver.iter().count(), 0);
+        assert!(poh_service.join().unwrap().0.is_err());
+    }
+
+    #[test]
+    fn test_poh_service_tick_intervals() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, _entry_receiver) = channel();
+        let mut poh_recorder = PohRecorder::new(bank, entry_sender, prev_id, None);
+
+        let mut tick_intervals = TickIntervalHistogram::new(String::new());
+        PohService::tick_producer(
+            &mut poh_recorder,
+            Config::TickCount(2, 5),
+            &AtomicBool::new(false),
+            &TickStats::new(),
+            &Mutex::new(None),
+            &mut tick_intervals,
+            None,
+            &SystemTickClock,
+        )
+        .unwrap();
+
+        // One sample per interval between the 5 ticks
+        assert_eq!(tick_intervals.samples(), 4);
+    }
+
+    #[test]
+    fn test_poh_service_with_bank() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+
+        // A bank other than the recorder's, which only learns of ticks from the service
+        let other_bank = Arc::new(Bank::new(&mint));
+        let tick_height = other_bank.tick_height();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank.clone(), entry_sender, prev_id, None);
+        let poh_service =
+            PohService::new_with_bank(poh_recorder, Config::TickCount(2, 5), other_bank.clone());
+        let entries: Vec<_> = entry_receiver.iter().flatten().collect();
+        assert!(poh_service.join().unwrap().0.is_ok());
+        assert_eq!(other_bank.tick_height(), tick_height + 5);
+        assert_eq!(other_bank.last_id(), entries.last().unwrap().id);
+
+        // The recorder already registers ticks with its own bank
+        let tick_height = bank.tick_height();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank.clone(), entry_sender, bank.last_id(), None);
+        let poh_service =
+            PohService::new_with_bank(poh_recorder, Config::TickCount(2, 5), bank.clone());
+        let entries: Vec<_> = entry_receiver.iter().flatten().collect();
+        assert!(poh_service.join().unwrap().0.is_ok());
+        assert_eq!(bank.tick_height(), tick_height + 5);
+        assert_eq!(bank.last_id(), entries.last().unwrap().id);
+    }
+
+    #[test]
+    fn test_poh_service_with_bank_tick_timestamps() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let other_bank = Arc::new(Bank::new(&mint));
+        let tick_height = other_bank.tick_height();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank.clone(), entry_sender, prev_id, None);
+
+        let start = timing::timestamp();
+        let poh_service = PohService::new_with_bank(
+            poh_recorder,
+            Config::Sleep(Duration::from_millis(10)),
+            other_bank.clone(),
+        );
+        while other_bank.tick_height() < tick_height + 5 {
+            sleep(Duration::from_millis(1));
+        }
+        assert!(poh_service.close().unwrap().0.is_ok());
+        drop(entry_receiver);
+        let end = timing::timestamp();
+
+        // The recorder's bank timestamps each tick as PoH produces it, the other bank should
+        // agree to within scheduling noise
+        for tick_height in tick_height + 1..=tick_height + 5 {
+            let produced = bank.timestamp_for_tick(tick_height).unwrap();
+            let registered = other_bank.timestamp_for_tick(tick_height).unwrap();
+            assert!(start <= registered && registered <= end);
+            assert!(
+                (registered as i64 - produced as i64).abs() <= 50,
+                "tick {} produced at {}, registered at {}",
+                tick_height,
+                produced,
+                registered
+            );
+        }
+    }
+
+    #[test]
+    fn test_poh_service_reset() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank, entry_sender, prev_id, None);
+
+        // Sleep ticks roll a single hash each
+        let poh_service = PohService::new(
+            poh_recorder,
+            Config::Sleep(Duration::from_millis(1)),
+            String::new(),
+        );
+        entry_receiver.recv().unwrap();
+
+        let new_prev_id = hash(b"fork");
+        poh_service.reset(new_prev_id);
+
+        // Skip the ticks produced before the reset took effect
+        let mut last_id = hash(new_prev_id.as_ref());
+        entry_receiver
+            .iter()
+            .flatten()
+            .find(|entry| entry.id == last_id)
+            .unwrap();
+        for entry in entry_receiver.iter().flatten().take(3) {
+            assert_eq!(entry.num_hashes, 1);
+            assert_eq!(entry.id, hash(last_id.as_ref()));
+            last_id = entry.id;
+        }
+
+        assert!(poh_service.close().is_ok());
+    }
+
+    #[test]
+    fn test_poh_service_name_suffix() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+
+        // Keep the receivers alive, or the tick producers exit with an error on their first tick
+        let (poh_services, _entry_receivers): (Vec<_>, Vec<_>) = ["-a", "-b"]
+            .iter()
+            .map(|name_suffix| {
+                let (entry_sender, entry_receiver) = channel();
+                let poh_recorder = PohRecorder::new(bank.clone(), entry_sender, prev_id, None);
+                let poh_service = PohService::new(
+                    poh_recorder,
+                    Config::TickCount(2, 1),
+                    name_suffix.to_string(),
+                );
+                (poh_service, entry_receiver)
+            })
+            .unzip();
+
+        let thread_names: Vec<_> = poh_services
+            .iter()
+            .map(|poh_service| {
+                poh_service
+                    .tick_producer
+                    .thread()
+                    .name()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            thread_names,
+            vec![
+                "solana-poh-service-tick_producer-a",
+                "solana-poh-service-tick_producer-b"
+            ]
+        );
+
+        for poh_service in poh_services {
+            poh_service.join().unwrap().0.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_poh_service_returns_pending_transactions() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new_with_coalescing(bank, entry_sender, prev_id, None, 100);
+        let poh_service = PohService::new(
+            poh_recorder.clone(),
+            Config::Sleep(Duration::from_millis(200)),
+            String::new(),
+        );
+
+        // Queue transactions right after a tick, while the tick producer sleeps, and exit
+        // before the next tick records them
+        entry_receiver.recv().unwrap();
+        let txs = vec![test_tx(), test_tx()];
+        poh_recorder.record(hash(b"pending"), txs.clone()).unwrap();
+        poh_service.exit();
+
+        let (result, pending) = poh_service.join().unwrap();
+        assert!(result.is_ok());
+        assert_eq!(pending, txs);
+        assert!(entry_receiver
+            .try_iter()
+            .flatten()
+            .all(|entry| entry.is_tick()));
+    }
+
+    #[test]
+    fn test_poh_service_returns_pending_transactions_at_max_tick_height() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let max_tick_height = Some(bank.tick_height() + 1);
+        let poh_recorder =
+            PohRecorder::new_with_coalescing(bank, entry_sender, prev_id, max_tick_height, 100);
+        let poh_service = PohService::new(
+            poh_recorder.clone(),
+            Config::Sleep(Duration::from_millis(200)),
+            String::new(),
+        );
+
+        // Left behind by the last tick, the next one fails on the max tick height instead of
+        // recording them
+        entry_receiver.recv().unwrap();
+        let txs = vec![test_tx(), test_tx()];
+        poh_recorder.queue_pending(txs.clone());
+
+        let (result, pending) = poh_service.join().unwrap();
+        assert_matches!(
+            result,
+            Err(Error::PohRecorderError(PohRecorderError::MaxHeightReached))
+        );
+        assert_eq!(pending, txs);
+    }
+
+    #[test]
+    fn test_poh_service_is_healthy() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank.clone(), entry_sender, prev_id, None);
+        let poh_service = PohService::new(poh_recorder, Config::Tick(2), String::new());
+        entry_receiver.recv().unwrap();
+        assert!(poh_service.is_healthy(Duration::from_secs(1)));
+        assert!(poh_service.close().is_ok());
+
+        // A recorder that stalls while the tick producer is still running, backing off
+        // for RECORDER_RETRY_BACKOFF_MS * (1 + .. + MAX_RECORDER_RETRIES) before recovering
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank.clone(), entry_sender, prev_id, None);
+        let poh_service = PohService::new(poh_recorder.clone(), Config::Tick(2), String::new());
+        entry_receiver.recv().unwrap();
+        poh_recorder.fail_next_calls(MAX_RECORDER_RETRIES);
+        sleep(Duration::from_millis(RECORDER_RETRY_BACKOFF_MS * 2));
+        assert!(!poh_service.is_healthy(Duration::from_millis(RECORDER_RETRY_BACKOFF_MS)));
+        // Ticks resume once the last retry succeeds
+        while !poh_service.is_healthy(Duration::from_millis(RECORDER_RETRY_BACKOFF_MS)) {
+            entry_receiver.recv().unwrap();
+        }
+        assert!(poh_service.close().is_ok());
+
+        // A recorder that stops ticking once it reaches its max tick height
+        let (entry_sender, entry_receiver) = channel();
+        let max_tick_height = Some(bank.tick_height() + 2);
+        let poh_recorder = PohRecorder::new(bank, entry_sender, prev_id, max_tick_height);
+        let poh_service = PohService::new(poh_recorder, Config::Tick(2), String::new());
+        assert_eq!(entry_receiver.iter().flatten().count(), 2);
+        sleep(Duration::from_millis(20));
+        assert!(!poh_service.is_healthy(Duration::from_millis(10)));
+        assert!(poh_service.join().unwrap().0.is_err());
+    }
+
+    /// Advances by the requested duration plus the next of `delays` on every sleep, standing
+    /// in for a scheduler that wakes the tick producer late
+    struct JitterClock {
+        now: Cell<Instant>,
+        delays: RefCell<vec::IntoIter<Duration>>,
+    }
+
+    impl TickClock for JitterClock {
+        fn now(&self) -> Instant {
+            self.now.get()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            let delay = self.delays.borrow_mut().next().unwrap_or_default();
+            self.now.set(self.now.get() + duration + delay);
+        }
+    }
+
+    #[test]
+    fn test_poh_service_tick_jitter() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, _entry_receiver) = channel();
+        let max_tick_height = Some(bank.tick_height() + 6);
+        let mut poh_recorder = PohRecorder::new(bank, entry_sender, prev_id, max_tick_height);
+
+        // Every sleep but the one before the first tick wakes up late, so the 5 intervals
+        // between the 6 ticks are 1, 2, 5, 10 and 20ms, one at each of the lowest bounds
+        let clock = JitterClock {
+            now: Cell::new(Instant::now()),
+            delays: RefCell::new(
+                [0, 0, 1, 4, 9, 19]
+                    .iter()
+                    .map(|ms| Duration::from_millis(*ms))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            ),
+        };
+        let mut tick_intervals = TickIntervalHistogram::new(String::new());
+        assert!(PohService::tick_producer(
+            &mut poh_recorder,
+            Config::Sleep(Duration::from_millis(1)),
+            &AtomicBool::new(false),
+            &TickStats::new(),
+            &Mutex::new(None),
+            &mut tick_intervals,
+            None,
+            &clock,
+        )
+        .is_err());
+        assert_eq!(tick_intervals.counts, [1, 1, 1, 1, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_poh_service_tick_priority() {
+        let mint = Mint::new(1);
+        let bank = Arc::new(Bank::new(&mint));
+        let prev_id = bank.last_id();
+        let (entry_sender, entry_receiver) = channel();
+        let poh_recorder = PohRecorder::new(bank, entry_sender, prev_id, None);
+
+        // Whether or not the platform lets it raise its priority, the tick producer runs
+        let poh_service = PohService::new_with_tick_priority(
+            poh_recorder,
+            Config::Sleep(Duration::from_millis(1)),
+            Some(HIGH_TICK_PRIORITY),
+            String::new(),
+        );
+        for _ in 0..20 {
+            entry_receiver.recv().unwrap();
+        }
+        assert!(poh_service.close().is_ok());
+    }
 }
@@ -2,6 +2,7 @@ use solana_native_loader;
 use solana_sdk::account::{create_keyed_accounts, Account, KeyedAccount};
 use solana_sdk::native_program::ProgramError;
 use solana_sdk::pubkey::Pubkey;
+use solana_sdk::recent_hashes;
 use solana_sdk::system_program;
 use solana_sdk::transaction::Transaction;
 use solana_system_program;
@@ -143,6 +144,10 @@ pub fn execute_transaction(
     tx_accounts: &mut [Account],
     tick_height: u64,
 ) -> Result<(), RuntimeError> {
+    // The recent hashes account is maintained by the bank, transactions may only read it
+    let recent_hashes_index = tx.account_keys.iter().position(recent_hashes::check_id);
+    let recent_hashes_account = recent_hashes_index.map(|index| tx_accounts[index].clone());
+
     for (instruction_index, instruction) in tx.instructions.iter().enumerate() {
         let executable_accounts = &mut (&mut loaders[instruction.program_ids_index as usize]);
         with_subset(tx_accounts, &instruction.accounts, |program_accounts| {
@@ -156,6 +161,14 @@ pub fn execute_transaction(
             .map_err(|err| RuntimeError::ProgramError(instruction_index as u8, err))?;
             Ok(())
         })?;
+        if let (Some(index), Some(account)) = (recent_hashes_index, &recent_hashes_account) {
+            if tx_accounts[index] != *account {
+                return Err(RuntimeError::ProgramError(
+                    instruction_index as u8,
+                    ProgramError::ReadonlyAccountModified,
+                ));
+            }
+        }
     }
     Ok(())
 }
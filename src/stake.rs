@@ -0,0 +1,108 @@
+//! The `stake` module defines the `Stake` newtype, so that stake can't be mixed up with the
+//! tick heights and timestamps it is tallied alongside.
+
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Sub};
+
+/// An amount of stake, in tokens
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Stake(pub u64);
+
+impl Add for Stake {
+    type Output = Stake;
+
+    fn add(self, other: Stake) -> Stake {
+        Stake(self.0 + other.0)
+    }
+}
+
+impl<'a> Add<&'a Stake> for Stake {
+    type Output = Stake;
+
+    fn add(self, other: &'a Stake) -> Stake {
+        self + *other
+    }
+}
+
+impl AddAssign for Stake {
+    fn add_assign(&mut self, other: Stake) {
+        self.0 += other.0;
+    }
+}
+
+impl<'a> AddAssign<&'a Stake> for Stake {
+    fn add_assign(&mut self, other: &'a Stake) {
+        *self += *other;
+    }
+}
+
+impl Sub for Stake {
+    type Output = Stake;
+
+    fn sub(self, other: Stake) -> Stake {
+        Stake(self.0 - other.0)
+    }
+}
+
+/// Scales stake by a plain factor, e.g. `total_stake * 2 / 3`
+impl Mul<u64> for Stake {
+    type Output = Stake;
+
+    fn mul(self, factor: u64) -> Stake {
+        Stake(self.0 * factor)
+    }
+}
+
+impl Div<u64> for Stake {
+    type Output = Stake;
+
+    fn div(self, divisor: u64) -> Stake {
+        Stake(self.0 / divisor)
+    }
+}
+
+impl Sum for Stake {
+    fn sum<I: Iterator<Item = Stake>>(iter: I) -> Stake {
+        iter.fold(Stake::default(), Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Stake> for Stake {
+    fn sum<I: Iterator<Item = &'a Stake>>(iter: I) -> Stake {
+        iter.fold(Stake::default(), Add::add)
+    }
+}
+
+impl fmt::Display for Stake {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stake_arithmetic() {
+        let mut stake = Stake(5) + Stake(4);
+        assert_eq!(stake, Stake(9));
+        stake += Stake(3);
+        assert_eq!(stake, Stake(12));
+        stake += &Stake(1);
+        assert_eq!(stake - Stake(10), Stake(3));
+        assert_eq!(Stake(9) * 2 / 3, Stake(6));
+        assert_eq!(Stake(10) * 2 / 3, Stake(6));
+        assert!(Stake(7) > Stake(6));
+    }
+
+    #[test]
+    fn test_stake_sum() {
+        let stakes = vec![Stake(1), Stake(2), Stake(3)];
+        assert_eq!(stakes.iter().sum::<Stake>(), Stake(6));
+        assert_eq!(stakes.into_iter().sum::<Stake>(), Stake(6));
+        assert_eq!(Vec::<Stake>::new().into_iter().sum::<Stake>(), Stake(0));
+        assert_eq!(format!("{}", Stake(42)), "42");
+    }
+}
@@ -1,5 +1,6 @@
 use crate::checkpoint::Checkpoint;
 use crate::poh_service::NUM_TICKS_PER_SECOND;
+use crate::stake::Stake;
 use hashbrown::HashMap;
 use solana_sdk::hash::Hash;
 use solana_sdk::signature::Signature;
@@ -69,6 +70,10 @@ pub struct StatusDeque<T> {
     entries: StatusEntryMap<T>,
 
     checkpoints: VecDeque<(u64, Option<Hash>, StatusEntryMap<T>)>,
+
+    /// Timestamp of the last registered tick when ticks are timestamped by a counter rather
+    /// than the wall clock, see `set_test_clock`
+    test_clock: Option<u64>,
 }
 
 impl<T> Default for StatusDeque<T> {
@@ -78,6 +83,7 @@ impl<T> Default for StatusDeque<T> {
             last_id: None,
             entries: HashMap::new(),
             checkpoints: VecDeque::new(),
+            test_clock: None,
         }
     }
 }
@@ -155,11 +161,43 @@ impl<T: Clone> StatusDeque<T> {
             _ => false,
         }
     }
+    /// Timestamp every subsequently registered tick with the next value of a counter
+    /// starting after `start`, instead of the wall clock
+    #[cfg(any(test, feature = "test"))]
+    pub fn set_test_clock(&mut self, start: u64) {
+        self.test_clock = Some(start);
+    }
+
+    /// Same as `set_test_clock`, unless the counter is already running
+    #[cfg(any(test, feature = "test"))]
+    pub fn start_test_clock(&mut self, start: u64) {
+        self.test_clock.get_or_insert(start);
+    }
+
+    /// The current time by the clock ticks are timestamped with: the timestamp of the last
+    /// registered tick while the test clock runs, otherwise the wall clock
+    pub fn now(&self) -> u64 {
+        self.test_clock.unwrap_or_else(timestamp)
+    }
+
     /// Tell the bank which Entry IDs exist on the ledger. This function
     /// assumes subsequent calls correspond to later entries, and will boot
     /// the oldest ones once its internal cache is full. Once boot, the
     /// bank will reject transactions using that `last_id`.
     pub fn register_tick(&mut self, last_id: &Hash) {
+        let timestamp = match self.test_clock.as_mut() {
+            Some(clock) => {
+                *clock += 1;
+                *clock
+            }
+            None => timestamp(),
+        };
+        self.register_tick_at(last_id, timestamp);
+    }
+
+    /// Same as `register_tick`, but timestamps the tick with `timestamp`, e.g. the time it
+    /// was produced at, rather than the time it's registered at
+    pub fn register_tick_at(&mut self, last_id: &Hash, timestamp: u64) {
         self.tick_height += 1;
         let tick_height = self.tick_height;
 
@@ -174,7 +212,7 @@ impl<T: Clone> StatusDeque<T> {
             *last_id,
             StatusEntry {
                 tick_height,
-                timestamp: timestamp(),
+                timestamp,
                 statuses: HashMap::new(),
             },
         );
@@ -182,30 +220,72 @@ impl<T: Clone> StatusDeque<T> {
         self.last_id = Some(*last_id);
     }
 
+    /// Returns true if a vote for `tick_height` counts towards finality: the tick is one of
+    /// the last MAX_ENTRY_IDS registered. Votes for ticks that haven't been registered yet
+    /// don't count.
+    pub fn is_recent_tick(&self, tick_height: u64) -> bool {
+        self.tick_height
+            .checked_sub(tick_height)
+            .map_or(false, |age| (age as usize) < MAX_ENTRY_IDS)
+    }
+
     /// Looks through a list of tick heights and stakes, and finds the latest
-    /// tick that has achieved finality
-    pub fn get_finality_timestamp(
+    /// tick that has achieved finality, i.e. where the stake of the recent votes up to
+    /// and including it is strictly more than `supermajority_stake`. With a
+    /// `supermajority_stake` of `total_stake * 2 / 3` that is exactly the votes holding
+    /// more than 2/3 of the total stake, as the integer division rounds down.
+    pub fn get_finality_tick(
         &self,
-        ticks_and_stakes: &mut [(u64, u64)],
-        supermajority_stake: u64,
+        ticks_and_stakes: &mut [(u64, Stake)],
+        supermajority_stake: Stake,
     ) -> Option<u64> {
         // Sort by tick height
         ticks_and_stakes.sort_by(|a, b| a.0.cmp(&b.0));
-        let current_tick_height = self.tick_height;
-        let mut total = 0;
+        let mut total = Stake::default();
         for (tick_height, stake) in ticks_and_stakes.iter() {
-            if ((current_tick_height - tick_height) as usize) < MAX_ENTRY_IDS {
+            if self.is_recent_tick(*tick_height) {
                 total += stake;
                 if total > supermajority_stake {
-                    return self.tick_height_to_timestamp(*tick_height);
+                    return Some(*tick_height);
                 }
             }
         }
         None
     }
 
-    /// Maps a tick height to a timestamp
-    fn tick_height_to_timestamp(&self, tick_height: u64) -> Option<u64> {
+    /// Looks through a list of tick heights and stakes, and finds the timestamp
+    /// of the latest tick that has achieved finality
+    pub fn get_finality_timestamp(
+        &self,
+        ticks_and_stakes: &mut [(u64, Stake)],
+        supermajority_stake: Stake,
+    ) -> Option<u64> {
+        self.get_finality_tick(ticks_and_stakes, supermajority_stake)
+            .and_then(|tick_height| self.tick_height_to_timestamp(tick_height))
+    }
+
+    /// Looks through a list of tick heights and stakes, and finds the deepest tick
+    /// confirmed by a supermajority, i.e. the highest tick that more than
+    /// `supermajority_stake` has voted at or above
+    pub fn get_confirmed_tick(
+        &self,
+        ticks_and_stakes: &mut [(u64, Stake)],
+        supermajority_stake: Stake,
+    ) -> Option<u64> {
+        // Sort by tick height, highest first
+        ticks_and_stakes.sort_by(|a, b| b.0.cmp(&a.0));
+        let mut total = Stake::default();
+        for (tick_height, stake) in ticks_and_stakes.iter() {
+            total += stake;
+            if total > supermajority_stake {
+                return Some(*tick_height);
+            }
+        }
+        None
+    }
+
+    /// Maps a tick height to the timestamp recorded when it was registered
+    pub fn tick_height_to_timestamp(&self, tick_height: u64) -> Option<u64> {
         for entry in self.entries.values() {
             if entry.tick_height == tick_height {
                 return Some(entry.timestamp);
@@ -396,4 +476,103 @@ mod tests {
             Err(StatusDequeError::LastIdNotFound)
         );
     }
+
+    #[test]
+    fn test_get_finality_tick_ignores_future_votes() {
+        let mut status_deque: StatusDeque<()> = StatusDeque::default();
+        for i in 0..3 {
+            let last_id = hash(&serialize(&i).unwrap()); // Unique hash
+            status_deque.register_tick(&last_id);
+        }
+        // A vote beyond the current tick height counts for nothing
+        let mut ticks_and_stakes = [(2, Stake(2)), (std::u64::MAX, Stake(10))];
+        assert_eq!(
+            status_deque.get_finality_tick(&mut ticks_and_stakes, Stake(1)),
+            Some(2)
+        );
+        let mut ticks_and_stakes = [(2, Stake(2)), (std::u64::MAX, Stake(10))];
+        assert_eq!(
+            status_deque.get_finality_tick(&mut ticks_and_stakes, Stake(8)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_set_test_clock() {
+        let mut status_deque: StatusDeque<()> = StatusDeque::default();
+        status_deque.set_test_clock(100);
+        for i in 0..3 {
+            let last_id = hash(&serialize(&i).unwrap()); // Unique hash
+            status_deque.register_tick(&last_id);
+        }
+        let timestamps: Vec<_> = (1..=3)
+            .map(|tick_height| status_deque.tick_height_to_timestamp(tick_height))
+            .collect();
+        assert_eq!(timestamps, vec![Some(101), Some(102), Some(103)]);
+    }
+
+    #[test]
+    fn test_register_tick_at() {
+        let mut status_deque: StatusDeque<()> = StatusDeque::default();
+        status_deque.set_test_clock(100);
+        status_deque.register_tick(&hash(&[0]));
+        status_deque.register_tick_at(&hash(&[1]), 5);
+        status_deque.register_tick(&hash(&[2]));
+        let timestamps: Vec<_> = (1..=3)
+            .map(|tick_height| status_deque.tick_height_to_timestamp(tick_height))
+            .collect();
+        assert_eq!(timestamps, vec![Some(101), Some(5), Some(102)]);
+    }
+
+    #[test]
+    fn test_get_finality_tick_supermajority_boundary() {
+        let mut status_deque: StatusDeque<()> = StatusDeque::default();
+        for i in 0..MAX_ENTRY_IDS + 10 {
+            let last_id = hash(&serialize(&i).unwrap()); // Unique hash
+            status_deque.register_tick(&last_id);
+        }
+        let current_tick_height = status_deque.tick_height;
+
+        // Votes for tick 1 are too old to count towards finality, but their stake is part of
+        // the total
+        for &(total_stake, recent_stake) in &[(9, 6), (10, 6), (12, 8)] {
+            let (total_stake, recent_stake) = (Stake(total_stake), Stake(recent_stake));
+            let supermajority_stake = total_stake * 2 / 3;
+            // Exactly 2/3, or just under it, is not enough
+            let mut ticks_and_stakes = [
+                (1, total_stake - recent_stake),
+                (current_tick_height, recent_stake),
+            ];
+            assert_eq!(
+                status_deque.get_finality_tick(&mut ticks_and_stakes, supermajority_stake),
+                None
+            );
+            // One more than that is
+            let mut ticks_and_stakes = [
+                (1, total_stake - recent_stake - Stake(1)),
+                (current_tick_height, recent_stake + Stake(1)),
+            ];
+            assert_eq!(
+                status_deque.get_finality_tick(&mut ticks_and_stakes, supermajority_stake),
+                Some(current_tick_height)
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_confirmed_tick_supermajority_boundary() {
+        let status_deque: StatusDeque<()> = StatusDeque::default();
+        // A total stake of 9, where the votes at tick 5 hold exactly 2/3 and then one more
+        let supermajority_stake = Stake(9) * 2 / 3;
+        let mut ticks_and_stakes = [(2, Stake(3)), (5, Stake(6))];
+        assert_eq!(
+            status_deque.get_confirmed_tick(&mut ticks_and_stakes, supermajority_stake),
+            Some(2)
+        );
+        let mut ticks_and_stakes = [(2, Stake(2)), (5, Stake(7))];
+        assert_eq!(
+            status_deque.get_confirmed_tick(&mut ticks_and_stakes, supermajority_stake),
+            Some(5)
+        );
+    }
 }
@@ -16,7 +16,6 @@ use solana_sdk::signature::Keypair;
 use solana_sdk::signature::Signature;
 use solana_sdk::storage_program;
 use solana_sdk::storage_program::StorageProgram;
-use solana_sdk::vote_program;
 use std::collections::HashSet;
 use std::mem::size_of;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -138,6 +137,7 @@ impl StorageStage {
         keypair: Arc<Keypair>,
         exit: Arc<AtomicBool>,
         entry_height: u64,
+        vote_program_id: Pubkey,
     ) -> Self {
         debug!("storage_stage::new: entry_height: {}", entry_height);
         storage_state.state.write().unwrap().entry_height = entry_height;
@@ -160,6 +160,7 @@ impl StorageStage {
                             &mut poh_height,
                             &mut entry_height,
                             &mut current_key,
+                            &vote_program_id,
                         ) {
                             match e {
                                 Error::RecvTimeoutError(RecvTimeoutError::Disconnected) => break,
@@ -256,6 +257,7 @@ impl StorageStage {
         poh_height: &mut u64,
         entry_height: &mut u64,
         current_key_idx: &mut usize,
+        vote_program_id: &Pubkey,
     ) -> Result<()> {
         let timeout = Duration::new(1, 0);
         let entries = entry_receiver.recv_timeout(timeout)?;
@@ -265,7 +267,7 @@ impl StorageStage {
             // the storage_keys with their signatures.
             for tx in entry.transactions {
                 for (i, program_id) in tx.program_ids.iter().enumerate() {
-                    if vote_program::check_id(&program_id) {
+                    if program_id == vote_program_id {
                         debug!(
                             "generating storage_keys from votes current_key_idx: {}",
                             *current_key_idx
@@ -349,7 +351,7 @@ mod tests {
     use solana_sdk::hash::Hasher;
     use solana_sdk::signature::{Keypair, KeypairUtil, Signature};
     use solana_sdk::transaction::Transaction;
-    use solana_sdk::vote_program::Vote;
+    use solana_sdk::vote_program::{self, Vote};
     use solana_sdk::vote_transaction::VoteTransaction;
     use std::cmp::{max, min};
     use std::fs::remove_dir_all;
@@ -373,6 +375,7 @@ mod tests {
             keypair,
             exit.clone(),
             0,
+            vote_program::id(),
         );
         exit.store(true, Ordering::Relaxed);
         storage_stage.join().unwrap();
@@ -408,6 +411,7 @@ mod tests {
             keypair,
             exit.clone(),
             0,
+            vote_program::id(),
         );
         storage_entry_sender.send(entries.clone()).unwrap();
 
@@ -473,6 +477,7 @@ mod tests {
             keypair,
             exit.clone(),
             0,
+            vote_program::id(),
         );
         storage_entry_sender.send(entries.clone()).unwrap();
 
@@ -485,6 +490,7 @@ mod tests {
         let mut vote_txs: Vec<Transaction> = Vec::new();
         let vote = Vote {
             tick_height: 123456,
+            bank_hash: Hash::default(),
         };
         let keypair = Keypair::new();
         let vote_tx = VoteTransaction::vote_new(&keypair, vote, Hash::default(), 1);
@@ -127,6 +127,7 @@ impl Tvu {
             keypair,
             exit.clone(),
             entry_height,
+            bank.vote_program_id(),
         );
 
         Tvu {
@@ -37,7 +37,10 @@ pub fn create_new_signed_vote_blob(
     let leader_tpu = get_leader_tpu(&bank, cluster_info)?;
     //TODO: doesn't seem like there is a synchronous call to get height and id
     debug!("voting on {:?}", &last_id.as_ref()[..8]);
-    let vote = Vote { tick_height };
+    let vote = Vote {
+        tick_height,
+        bank_hash: *last_id,
+    };
     let tx = Transaction::vote_new(&vote_account, vote, *last_id, 0);
     {
         let mut blob = shared_blob.write().unwrap();